@@ -0,0 +1,204 @@
+use chan::Receiver;
+use rustc_serialize::json;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::sync::{Arc, Mutex};
+
+use datatype::{AuditConfig, AuditEntry, Error, Event};
+use datatype::audit::parse_timestamp;
+use gateway::Interpret;
+
+
+/// The number of most recent entries returned when a history query sets no limit.
+pub const DEFAULT_HISTORY_LIMIT: u64 = 100;
+
+
+/// An append-only log of JSON encoded `AuditEntry` lines. When the log file
+/// would exceed its maximum size it is rotated to `<path>.1`, with older files
+/// shifted up to `<path>.<max_files - 1>`. Cloned instances share a lock.
+#[derive(Clone)]
+pub struct AuditLog {
+    path:      String,
+    max_size:  u64,
+    max_files: u32,
+    lock:      Arc<Mutex<()>>,
+}
+
+impl AuditLog {
+    /// Create a new audit log from the [audit] config section.
+    pub fn new(cfg: &AuditConfig) -> AuditLog {
+        AuditLog {
+            path:      cfg.path.clone(),
+            max_size:  cfg.max_size_kb * 1024,
+            max_files: cfg.max_files,
+            lock:      Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Record a `Command` along with its originating gateway.
+    pub fn command(&self, interpret: &Interpret) {
+        let entry = AuditEntry::command(&interpret.command, interpret.gateway, &interpret.correlation_id);
+        self.append(&entry).unwrap_or_else(|err| error!("couldn't write to audit log: {}", err));
+    }
+
+    /// Record a broadcast `Event`.
+    pub fn event(&self, event: &Event) {
+        self.append(&AuditEntry::event(event)).unwrap_or_else(|err| error!("couldn't write to audit log: {}", err));
+    }
+
+    /// Record each `Event` received until the channel is closed.
+    pub fn record_events(&self, erx: Receiver<Event>) {
+        for event in erx {
+            self.event(&event);
+        }
+    }
+
+    /// Append a new entry, rotating the log files first if necessary.
+    pub fn append(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let mut line = try!(json::encode(entry));
+        line.push('\n');
+
+        let _lock = self.lock.lock().unwrap();
+        let size  = fs::metadata(&self.path).map(|meta| meta.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            try!(self.rotate());
+        }
+
+        let mut file = try!(OpenOptions::new().create(true).append(true).open(&self.path));
+        Ok(try!(file.write_all(line.as_bytes())))
+    }
+
+    fn rotate(&self) -> Result<(), Error> {
+        debug!("rotating audit log: {}", self.path);
+        if self.max_files <= 1 {
+            return Ok(try!(fs::remove_file(&self.path)))
+        }
+
+        for n in (1..self.max_files).rev() {
+            let from = if n == 1 { self.path.clone() } else { self.rotated(n - 1) };
+            match fs::rename(&from, self.rotated(n)) {
+                Err(ref err) if err.kind() == ErrorKind::NotFound => (),
+                other => try!(other)
+            }
+        }
+        Ok(())
+    }
+
+    fn rotated(&self, n: u32) -> String {
+        format!("{}.{}", self.path, n)
+    }
+
+    /// Return the most recent entries recorded since the RFC 3339 timestamp
+    /// and matching the filter, oldest first.
+    pub fn query(&self, since: Option<&str>, limit: Option<u64>, filter: Option<&str>) -> Result<Vec<AuditEntry>, Error> {
+        let since = match since {
+            Some(since) => Some(try!(parse_timestamp(since))),
+            None        => None
+        };
+
+        let _lock     = self.lock.lock().unwrap();
+        let mut paths = (1..self.max_files).rev().map(|n| self.rotated(n)).collect::<Vec<_>>();
+        paths.push(self.path.clone());
+
+        let mut entries = Vec::new();
+        for path in paths {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(Error::Io(err))
+            };
+
+            for line in BufReader::new(file).lines() {
+                let line = try!(line);
+                match json::decode::<AuditEntry>(&line) {
+                    Ok(entry) => if entry.matches(since, filter) { entries.push(entry) },
+                    Err(err)  => warn!("skipping bad audit log line in {}: {}", path, err)
+                }
+            }
+        }
+
+        let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT) as usize;
+        if entries.len() > limit {
+            let skip = entries.len() - limit;
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+
+    use super::*;
+    use datatype::{AuditConfig, AuditEntry, Command, Event};
+    use package_manager::TestDir;
+
+
+    fn new_log(dir: &TestDir, max_size_kb: u64, max_files: u32) -> AuditLog {
+        AuditLog::new(&AuditConfig {
+            enabled:     true,
+            path:        format!("{}/audit.log", dir.0),
+            max_size_kb: max_size_kb,
+            max_files:   max_files,
+        })
+    }
+
+    #[test]
+    fn test_query() {
+        let dir = TestDir::new("sota-test-audit");
+        let log = new_log(&dir, 1024, 5);
+        log.append(&AuditEntry::command(&Command::StartDownload("1".to_string()), "console", "abc")).unwrap();
+        log.event(&Event::DownloadingUpdate("1".to_string()));
+        log.event(&Event::NoUpdateRequests);
+
+        let entries = log.query(None, None, None).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].gateway, Some("console".to_string()));
+        assert_eq!(entries[0].correlation_id, Some("abc".to_string()));
+        assert_eq!(entries[1].kind, "event");
+
+        let names = log.query(None, None, Some("1")).unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["StartDownload", "DownloadingUpdate"]);
+        let names = log.query(None, Some(1), None).unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["NoUpdateRequests"]);
+        assert!(log.query(Some("9999-01-01T00:00:00Z"), None, None).unwrap().is_empty());
+        assert!(log.query(Some("9999"), None, None).is_err());
+    }
+
+    #[test]
+    fn test_redacted_credentials() {
+        let dir  = TestDir::new("sota-test-audit");
+        let log  = new_log(&dir, 1024, 5);
+        let auth = "auth my_id my_secret".parse::<Command>().unwrap();
+        log.append(&AuditEntry::command(&auth, "socket", "abc")).unwrap();
+
+        let entries = log.query(None, None, None).unwrap();
+        assert_eq!(entries[0].detail, "Authenticate(<redacted>)");
+        let mut text = String::new();
+        File::open(&format!("{}/audit.log", dir.0)).unwrap().read_to_string(&mut text).unwrap();
+        assert!(!text.contains("my_secret"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = TestDir::new("sota-test-audit");
+        let log = new_log(&dir, 1, 3);
+        for n in 0..50 {
+            log.event(&Event::DownloadingUpdate(format!("{}", n)));
+        }
+
+        assert!(fs::metadata(&format!("{}/audit.log.2", dir.0)).is_ok());
+        assert!(fs::metadata(&format!("{}/audit.log.3", dir.0)).is_err());
+        for path in &["audit.log", "audit.log.1", "audit.log.2"] {
+            assert!(fs::metadata(&format!("{}/{}", dir.0, path)).unwrap().len() <= 1024);
+        }
+
+        let entries = log.query(None, Some(1000), None).unwrap();
+        assert!(entries.len() < 50);
+        assert_eq!(entries.last().and_then(|entry| entry.update_id.clone()), Some("49".to_string()));
+    }
+}
@@ -1,17 +1,29 @@
 use chan;
 use chan::{Sender, Receiver};
+use std::sync::{Arc, Mutex};
+
+use metrics::Metrics;
 
 
 /// Retain a list of all peers that should receive the incoming message.
+/// Cloned instances share the list so peers may subscribe after starting.
+#[derive(Clone)]
 pub struct Broadcast<A: Clone> {
-    peers: Vec<Sender<A>>,
-    rx:    Receiver<A>
+    peers:   Arc<Mutex<Vec<Sender<A>>>>,
+    rx:      Receiver<A>,
+    metrics: Option<Metrics>,
 }
 
-impl<A: Clone> Broadcast<A> {
+impl<A: Clone + Send + 'static> Broadcast<A> {
     /// Instantiate a new broadcaster for the given `Receiver`.
     pub fn new(rx: Receiver<A>) -> Broadcast<A> {
-        Broadcast { peers: vec![], rx: rx }
+        Broadcast { peers: Arc::new(Mutex::new(Vec::new())), rx: rx, metrics: None }
+    }
+
+    /// Record the number of messages received but not yet delivered to every
+    /// peer, such as while waiting on a stalled subscriber.
+    pub fn record_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
     }
 
     /// Start receiving broadcasting messages and forwarding each to the list
@@ -19,18 +31,21 @@ impl<A: Clone> Broadcast<A> {
     pub fn start(&self) {
         loop {
             self.rx.recv().map(|a| {
-                for subscriber in &self.peers {
+                self.metrics.as_ref().map(|metrics| metrics.add("sota_broadcast_in_flight", &[], 1.0));
+                let peers = self.peers.lock().unwrap().clone();
+                for subscriber in &peers {
                     subscriber.send(a.clone());
                 }
+                self.metrics.as_ref().map(|metrics| metrics.add("sota_broadcast_in_flight", &[], -1.0));
             });
         }
     }
 
     /// Add a new subscriber to the list of peers that will receive the broadcast
     /// messages.
-    pub fn subscribe(&mut self) -> Receiver<A> {
+    pub fn subscribe(&self) -> Receiver<A> {
         let (tx, rx) = chan::sync::<A>(0);
-        self.peers.push(tx);
+        self.peers.lock().unwrap().push(tx);
         rx
     }
 }
@@ -40,21 +55,51 @@ impl<A: Clone> Broadcast<A> {
 mod tests {
     use chan;
     use std::thread;
+    use std::time::Duration;
 
     use super::*;
+    use metrics::Metrics;
 
 
     #[test]
     fn test_broadcasts_events() {
-        let (tx, rx)      = chan::sync(0);
-        let mut broadcast = Broadcast::new(rx);
+        let (tx, rx)  = chan::sync(0);
+        let broadcast = Broadcast::new(rx);
 
         let a = broadcast.subscribe();
         let b = broadcast.subscribe();
+        let handle = broadcast.clone();
         thread::spawn(move || broadcast.start());
 
         tx.send(123);
         assert_eq!(123, a.recv().unwrap());
         assert_eq!(123, b.recv().unwrap());
+
+        let c = handle.subscribe();
+        tx.send(456);
+        assert_eq!(456, a.recv().unwrap());
+        assert_eq!(456, b.recv().unwrap());
+        assert_eq!(456, c.recv().unwrap());
+    }
+
+    #[test]
+    fn test_broadcast_in_flight() {
+        let (tx, rx)      = chan::sync(0);
+        let mut broadcast = Broadcast::new(rx);
+        let metrics       = Metrics::default();
+        broadcast.record_metrics(metrics.clone());
+
+        let a = broadcast.subscribe();
+        let b = broadcast.subscribe();
+        thread::spawn(move || broadcast.start());
+
+        // the sender still waits for the broadcaster to receive each message
+        tx.send(1);
+        assert_eq!(1, a.recv().unwrap());
+        thread::sleep(Duration::from_millis(100));
+        assert!(metrics.render().contains("sota_broadcast_in_flight 1\n"));
+        assert_eq!(1, b.recv().unwrap());
+        thread::sleep(Duration::from_millis(100));
+        assert!(metrics.render().contains("sota_broadcast_in_flight 0\n"));
     }
 }
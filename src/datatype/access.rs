@@ -0,0 +1,125 @@
+use datatype::{Command, Error};
+
+
+/// The credentials of a connected peer as reported by the operating system.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PeerCredentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+
+/// An optional access policy for a local gateway. Each field left unset will
+/// not be checked.
+#[derive(RustcDecodable, PartialEq, Eq, Debug, Clone, Default)]
+pub struct AccessPolicy {
+    /// A bearer token or shared secret that the client must present.
+    pub token:    Option<String>,
+    /// The peer user ids allowed to connect to a Unix Domain Socket.
+    pub uids:     Option<Vec<u32>>,
+    /// The peer group ids allowed to connect to a Unix Domain Socket.
+    pub gids:     Option<Vec<u32>>,
+    /// The names of the `Command` variants that may be sent via this gateway.
+    pub commands: Option<Vec<String>>,
+}
+
+impl AccessPolicy {
+    /// Verify that the token presented by the client matches the shared secret.
+    pub fn check_token(&self, presented: Option<&str>) -> Result<(), Error> {
+        match (self.token.as_ref(), presented) {
+            (None, _)                  => Ok(()),
+            (Some(_), None)            => Err(Error::AccessDenied("missing token".to_string())),
+            (Some(token), Some(given)) => if constant_time_eq(token, given) {
+                Ok(())
+            } else {
+                Err(Error::AccessDenied("invalid token".to_string()))
+            }
+        }
+    }
+
+    /// Verify that the peer's user and group ids are allowed to connect.
+    pub fn check_peer(&self, peer: &PeerCredentials) -> Result<(), Error> {
+        if let Some(ref uids) = self.uids {
+            if !uids.contains(&peer.uid) {
+                return Err(Error::AccessDenied(format!("uid {} not allowed", peer.uid)));
+            }
+        }
+        if let Some(ref gids) = self.gids {
+            if !gids.contains(&peer.gid) {
+                return Err(Error::AccessDenied(format!("gid {} not allowed", peer.gid)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify that the `Command` is on the allowlist for this gateway.
+    pub fn check_command(&self, cmd: &Command) -> Result<(), Error> {
+        match self.commands {
+            Some(ref allowed) if !allowed.iter().any(|name| name == cmd.name()) => {
+                Err(Error::AccessDenied(format!("command {} not allowed", cmd.name())))
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+// Compare each byte so that the time taken doesn't leak the matching prefix length.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatype::Command;
+
+
+    fn peer(uid: u32, gid: u32) -> PeerCredentials {
+        PeerCredentials { pid: 1, uid: uid, gid: gid }
+    }
+
+    #[test]
+    fn open_policy() {
+        let policy = AccessPolicy::default();
+        assert!(policy.check_token(None).is_ok());
+        assert!(policy.check_peer(&peer(1000, 1000)).is_ok());
+        assert!(policy.check_command(&Command::Shutdown).is_ok());
+    }
+
+    #[test]
+    fn token_policy() {
+        let policy = AccessPolicy { token: Some("secret".to_string()), ..AccessPolicy::default() };
+        assert!(policy.check_token(Some("secret")).is_ok());
+        assert!(policy.check_token(Some("secreT")).is_err());
+        assert!(policy.check_token(Some("")).is_err());
+        assert!(policy.check_token(None).is_err());
+    }
+
+    #[test]
+    fn peer_policy() {
+        let policy = AccessPolicy {
+            uids: Some(vec![0, 1000]),
+            gids: Some(vec![1000]),
+            ..AccessPolicy::default()
+        };
+        assert!(policy.check_peer(&peer(1000, 1000)).is_ok());
+        assert!(policy.check_peer(&peer(0, 0)).is_err());
+        assert!(policy.check_peer(&peer(1001, 1000)).is_err());
+    }
+
+    #[test]
+    fn command_policy() {
+        let policy = AccessPolicy {
+            commands: Some(vec!["GetUpdateRequests".to_string(), "StartDownload".to_string()]),
+            ..AccessPolicy::default()
+        };
+        assert!(policy.check_command(&Command::GetUpdateRequests).is_ok());
+        assert!(policy.check_command(&Command::StartDownload("1".to_string())).is_ok());
+        assert!(policy.check_command(&Command::Shutdown).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use time;
+use time::Timespec;
+
+use datatype::{Command, Error, Event};
+
+
+/// A single record of the audit log for either a `Command` received by the
+/// `GlobalInterpreter` or an `Event` broadcast by the client.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp:      String,
+    pub kind:           String,
+    pub name:           String,
+    pub gateway:        Option<String>,
+    pub correlation_id: Option<String>,
+    pub update_id:      Option<String>,
+    pub detail:         String,
+}
+
+impl AuditEntry {
+    /// Record a `Command` received from a gateway.
+    pub fn command(cmd: &Command, gateway: &str, correlation_id: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp:      format!("{}", time::now_utc().rfc3339()),
+            kind:           "command".to_string(),
+            name:           cmd.name().to_string(),
+            gateway:        Some(gateway.to_string()),
+            correlation_id: Some(correlation_id.to_string()),
+            update_id:      cmd.update_id().map(String::from),
+            detail:         format!("{}", cmd),
+        }
+    }
+
+    /// Record a broadcast `Event`.
+    pub fn event(event: &Event) -> AuditEntry {
+        let detail = match *event {
+            Event::History(ref entries) => format!("History({} entries)", entries.len()),
+            _                           => format!("{}", event)
+        };
+
+        AuditEntry {
+            timestamp:      format!("{}", time::now_utc().rfc3339()),
+            kind:           "event".to_string(),
+            name:           event.name().to_string(),
+            gateway:        None,
+            correlation_id: None,
+            update_id:      event.update_id().map(String::from),
+            detail:         detail,
+        }
+    }
+
+    /// Whether the entry was recorded at or after the time given and whether
+    /// the filter matches its name, gateway or update id.
+    pub fn matches(&self, since: Option<Timespec>, filter: Option<&str>) -> bool {
+        let recent  = since.map_or(true, |since| {
+            parse_timestamp(&self.timestamp).map(|recorded| recorded >= since).unwrap_or(false)
+        });
+        let matched = filter.map_or(true, |filter| {
+            self.name.contains(filter)
+                || self.gateway.as_ref().map_or(false, |gateway| gateway == filter)
+                || self.update_id.as_ref().map_or(false, |id| id == filter)
+        });
+        recent && matched
+    }
+}
+
+
+/// Parse an RFC 3339 date-time, or a full-date as midnight UTC, into the time
+/// since the Unix epoch so that timestamps with any offset may be compared.
+pub fn parse_timestamp(text: &str) -> Result<Timespec, Error> {
+    let invalid = || Error::Parse(format!("invalid RFC 3339 timestamp: {}", text));
+    let field   = |from: usize, to: usize| digits(&text[from..to]).ok_or_else(&invalid);
+    let bytes   = text.as_bytes();
+    if bytes.len() < 10 || bytes.iter().any(|b| *b > 127) || bytes[4] != b'-' || bytes[7] != b'-' {
+        return Err(invalid());
+    }
+
+    let (year, month, day) = (try!(field(0, 4)), try!(field(5, 7)), try!(field(8, 10)));
+    if month < 1 || month > 12 || day < 1 || day > 31 {
+        return Err(invalid());
+    }
+    let midnight = days_from_civil(year, month, day) * 86400;
+    if bytes.len() == 10 {
+        return Ok(Timespec::new(midnight, 0));
+    }
+
+    if bytes.len() < 20 || (bytes[10] != b'T' && bytes[10] != b't' && bytes[10] != b' ')
+        || bytes[13] != b':' || bytes[16] != b':' {
+        return Err(invalid());
+    }
+    let (hour, min, sec) = (try!(field(11, 13)), try!(field(14, 16)), try!(field(17, 19)));
+    if hour > 23 || min > 59 || sec > 60 {
+        return Err(invalid());
+    }
+
+    let mut rest = &text[19..];
+    let mut nsec = 0;
+    if rest.starts_with('.') {
+        let len = rest[1..].bytes().take_while(|b| *b >= b'0' && *b <= b'9').count();
+        if len == 0 {
+            return Err(invalid());
+        }
+        let frac = rest[1..1 + len].chars().chain("000000000".chars()).take(9).collect::<String>();
+        nsec = try!(digits(&frac).ok_or_else(&invalid)) as i32;
+        rest = &rest[1 + len..];
+    }
+
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _    => return Err(invalid())
+            };
+            let hours = try!(digits(&rest[1..3]).ok_or_else(&invalid));
+            let mins  = try!(digits(&rest[4..6]).ok_or_else(&invalid));
+            sign * (hours * 3600 + mins * 60)
+        }
+        _ => return Err(invalid())
+    };
+
+    Ok(Timespec::new(midnight + hour * 3600 + min * 60 + sec - offset, nsec))
+}
+
+fn digits(text: &str) -> Option<i64> {
+    if text.is_empty() || !text.bytes().all(|b| b >= b'0' && b <= b'9') {
+        None
+    } else {
+        text.parse().ok()
+    }
+}
+
+// The number of days from 1970-01-01 to the proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era  = if year >= 0 { year } else { year - 399 } / 400;
+    let yoe  = year - era * 400;
+    let doy  = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe  = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+
+#[cfg(test)]
+mod tests {
+    use time::Timespec;
+
+    use super::*;
+    use datatype::{Command, Event};
+
+
+    fn since(text: &str) -> Option<Timespec> {
+        Some(parse_timestamp(text).expect("couldn't parse timestamp"))
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut entry = AuditEntry::command(&Command::StartDownload("123".to_string()), "socket", "abc");
+        entry.timestamp = "2016-10-01T12:00:00Z".to_string();
+        assert!(entry.matches(None, None));
+        assert!(entry.matches(since("2016-10-01"), Some("Download")));
+        assert!(entry.matches(None, Some("socket")));
+        assert!(entry.matches(None, Some("123")));
+        assert!(!entry.matches(since("2016-10-02"), None));
+        assert!(!entry.matches(None, Some("12")));
+
+        // compared as times rather than strings
+        assert!(entry.matches(since("2016-10-01T13:00:00+02:00"), None));
+        assert!(!entry.matches(since("2016-10-01T12:00:00.5Z"), None));
+        assert!(!entry.matches(since("2016-10-01T08:00:00-05:00"), None));
+        entry.timestamp = "2016-10-01T14:00:00+02:00".to_string();
+        assert!(!entry.matches(since("2016-10-01T12:30:00Z"), None));
+
+        let history = AuditEntry::event(&Event::History(vec![entry]));
+        assert_eq!(history.detail, "History(1 entries)");
+        assert_eq!(history.gateway, None);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z").unwrap(), Timespec::new(0, 0));
+        assert_eq!(parse_timestamp("2016-10-01").unwrap(), Timespec::new(1475280000, 0));
+        assert_eq!(parse_timestamp("2016-10-01T12:00:00.25+01:30").unwrap(), Timespec::new(1475317800, 250000000));
+        assert_eq!(parse_timestamp("2000-02-29t23:59:60z").unwrap(), Timespec::new(951868800, 0));
+        assert!(parse_timestamp("9999").is_err());
+        assert!(parse_timestamp("2016-13-01").is_err());
+        assert!(parse_timestamp("2016-10-01T12:00:00").is_err());
+        assert!(parse_timestamp("2016-10-01T12:00:00+0100").is_err());
+        assert!(parse_timestamp("2016-10-01T12:00:00.Z").is_err());
+    }
+}
@@ -14,9 +14,14 @@ pub enum Command {
     Authenticate(Option<ClientCredentials>),
     /// Shutdown the client immediately.
     Shutdown,
+    /// Re-read the config file and apply any settings that can change live.
+    ReloadConfig,
 
     /// Check for any pending or in-flight updates.
     GetUpdateRequests,
+    /// Query the audit log for the most recent entries since an RFC 3339
+    /// timestamp that match the filter.
+    GetHistory { since: Option<String>, limit: Option<u64>, filter: Option<String> },
 
     /// List the installed packages on the system.
     ListInstalledPackages,
@@ -27,6 +32,8 @@ pub enum Command {
     StartDownload(UpdateRequestId),
     /// Start installing an update.
     StartInstall(UpdateRequestId),
+    /// Abort a pending or in-flight update.
+    AbortUpdate(UpdateRequestId),
 
     /// Send a list of packages to the Core server.
     SendInstalledPackages(Vec<Package>),
@@ -38,9 +45,43 @@ pub enum Command {
     SendUpdateReport(UpdateReport),
 }
 
+impl Command {
+    /// Returns the name of the `Command` variant without any arguments.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Command::Authenticate(_)          => "Authenticate",
+            Command::Shutdown                 => "Shutdown",
+            Command::ReloadConfig             => "ReloadConfig",
+            Command::GetUpdateRequests        => "GetUpdateRequests",
+            Command::GetHistory { .. }        => "GetHistory",
+            Command::ListInstalledPackages    => "ListInstalledPackages",
+            Command::ListSystemInfo           => "ListSystemInfo",
+            Command::StartDownload(_)         => "StartDownload",
+            Command::StartInstall(_)          => "StartInstall",
+            Command::AbortUpdate(_)           => "AbortUpdate",
+            Command::SendInstalledPackages(_) => "SendInstalledPackages",
+            Command::SendInstalledSoftware(_) => "SendInstalledSoftware",
+            Command::SendSystemInfo           => "SendSystemInfo",
+            Command::SendUpdateReport(_)      => "SendUpdateReport",
+        }
+    }
+
+    /// Returns the update request id that the `Command` refers to, if any.
+    pub fn update_id(&self) -> Option<&str> {
+        match *self {
+            Command::StartDownload(ref id)        |
+            Command::StartInstall(ref id)         |
+            Command::AbortUpdate(ref id)          => Some(id.as_str()),
+            Command::SendUpdateReport(ref report) => Some(report.update_id.as_str()),
+            _                                     => None
+        }
+    }
+}
+
 impl Display for Command {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         let text = match *self {
+            Command::Authenticate(Some(_))    => "Authenticate(<redacted>)".to_string(),
             Command::SendInstalledPackages(_) => "SendInstalledPackages(...)".to_string(),
             _                                 => format!("{:?}", self)
         };
@@ -67,12 +108,16 @@ named!(command <(Command, Vec<&str>)>, chain!(
             => { |_| Command::Authenticate(None) }
         | alt_complete!(tag!("GetUpdateRequests") | tag!("getreq"))
             => { |_| Command::GetUpdateRequests }
+        | alt_complete!(tag!("GetHistory") | tag!("history"))
+            => { |_| Command::GetHistory { since: None, limit: None, filter: None } }
         | alt_complete!(tag!("ListInstalledPackages") | tag!("ls"))
             => { |_| Command::ListInstalledPackages }
         | alt_complete!(tag!("ListSystemInfo") | tag!("info"))
             => { |_| Command::ListSystemInfo }
         | alt_complete!(tag!("Shutdown") | tag!("shutdown"))
             => { |_| Command::Shutdown }
+        | alt_complete!(tag!("ReloadConfig") | tag!("reload"))
+            => { |_| Command::ReloadConfig }
         | alt_complete!(tag!("SendInstalledPackages") | tag!("sendpack"))
             => { |_| Command::SendInstalledPackages(Vec::new()) }
         | alt_complete!(tag!("SendInstalledSoftware") | tag!("sendinst"))
@@ -85,6 +130,8 @@ named!(command <(Command, Vec<&str>)>, chain!(
             => { |_| Command::StartDownload("".to_string()) }
         | alt_complete!(tag!("StartInstall") | tag!("inst"))
             => { |_| Command::StartInstall("".to_string()) }
+        | alt_complete!(tag!("AbortUpdate") | tag!("abort"))
+            => { |_| Command::AbortUpdate("".to_string()) }
     )
         ~ args: arguments
         ~ alt!(eof | tag!("\r") | tag!("\n") | tag!(";")),
@@ -122,6 +169,22 @@ fn parse_arguments(cmd: Command, args: Vec<&str>) -> Result<Command, Error> {
             _ => Err(Error::Command(format!("unexpected GetUpdateRequests args: {:?}", args))),
         },
 
+        Command::GetHistory { .. } => {
+            let (mut since, mut limit, mut filter) = (None, None, None);
+            for arg in args {
+                let mut parts = arg.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("since"), Some(val))  => since = Some(val.to_string()),
+                    (Some("filter"), Some(val)) => filter = Some(val.to_string()),
+                    (Some("limit"), Some(val))  => {
+                        limit = Some(try!(val.parse().map_err(|_| Error::Command(format!("invalid limit: {}", val)))));
+                    }
+                    _ => return Err(Error::Command("usage: history [since=<time>] [limit=<n>] [filter=<text>]".to_string()))
+                }
+            }
+            Ok(Command::GetHistory { since: since, limit: limit, filter: filter })
+        }
+
         Command::ListInstalledPackages => match args.len() {
             0 => Ok(Command::ListInstalledPackages),
             _ => Err(Error::Command(format!("unexpected ListInstalledPackages args: {:?}", args))),
@@ -132,6 +195,11 @@ fn parse_arguments(cmd: Command, args: Vec<&str>) -> Result<Command, Error> {
             _ => Err(Error::Command(format!("unexpected ListSystemInfo args: {:?}", args))),
         },
 
+        Command::ReloadConfig => match args.len() {
+            0 => Ok(Command::ReloadConfig),
+            _ => Err(Error::Command(format!("unexpected ReloadConfig args: {:?}", args))),
+        },
+
         Command::SendInstalledPackages(_) => match args.len() {
             0 | 1 => Err(Error::Command("usage: sendpack (<name> <version> )+".to_string())),
             n if n % 2 == 0 => {
@@ -186,6 +254,11 @@ fn parse_arguments(cmd: Command, args: Vec<&str>) -> Result<Command, Error> {
             _ => Err(Error::Command(format!("unexpected StartInstall args: {:?}", args))),
         },
 
+        Command::AbortUpdate(_) => match args.len() {
+            0 => Err(Error::Command("usage: abort <id>".to_string())),
+            1 => Ok(Command::AbortUpdate(args[0].to_string())),
+            _ => Err(Error::Command(format!("unexpected AbortUpdate args: {:?}", args))),
+        },
     }
 }
 
@@ -238,6 +311,20 @@ mod tests {
         assert!("getreq now".parse::<Command>().is_err());
     }
 
+    #[test]
+    fn get_history_test() {
+        assert_eq!("GetHistory".parse::<Command>().unwrap(),
+                   Command::GetHistory { since: None, limit: None, filter: None });
+        assert_eq!("history since=2016-10-01 limit=5 filter=Download".parse::<Command>().unwrap(),
+                   Command::GetHistory {
+                       since:  Some("2016-10-01".to_string()),
+                       limit:  Some(5),
+                       filter: Some("Download".to_string()),
+                   });
+        assert!("history limit=many".parse::<Command>().is_err());
+        assert!("history everything".parse::<Command>().is_err());
+    }
+
     #[test]
     fn list_installed_test() {
         assert_eq!("ListInstalledPackages".parse::<Command>().unwrap(), Command::ListInstalledPackages);
@@ -253,6 +340,13 @@ mod tests {
         assert!("info please".parse::<Command>().is_err());
     }
 
+    #[test]
+    fn reload_config_test() {
+        assert_eq!("ReloadConfig".parse::<Command>().unwrap(), Command::ReloadConfig);
+        assert_eq!("reload".parse::<Command>().unwrap(), Command::ReloadConfig);
+        assert!("reload now".parse::<Command>().is_err());
+    }
+
     #[test]
     fn send_installed_packages_test() {
         assert_eq!("SendInstalledPackages myname myversion".parse::<Command>().unwrap(),
@@ -320,4 +414,12 @@ mod tests {
         assert!("StartInstall".parse::<Command>().is_err());
         assert!("inst more than one".parse::<Command>().is_err());
     }
+
+    #[test]
+    fn abort_update_test() {
+        assert_eq!("AbortUpdate 123".parse::<Command>().unwrap(), Command::AbortUpdate("123".to_string()));
+        assert_eq!("abort this".parse::<Command>().unwrap(), Command::AbortUpdate("this".to_string()));
+        assert!("AbortUpdate".parse::<Command>().is_err());
+        assert!("abort more than one".parse::<Command>().is_err());
+    }
 }
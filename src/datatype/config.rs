@@ -1,25 +1,34 @@
-use rustc_serialize::Decodable;
+use libc;
+use rustc_serialize::{Decodable, Decoder as RustcDecoder};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::fs;
 use std::fs::File;
 use std::io::ErrorKind;
 use std::os::unix::fs::PermissionsExt;
 use std::io::prelude::*;
 use std::path::Path;
+use std::str::FromStr;
 use toml;
-use toml::{Decoder, Parser, Table};
+use toml::{DecodeError, Decoder, Parser, Table, Value};
 
-use datatype::{Error, SocketAddr, Url};
+use datatype::{AccessPolicy, Error, SocketAddr, StorageLimits, Url};
 use package_manager::PackageManager;
 
 
 /// A container for all parsed configs.
 #[derive(Default, PartialEq, Eq, Debug, Clone)]
 pub struct Config {
+    pub access:  AccessConfig,
+    pub audit:   AuditConfig,
     pub auth:    Option<AuthConfig>,
     pub core:    CoreConfig,
     pub dbus:    Option<DBusConfig>,
     pub device:  DeviceConfig,
     pub gateway: GatewayConfig,
+    pub health:  HealthConfig,
+    pub log:     LogConfig,
     pub network: NetworkConfig,
     pub rvi:     Option<RviConfig>,
 }
@@ -28,48 +37,398 @@ impl Config {
     /// Read in a toml configuration file using default values for missing
     /// sections or fields.
     pub fn load(path: &str) -> Result<Config, Error> {
-        info!("Loading config file: {}", path);
-        let mut file = try!(File::open(path).map_err(Error::Io));
-        let mut toml = String::new();
-        try!(file.read_to_string(&mut toml));
-        Config::parse(&toml)
+        Config::load_with(path, &[])
+    }
+
+    /// Read in a toml configuration file then apply each override in order.
+    pub fn load_with(path: &str, overrides: &[ConfigOverride]) -> Result<Config, Error> {
+        Config::load_files(&[path.to_string()], overrides).map(|(config, _)| config)
+    }
+
+    /// Read in each toml configuration file in order, followed by the sorted
+    /// `*.toml` files in its `.d` drop-in directory, with later values
+    /// replacing earlier ones key by key, including within nested tables such
+    /// as `[access.http]`, before applying each override.
+    pub fn load_files(paths: &[String], overrides: &[ConfigOverride]) -> Result<(Config, ConfigSources), Error> {
+        let mut table   = Table::new();
+        let mut sources = ConfigSources::new();
+        for path in try!(config_files(paths)) {
+            info!("Loading config file: {}", path);
+            let layer = try!(parse_table(&try!(read_file(&path))).map_err(|err| match err {
+                Error::Config(msg) => Error::Config(format!("{}: {}", path, msg)),
+                _                  => err
+            }));
+            merge_table(&mut table, layer, &path, &mut sources);
+        }
+        for over in overrides {
+            if over.apply(&mut table) {
+                sources.insert(over.key.name(), ConfigSource { value: over.value.clone(), source: over.source.clone() });
+            }
+        }
+        let config = try!(Config::from_table(&table, overrides, true, &mut sources));
+        Ok((config, sources))
     }
 
     /// Parse a toml configuration string using default values for missing
     /// sections or fields while retaining backwards compatibility.
     pub fn parse(toml: &str) -> Result<Config, Error> {
-        let table = try!(parse_table(&toml));
+        Config::parse_with(toml, &[])
+    }
+
+    /// Parse a toml configuration string then apply each override in order.
+    pub fn parse_with(toml: &str, overrides: &[ConfigOverride]) -> Result<Config, Error> {
+        let mut table = try!(parse_table(&toml));
+        for over in overrides {
+            over.apply(&mut table);
+        }
+        Config::from_table(&table, overrides, true, &mut ConfigSources::new())
+    }
 
-        let mut auth:    Option<ParsedAuthConfig> = try!(maybe_parse_section(&table, "auth"));
-        let mut core:    ParsedCoreConfig         = try!(parse_section(&table, "core"));
-        let mut dbus:    Option<ParsedDBusConfig> = try!(maybe_parse_section(&table, "dbus"));
-        let mut device:  ParsedDeviceConfig       = try!(parse_section(&table, "device"));
-        let mut gateway: ParsedGatewayConfig      = try!(parse_section(&table, "gateway"));
-        let mut network: ParsedNetworkConfig      = try!(parse_section(&table, "network"));
-        let mut rvi:     Option<ParsedRviConfig>  = try!(maybe_parse_section(&table, "rvi"));
+    // Build a config from the parsed toml, optionally reading or writing the
+    // auth credentials file, and record the source of any derived values. The
+    // auth overrides are applied again so they replace the credentials file.
+    fn from_table(table: &Table, overrides: &[ConfigOverride], bootstrap: bool, sources: &mut ConfigSources) -> Result<Config, Error> {
+        let access:      AccessConfig             = try!(parse_section(table, "access"));
+        let mut audit:   ParsedAuditConfig        = try!(parse_section(table, "audit"));
+        let mut auth:    Option<ParsedAuthConfig> = try!(maybe_parse_section(table, "auth"));
+        let mut core:    ParsedCoreConfig         = try!(parse_section(table, "core"));
+        let mut dbus:    Option<ParsedDBusConfig> = try!(maybe_parse_section(table, "dbus"));
+        let mut device:  ParsedDeviceConfig       = try!(parse_section(table, "device"));
+        let mut gateway: ParsedGatewayConfig      = try!(parse_section(table, "gateway"));
+        let mut health:  ParsedHealthConfig       = try!(parse_section(table, "health"));
+        let mut log:     ParsedLogConfig          = try!(parse_section(table, "log"));
+        let mut network: ParsedNetworkConfig      = try!(parse_section(table, "network"));
+        let mut rvi:     Option<ParsedRviConfig>  = try!(maybe_parse_section(table, "rvi"));
 
         if let Some(cfg) = auth {
-            auth = Some(try!(bootstrap_credentials(cfg)));
+            auth = Some(if bootstrap { try!(bootstrap_credentials(cfg, sources)) } else { cfg });
+        }
+        if let Some(ref mut cfg) = auth {
+            for over in overrides.iter().filter(|over| over.key.section == "auth") {
+                match (over.key.key, &over.value) {
+                    ("client_id", &Value::String(ref id))         => cfg.client_id = Some(id.clone()),
+                    ("client_secret", &Value::String(ref secret)) => cfg.client_secret = Some(secret.clone()),
+                    _ => continue
+                }
+                sources.insert(over.key.name(), ConfigSource { value: over.value.clone(), source: over.source.clone() });
+            }
         }
 
         try!(apply_transformations(&mut auth, &mut core, &mut dbus, &mut device,
                                    &mut gateway, &mut network, &mut rvi));
 
+        if let Some(legacy) = sources.remove("device.polling_interval") {
+            let source = format!("{} as device.polling_interval", legacy.source);
+            if let Some(polling) = core.polling {
+                sources.entry("core.polling".to_string()).or_insert(ConfigSource { value: Value::Boolean(polling), source: source.clone() });
+            }
+            if let Some(polling_sec) = core.polling_sec {
+                sources.entry("core.polling_sec".to_string()).or_insert(ConfigSource { value: Value::Integer(polling_sec as i64), source: source });
+            }
+        }
+
         Ok(Config {
+            access:  access,
+            audit:   audit.defaultify(),
             auth:    auth.map(|mut cfg| cfg.defaultify()),
             core:    core.defaultify(),
             dbus:    dbus.map(|mut cfg| cfg.defaultify()),
             device:  device.defaultify(),
             gateway: gateway.defaultify(),
+            health:  health.defaultify(),
+            log:     log.defaultify(),
             network: network.defaultify(),
             rvi:     rvi.map(|mut cfg| cfg.defaultify())
         })
     }
+
+    /// Check each toml configuration file and its `.d` drop-ins for problems
+    /// without starting anything, returning each problem with the file that
+    /// set the key. Settings that would fail on start-up are checked against
+    /// the merged files.
+    pub fn check_files(paths: &[String]) -> Result<Vec<(String, ConfigProblem)>, Error> {
+        let mut table   = Table::new();
+        let mut sources = ConfigSources::new();
+        let mut texts   = BTreeMap::new();
+        let mut failed  = Vec::new();
+        let mut found   = Vec::new();
+        for path in try!(config_files(paths)) {
+            let text = try!(read_file(&path));
+            match check_layer(&text) {
+                Ok((layer, problems, mut layer_failed)) => {
+                    found.extend(problems.into_iter().map(|problem| (path.clone(), problem)));
+                    failed.append(&mut layer_failed);
+                    merge_table(&mut table, layer, &path, &mut sources);
+                }
+                Err(problems) => found.extend(problems.into_iter().map(|problem| (path.clone(), problem))),
+            }
+            texts.insert(path, text);
+        }
+
+        let first  = paths.first().cloned().unwrap_or(String::new());
+        let result = check_environment(&table, &failed, |section, key, message| {
+            let path = sources.get(&format!("{}.{}", section, key)).map_or(first.clone(), |source| source.source.clone());
+            let text = texts.get(&path).map_or("", |text| text.as_str());
+            found.push((path, ConfigProblem::new(text, section, Some(key.to_string()), message)));
+        });
+        if let Err(err) = result {
+            found.push((first, ConfigProblem { section: "".to_string(), key: None, line: None, message: format!("{}", err) }));
+        }
+        Ok(found)
+    }
+
+    /// Check a toml configuration string for syntax errors, unknown keys, bad
+    /// values, and settings that would fail on start-up, such as a missing CA
+    /// file or an unwritable packages directory.
+    pub fn check(toml: &str) -> Vec<ConfigProblem> {
+        let (table, mut problems, failed) = match check_layer(toml) {
+            Ok(checked)   => checked,
+            Err(problems) => return problems
+        };
+        let result = check_environment(&table, &failed, |section, key, message| {
+            problems.push(ConfigProblem::new(toml, section, Some(key.to_string()), message));
+        });
+        if let Err(err) = result {
+            problems.push(ConfigProblem { section: "".to_string(), key: None, line: None, message: format!("{}", err) });
+        }
+        problems
+    }
+
+    /// Check for settings that parse correctly but can't be run together.
+    pub fn validate(&self) -> Result<(), Error> {
+        if (self.gateway.dbus || self.gateway.rvi) && self.dbus.is_none() {
+            return Err(Error::Config("[dbus] config required for the dbus and rvi gateways".to_string()));
+        }
+        if self.gateway.rvi && self.rvi.is_none() {
+            return Err(Error::Config("[rvi] config required for the rvi gateway".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Compare a newly loaded config with this running config, returning the
+    /// config to run with and the keys that changed. Changes to settings only
+    /// read on start-up are reverted and reported as requiring a restart. A
+    /// newly enabled gateway is started, but a running gateway is only
+    /// stopped by a restart. The device settings read by the rvi services are
+    /// only applied live when the rvi gateway is off.
+    pub fn reload(&self, mut loaded: Config) -> (Config, ConfigChanges) {
+        let mut changes = ConfigChanges::default();
+        {
+            let applied = &mut changes.applied;
+            compare("auth",                   &self.auth,                   &loaded.auth,                   applied);
+            compare("core.server",            &self.core.server,            &loaded.core.server,            applied);
+            compare("core.polling",           &self.core.polling,           &loaded.core.polling,           applied);
+            compare("core.polling_sec",       &self.core.polling_sec,       &loaded.core.polling_sec,       applied);
+            compare("device.vin",             &self.device.vin,             &loaded.device.vin,             applied);
+            compare("device.packages_dir",    &self.device.packages_dir,    &loaded.device.packages_dir,    applied);
+            compare("device.package_manager", &self.device.package_manager, &loaded.device.package_manager, applied);
+            compare("device.system_info",     &self.device.system_info,     &loaded.device.system_info,     applied);
+            enabled("gateway.console",         self.gateway.console,          loaded.gateway.console,          applied);
+            enabled("gateway.dbus",            self.gateway.dbus,             loaded.gateway.dbus,             applied);
+            enabled("gateway.http",            self.gateway.http,             loaded.gateway.http,             applied);
+            enabled("gateway.socket",          self.gateway.socket,           loaded.gateway.socket,           applied);
+            enabled("gateway.websocket",       self.gateway.websocket,        loaded.gateway.websocket,        applied);
+            compare("log.level",              &self.log.level,              &loaded.log.level,              applied);
+            compare("log.format",             &self.log.format,             &loaded.log.format,             applied);
+            compare("log.sink",               &self.log.sink,               &loaded.log.sink,               applied);
+            compare("log.socket",             &self.log.socket,             &loaded.log.socket,             applied);
+            if !self.gateway.rvi {
+                compare("device.uuid",            &self.device.uuid,            &loaded.device.uuid,            applied);
+                compare("device.disk_reserve_mb", &self.device.disk_reserve_mb, &loaded.device.disk_reserve_mb, applied);
+                compare("device.disk_quota_mb",   &self.device.disk_quota_mb,   &loaded.device.disk_quota_mb,   applied);
+            }
+        }
+        {
+            let restart = &mut changes.restart_required;
+            revert("access",                   &self.access,                   &mut loaded.access,                   restart);
+            revert("audit",                    &self.audit,                    &mut loaded.audit,                    restart);
+            revert("dbus",                     &self.dbus,                     &mut loaded.dbus,                     restart);
+            revert("device.certificates_path", &self.device.certificates_path, &mut loaded.device.certificates_path, restart);
+            disabled("gateway.console",        self.gateway.console,           &mut loaded.gateway.console,          restart);
+            disabled("gateway.dbus",           self.gateway.dbus,              &mut loaded.gateway.dbus,             restart);
+            disabled("gateway.http",           self.gateway.http,              &mut loaded.gateway.http,             restart);
+            revert("gateway.metrics",          &self.gateway.metrics,          &mut loaded.gateway.metrics,          restart);
+            revert("gateway.rvi",              &self.gateway.rvi,              &mut loaded.gateway.rvi,              restart);
+            disabled("gateway.socket",         self.gateway.socket,            &mut loaded.gateway.socket,           restart);
+            disabled("gateway.websocket",      self.gateway.websocket,         &mut loaded.gateway.websocket,        restart);
+            revert("health",                   &self.health,                   &mut loaded.health,                   restart);
+            revert("network",                  &self.network,                  &mut loaded.network,                  restart);
+            revert("rvi",                      &self.rvi,                      &mut loaded.rvi,                      restart);
+            if self.gateway.rvi {
+                // the rvi services keep the device settings read on start-up
+                revert("device.uuid",            &self.device.uuid,            &mut loaded.device.uuid,            restart);
+                revert("device.disk_reserve_mb", &self.device.disk_reserve_mb, &mut loaded.device.disk_reserve_mb, restart);
+                revert("device.disk_quota_mb",   &self.device.disk_quota_mb,   &mut loaded.device.disk_quota_mb,   restart);
+            }
+        }
+        (loaded, changes)
+    }
+}
+
+// Record the key of a setting that can be changed live.
+fn compare<T: PartialEq>(key: &str, running: &T, loaded: &T, applied: &mut Vec<String>) {
+    if running != loaded {
+        applied.push(key.to_string());
+    }
+}
+
+// Record the key of a gateway that was enabled, as it is started live.
+fn enabled(key: &str, running: bool, loaded: bool, applied: &mut Vec<String>) {
+    if !running && loaded {
+        applied.push(key.to_string());
+    }
+}
+
+// Record the key of a gateway that was disabled, as it keeps running until a
+// restart, then revert it.
+fn disabled(key: &str, running: bool, loaded: &mut bool, restart: &mut Vec<String>) {
+    if running && !*loaded {
+        restart.push(key.to_string());
+        *loaded = true;
+    }
+}
+
+// Record the key of a setting that requires a restart, then revert it.
+fn revert<T: PartialEq + Clone>(key: &str, running: &T, loaded: &mut T, restart: &mut Vec<String>) {
+    if running != loaded {
+        restart.push(key.to_string());
+        *loaded = running.clone();
+    }
+}
+
+/// The config keys changed by a reload, and any gateways that were enabled
+/// but couldn't be started along with the reason.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone, Default)]
+pub struct ConfigChanges {
+    pub applied:          Vec<String>,
+    pub restart_required: Vec<String>,
+    pub failed:           Vec<String>,
+}
+
+impl Display for ConfigChanges {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "applied [{}], restart required for [{}], failed [{}]",
+               self.applied.join(", "), self.restart_required.join(", "), self.failed.join(", "))
+    }
+}
+
+fn read_file(path: &str) -> Result<String, Error> {
+    let mut file = try!(File::open(path).map_err(Error::Io));
+    let mut toml = String::new();
+    try!(file.read_to_string(&mut toml));
+    Ok(toml)
+}
+
+// List each config file followed by the sorted `*.toml` files in its `.d`
+// directory, if one exists.
+fn config_files(paths: &[String]) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    for path in paths {
+        files.push(path.clone());
+        let entries = match fs::read_dir(format!("{}.d", path)) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(Error::Io(err))
+        };
+
+        let mut drop_ins = Vec::new();
+        for entry in entries {
+            let path = try!(entry).path();
+            if path.is_file() && path.extension().map_or(false, |ext| ext == "toml") {
+                drop_ins.push(format!("{}", path.display()));
+            }
+        }
+        drop_ins.sort();
+        files.append(&mut drop_ins);
+    }
+    Ok(files)
+}
+
+// Copy each section key from the layer into the table, recording the source.
+fn merge_table(table: &mut Table, layer: Table, source: &str, sources: &mut ConfigSources) {
+    for (name, value) in layer {
+        match value {
+            Value::Table(keys) => {
+                let section = table.entry(name.clone()).or_insert_with(|| Value::Table(Table::new()));
+                if let Value::Table(ref mut section) = *section {
+                    merge_keys(section, keys, &name, source, sources);
+                }
+            }
+            _ => {
+                sources.insert(name.clone(), ConfigSource { value: value.clone(), source: source.to_string() });
+                table.insert(name, value);
+            }
+        }
+    }
+}
+
+// Copy each key into the section, merging nested tables such as
+// `[access.http]` key by key rather than replacing them.
+fn merge_keys(section: &mut Table, keys: Table, path: &str, source: &str, sources: &mut ConfigSources) {
+    for (key, value) in keys {
+        let key      = key.replace("-", "_");
+        let name     = format!("{}.{}", path, key);
+        let dashed   = section.remove(&key.replace("_", "-"));
+        let existing = section.remove(&key).or(dashed);
+        match value {
+            Value::Table(keys) => {
+                let mut nested = match existing {
+                    Some(Value::Table(nested)) => nested,
+                    _                          => Table::new()
+                };
+                merge_keys(&mut nested, keys, &name, source, sources);
+                section.insert(key, Value::Table(nested));
+            }
+            _ => {
+                sources.insert(name, ConfigSource { value: value.clone(), source: source.to_string() });
+                section.insert(key, value);
+            }
+        }
+    }
+}
+
+// Set a section key, replacing either the underscored or dashed form.
+fn set_value(table: &mut Table, section: &str, key: &str, value: Value) {
+    let section = table.entry(section.to_string()).or_insert_with(|| Value::Table(Table::new()));
+    if let Value::Table(ref mut section) = *section {
+        section.remove(&key.replace("_", "-"));
+        section.insert(key.to_string(), value);
+    }
+}
+
+// Remove the legacy `device.polling_interval` so that it doesn't conflict with
+// an overridden `core.polling_sec`, keeping the polling flag it implied.
+fn remove_polling_interval(table: &mut Table) {
+    let interval = match table.get_mut("device") {
+        Some(&mut Value::Table(ref mut device)) => {
+            let dashed = device.remove("polling-interval");
+            device.remove("polling_interval").or(dashed)
+        }
+        _ => None
+    };
+    let polling = match table.get("core") {
+        Some(&Value::Table(ref core)) => core.contains_key("polling"),
+        _                             => false
+    };
+    if let Some(Value::Integer(interval)) = interval {
+        if !polling {
+            set_value(table, "core", "polling", Value::Boolean(interval > 0));
+        }
+    }
 }
 
 fn parse_table(toml: &str) -> Result<Table, Error> {
     let mut parser = Parser::new(toml);
-    Ok(try!(parser.parse().ok_or_else(move || parser.errors)))
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => {
+            let errors = parser.errors.iter().map(|err| {
+                let (line, col) = parser.to_linecol(err.lo);
+                format!("line {} column {}: {}", line + 1, col + 1, err.desc)
+            }).collect::<Vec<_>>();
+            Err(Error::Config(errors.join("; ")))
+        }
+    }
 }
 
 fn parse_section<T: Decodable + Default>(table: &Table, section: &str) -> Result<T, Error> {
@@ -79,11 +438,418 @@ fn parse_section<T: Decodable + Default>(table: &Table, section: &str) -> Result
 fn maybe_parse_section<T: Decodable>(table: &Table, section: &str) -> Result<Option<T>, Error> {
     table.get(section).map_or(Ok(None), |sect| {
         let mut decoder = Decoder::new(sect.clone());
-        Ok(Some(try!(T::decode(&mut decoder))))
+        T::decode(&mut decoder).map(Some).map_err(|err| {
+            let DecodeError { field, kind } = err;
+            let message = format!("{}", DecodeError { field: None, kind: kind });
+            Error::Config(format!("{}", ConfigProblem { section: section.to_string(), key: field, line: None, message: message }))
+        })
     })
 }
 
 
+/// The type of value expected by a config key.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueKind {
+    Bool,
+    Integer,
+    String,
+}
+
+/// A config key that may be set with a `SOTA_<SECTION>_<KEY>` environment
+/// variable or a `--section-key` command line flag.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ConfigKey {
+    pub section: &'static str,
+    pub key:     &'static str,
+    pub kind:    ValueKind,
+    pub hint:    &'static str,
+    pub help:    &'static str,
+}
+
+impl ConfigKey {
+    /// The full key name, such as `core.polling_sec`.
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.section, self.key)
+    }
+
+    /// The command line flag name, such as `core-polling-sec`.
+    pub fn flag(&self) -> String {
+        format!("{}-{}", self.section, self.key.replace("_", "-"))
+    }
+
+    /// The environment variable name, such as `SOTA_CORE_POLLING_SEC`.
+    pub fn env_var(&self) -> String {
+        format!("SOTA_{}_{}", self.section, self.key).to_uppercase()
+    }
+
+    /// Check the text matches the expected type before creating an override.
+    pub fn parse(&'static self, source: &str, text: &str) -> Result<ConfigOverride, Error> {
+        let value = match self.kind {
+            ValueKind::Bool    => try!(text.parse().map(Value::Boolean).map_err(|_| {
+                Error::Config(format!("{}: expected true or false, found {:?}", source, text))
+            })),
+            ValueKind::Integer => try!(text.parse().map(Value::Integer).map_err(|_| {
+                Error::Config(format!("{}: expected an integer, found {:?}", source, text))
+            })),
+            ValueKind::String  => Value::String(text.to_string()),
+        };
+        Ok(ConfigOverride { key: self, value: value, source: source.to_string() })
+    }
+}
+
+/// Every config key that may be overridden.
+pub const CONFIG_KEYS: &'static [ConfigKey] = &[
+    ConfigKey { section: "audit",   key: "enabled",                 kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the audit log" },
+    ConfigKey { section: "audit",   key: "path",                    kind: ValueKind::String,  hint: "PATH",      help: "change the audit log path" },
+    ConfigKey { section: "audit",   key: "max_size_kb",             kind: ValueKind::Integer, hint: "KB",        help: "change the size of the audit log before rotation" },
+    ConfigKey { section: "audit",   key: "max_files",               kind: ValueKind::Integer, hint: "FILES",     help: "change the number of audit log files kept" },
+    ConfigKey { section: "auth",    key: "server",                  kind: ValueKind::String,  hint: "URL",       help: "change the auth server" },
+    ConfigKey { section: "auth",    key: "client_id",               kind: ValueKind::String,  hint: "ID",        help: "change the auth client id" },
+    ConfigKey { section: "auth",    key: "client_secret",           kind: ValueKind::String,  hint: "SECRET",    help: "change the auth client secret" },
+    ConfigKey { section: "auth",    key: "credentials_file",        kind: ValueKind::String,  hint: "PATH",      help: "change the auth credentials file" },
+    ConfigKey { section: "core",    key: "server",                  kind: ValueKind::String,  hint: "URL",       help: "change the core server" },
+    ConfigKey { section: "core",    key: "polling",                 kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle polling the core server for updates" },
+    ConfigKey { section: "core",    key: "polling_sec",             kind: ValueKind::Integer, hint: "SECONDS",   help: "change the core polling interval" },
+    ConfigKey { section: "dbus",    key: "bus",                     kind: ValueKind::String,  hint: "BUS",       help: "change the dbus bus (session, system or an address)" },
+    ConfigKey { section: "dbus",    key: "name",                    kind: ValueKind::String,  hint: "NAME",      help: "change the dbus registration name" },
+    ConfigKey { section: "dbus",    key: "path",                    kind: ValueKind::String,  hint: "PATH",      help: "change the dbus path" },
+    ConfigKey { section: "dbus",    key: "interface",               kind: ValueKind::String,  hint: "INTERFACE", help: "change the dbus interface name" },
+    ConfigKey { section: "dbus",    key: "software_manager",        kind: ValueKind::String,  hint: "NAME",      help: "change the dbus software manager name" },
+    ConfigKey { section: "dbus",    key: "software_manager_path",   kind: ValueKind::String,  hint: "PATH",      help: "change the dbus software manager path" },
+    ConfigKey { section: "dbus",    key: "timeout",                 kind: ValueKind::Integer, hint: "TIMEOUT",   help: "change the dbus call timeout in milliseconds" },
+    ConfigKey { section: "dbus",    key: "retries",                 kind: ValueKind::Integer, hint: "RETRIES",   help: "change the number of retries for dbus calls" },
+    ConfigKey { section: "device",  key: "uuid",                    kind: ValueKind::String,  hint: "UUID",      help: "change the device uuid" },
+    ConfigKey { section: "device",  key: "vin",                     kind: ValueKind::String,  hint: "VIN",       help: "change the device vin" },
+    ConfigKey { section: "device",  key: "packages_dir",            kind: ValueKind::String,  hint: "PATH",      help: "change downloaded directory for packages" },
+    ConfigKey { section: "device",  key: "package_manager",         kind: ValueKind::String,  hint: "MANAGER",   help: "change the package manager" },
+    ConfigKey { section: "device",  key: "certificates_path",       kind: ValueKind::String,  hint: "PATH",      help: "change the OpenSSL CA certificates file" },
+    ConfigKey { section: "device",  key: "system_info",             kind: ValueKind::String,  hint: "PATH",      help: "change the system information command" },
+    ConfigKey { section: "device",  key: "disk_reserve_mb",         kind: ValueKind::Integer, hint: "MB",        help: "change the disk space to keep free" },
+    ConfigKey { section: "device",  key: "disk_quota_mb",           kind: ValueKind::Integer, hint: "MB",        help: "change the disk quota for each storage directory" },
+    ConfigKey { section: "gateway", key: "console",                 kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the console gateway" },
+    ConfigKey { section: "gateway", key: "dbus",                    kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the dbus gateway" },
+    ConfigKey { section: "gateway", key: "http",                    kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the http gateway" },
+    ConfigKey { section: "gateway", key: "metrics",                 kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the prometheus metrics server" },
+    ConfigKey { section: "gateway", key: "rvi",                     kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the rvi gateway" },
+    ConfigKey { section: "gateway", key: "socket",                  kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the unix domain socket gateway" },
+    ConfigKey { section: "gateway", key: "websocket",               kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle the websocket gateway" },
+    ConfigKey { section: "health",  key: "busy_timeout_sec",        kind: ValueKind::Integer, hint: "SECONDS",   help: "change how long a busy thread may take before it is unhealthy" },
+    ConfigKey { section: "log",     key: "level",                   kind: ValueKind::String,  hint: "LEVEL",     help: "change the log level filter unless RUST_LOG is set" },
+    ConfigKey { section: "log",     key: "format",                  kind: ValueKind::String,  hint: "FORMAT",    help: "change the log format (text or json)" },
+    ConfigKey { section: "log",     key: "sink",                    kind: ValueKind::String,  hint: "SINK",      help: "change the log sink (stderr, journald or syslog)" },
+    ConfigKey { section: "log",     key: "socket",                  kind: ValueKind::String,  hint: "PATH",      help: "change the socket path of the journald or syslog sink" },
+    ConfigKey { section: "network", key: "http_server",             kind: ValueKind::String,  hint: "ADDR",      help: "change the http server gateway address" },
+    ConfigKey { section: "network", key: "metrics_server",          kind: ValueKind::String,  hint: "ADDR",      help: "change the prometheus metrics server address" },
+    ConfigKey { section: "network", key: "rvi_edge_server",         kind: ValueKind::String,  hint: "ADDR",      help: "change the rvi edge server gateway address" },
+    ConfigKey { section: "network", key: "socket_commands_path",    kind: ValueKind::String,  hint: "PATH",      help: "change the socket path for reading commands" },
+    ConfigKey { section: "network", key: "socket_events_path",      kind: ValueKind::String,  hint: "PATH",      help: "change the socket path for sending events" },
+    ConfigKey { section: "network", key: "socket_events_version",   kind: ValueKind::String,  hint: "VERSION",   help: "change the protocol version of socket events" },
+    ConfigKey { section: "network", key: "socket_persistent",       kind: ValueKind::Bool,    hint: "BOOL",      help: "toggle persistent connections on the commands socket" },
+    ConfigKey { section: "network", key: "websocket_server",        kind: ValueKind::String,  hint: "ADDR",      help: "change the websocket gateway address" },
+    ConfigKey { section: "network", key: "websocket_keepalive_sec", kind: ValueKind::Integer, hint: "SECONDS",   help: "change the websocket keepalive interval" },
+    ConfigKey { section: "rvi",     key: "client",                  kind: ValueKind::String,  hint: "URL",       help: "change the rvi client URL" },
+    ConfigKey { section: "rvi",     key: "storage_dir",             kind: ValueKind::String,  hint: "PATH",      help: "change the rvi storage directory" },
+    ConfigKey { section: "rvi",     key: "timeout",                 kind: ValueKind::Integer, hint: "TIMEOUT",   help: "change the rvi timeout" },
+];
+
+/// A type-checked value for a config key from the environment or the command
+/// line that replaces the value from the config file. An override for the
+/// optional `[auth]`, `[dbus]` or `[rvi]` sections is ignored unless the
+/// section is already configured.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConfigOverride {
+    pub key:    &'static ConfigKey,
+    pub value:  Value,
+    pub source: String,
+}
+
+impl ConfigOverride {
+    /// Read each `SOTA_<SECTION>_<KEY>` variable, such as from `env::vars()`.
+    pub fn from_env<I: Iterator<Item=(String, String)>>(vars: I) -> Result<Vec<ConfigOverride>, Error> {
+        let mut overrides = Vec::new();
+        for (name, text) in vars {
+            match CONFIG_KEYS.iter().find(|key| key.env_var() == name) {
+                Some(key) => overrides.push(try!(key.parse(&name, &text))),
+                None if name.starts_with("SOTA_") && name != "SOTA_CONFIG" => {
+                    warn!("ignoring unknown config variable: {}", name);
+                }
+                None => ()
+            }
+        }
+        Ok(overrides)
+    }
+
+    // Replace the value in the table, returning false when ignored.
+    fn apply(&self, table: &mut Table) -> bool {
+        if OPTIONAL_SECTIONS.contains(&self.key.section) && !table.contains_key(self.key.section) {
+            warn!("ignoring {} from {}: no [{}] section configured", self.key.name(), self.source, self.key.section);
+            return false
+        }
+
+        debug!("overriding {} from {}", self.key.name(), self.source);
+        if self.key.name() == "core.polling_sec" {
+            remove_polling_interval(table);
+        }
+        set_value(table, self.key.section, self.key.key, self.value.clone());
+        true
+    }
+}
+
+
+/// The effective value of each config key set by a file, environment variable
+/// or flag, keyed by `section.key`. Any other key uses its default value.
+pub type ConfigSources = BTreeMap<String, ConfigSource>;
+
+/// A config value and the file, environment variable or flag that set it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ConfigSource {
+    pub value:  Value,
+    pub source: String,
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} from {}", self.value, self.source)
+    }
+}
+
+
+/// The names of all known config sections.
+const SECTIONS: &'static [&'static str] = &["access", "audit", "auth", "core", "dbus", "device",
+                                             "gateway", "health", "log", "network", "rvi"];
+
+/// The sections that are only enabled when present in a config file.
+const OPTIONAL_SECTIONS: &'static [&'static str] = &["auth", "dbus", "rvi"];
+
+/// A problem found by `Config::check`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ConfigProblem {
+    pub section: String,
+    pub key:     Option<String>,
+    pub line:    Option<usize>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(toml: &str, section: &str, key: Option<String>, message: String) -> ConfigProblem {
+        ConfigProblem {
+            section: section.to_string(),
+            line:    find_line(toml, section, key.as_ref().map(|key| key.as_str())),
+            key:     key,
+            message: message,
+        }
+    }
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if let Some(line) = self.line {
+            try!(write!(f, "line {}: ", line));
+        }
+        if !self.section.is_empty() {
+            try!(write!(f, "[{}] ", self.section));
+        }
+        if let Some(ref key) = self.key {
+            try!(write!(f, "{}: ", key));
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+// Parse a toml configuration string then check it for unknown sections and
+// keys or bad values, returning the table, its problems and the sections that
+// failed to decode. Syntax errors are returned as the only problems.
+fn check_layer(toml: &str) -> Result<(Table, Vec<ConfigProblem>, Vec<&'static str>), Vec<ConfigProblem>> {
+    let mut parser = Parser::new(toml);
+    let table = match parser.parse() {
+        Some(table) => table,
+        None => return Err(parser.errors.iter().map(|err| {
+            let (line, col) = parser.to_linecol(err.lo);
+            ConfigProblem {
+                section: "".to_string(),
+                key:     None,
+                line:    Some(line + 1),
+                message: format!("column {}: {}", col + 1, err.desc),
+            }
+        }).collect())
+    };
+
+    let mut problems = Vec::new();
+    for section in table.keys().filter(|section| !SECTIONS.contains(&section.as_str())) {
+        problems.push(ConfigProblem::new(toml, section, None, "unknown section".to_string()));
+    }
+    let failed = vec![
+        ("access",  check_section::<AccessConfig>(toml, &table, "access", &mut problems)),
+        ("audit",   check_section::<ParsedAuditConfig>(toml, &table, "audit", &mut problems)),
+        ("auth",    check_section::<ParsedAuthConfig>(toml, &table, "auth", &mut problems)),
+        ("core",    check_section::<ParsedCoreConfig>(toml, &table, "core", &mut problems)),
+        ("dbus",    check_section::<ParsedDBusConfig>(toml, &table, "dbus", &mut problems)),
+        ("device",  check_section::<ParsedDeviceConfig>(toml, &table, "device", &mut problems)),
+        ("gateway", check_section::<ParsedGatewayConfig>(toml, &table, "gateway", &mut problems)),
+        ("health",  check_section::<ParsedHealthConfig>(toml, &table, "health", &mut problems)),
+        ("log",     check_section::<ParsedLogConfig>(toml, &table, "log", &mut problems)),
+        ("network", check_section::<ParsedNetworkConfig>(toml, &table, "network", &mut problems)),
+        ("rvi",     check_section::<ParsedRviConfig>(toml, &table, "rvi", &mut problems)),
+    ].into_iter().filter(|&(_, decoded)| !decoded).map(|(section, _)| section).collect();
+    Ok((table, problems, failed))
+}
+
+// Check the settings that would fail on start-up using the sections that
+// decoded, passing each problem's section, key and message to `problem`.
+fn check_environment<F: FnMut(&str, &str, String)>(table: &Table, failed: &[&str], mut problem: F) -> Result<(), Error> {
+    let mut valid = table.clone();
+    for section in failed {
+        valid.remove(*section);
+    }
+    let config  = try!(Config::from_table(&valid, &[], false, &mut ConfigSources::new()));
+    let decoded = |section: &str| !failed.iter().any(|failed| *failed == section);
+
+    if decoded("core") {
+        check_url(&config.core.server).map(|err| problem("core", "server", err));
+    }
+    config.auth.as_ref().and_then(|auth| check_url(&auth.server)).map(|err| problem("auth", "server", err));
+    config.rvi.as_ref().and_then(|rvi| check_url(&rvi.client)).map(|err| problem("rvi", "client", err));
+
+    if decoded("device") {
+        if let Err(err) = File::open(&config.device.certificates_path) {
+            problem("device", "certificates_path", format!("couldn't read CA file {}: {}", config.device.certificates_path, err));
+        }
+        check_writable_dir(&config.device.packages_dir).map(|err| problem("device", "packages_dir", err));
+    }
+
+    if decoded("gateway") && decoded("dbus") && config.gateway.dbus && config.dbus.is_none() {
+        problem("gateway", "dbus", "the dbus gateway requires a [dbus] section".to_string());
+    }
+    if decoded("gateway") && decoded("dbus") && config.gateway.rvi && config.dbus.is_none() {
+        problem("gateway", "rvi", "the rvi gateway requires a [dbus] section".to_string());
+    }
+    if decoded("gateway") && decoded("rvi") && config.gateway.rvi && config.rvi.is_none() {
+        problem("gateway", "rvi", "the rvi gateway requires an [rvi] section".to_string());
+    }
+    Ok(())
+}
+
+// Decode a section, recording each bad or unknown key as a problem. A bad key
+// is removed before decoding again so that every problem is reported. Returns
+// whether the section decoded without any bad keys.
+fn check_section<T: Decodable>(toml: &str, table: &Table, section: &str, problems: &mut Vec<ConfigProblem>) -> bool {
+    let mut value = match table.get(section) {
+        Some(value) => value.clone(),
+        None        => return true
+    };
+
+    let mut decoded = true;
+    loop {
+        let mut decoder = Decoder::new(value.clone());
+        match T::decode(&mut decoder) {
+            Ok(_) => {
+                if let Some(leftover) = decoder.toml {
+                    for key in leaf_keys(&leftover, "") {
+                        problems.push(ConfigProblem::new(toml, section, Some(key), "unknown key".to_string()));
+                    }
+                }
+                return decoded
+            }
+
+            Err(err) => {
+                decoded = false;
+                let DecodeError { field, kind } = err;
+                let message = format!("{}", DecodeError { field: None, kind: kind });
+                let removed = field.as_ref().map_or(false, |field| remove_key(&mut value, field));
+                problems.push(ConfigProblem::new(toml, section, field, message));
+                if !removed {
+                    return false
+                }
+            }
+        }
+    }
+}
+
+// Return the dotted path of each non-table value.
+fn leaf_keys(value: &Value, prefix: &str) -> Vec<String> {
+    match *value {
+        Value::Table(ref table) => table.iter().flat_map(|(key, value)| {
+            let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            leaf_keys(value, &path)
+        }).collect(),
+        _ => vec![prefix.to_string()]
+    }
+}
+
+// Remove the value at a dotted path, returning whether it was found.
+fn remove_key(value: &mut Value, path: &str) -> bool {
+    let mut parts = path.splitn(2, '.');
+    let key       = parts.next().unwrap_or("");
+    match (value, parts.next()) {
+        (&mut Value::Table(ref mut table), None) => {
+            table.remove(key).or_else(|| table.remove(&key.replace("_", "-"))).is_some()
+        }
+        (&mut Value::Table(ref mut table), Some(rest)) => {
+            table.get_mut(key).map_or(false, |value| remove_key(value, rest))
+        }
+        _ => false
+    }
+}
+
+// Find the line of a key within a section, or else of the section header.
+fn find_line(toml: &str, section: &str, key: Option<&str>) -> Option<usize> {
+    fn within(path: &str, outer: &str) -> bool {
+        path == outer || path.starts_with(&format!("{}.", outer))
+    }
+
+    let target     = key.map_or(section.to_string(), |key| format!("{}.{}", section, key));
+    let mut table  = String::new();
+    let mut header = None;
+
+    for (n, line) in toml.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue
+        } else if line.starts_with('[') {
+            table = line.trim_matches(|c| c == '[' || c == ']').trim().replace("-", "_");
+            if header.is_none() && within(&table, &target) {
+                header = Some(n + 1);
+            }
+        } else if let Some(eq) = line.find('=') {
+            let name = line[..eq].trim().trim_matches('"').replace("-", "_");
+            let path = if table.is_empty() { name } else { format!("{}.{}", table, name) };
+            if within(&target, &path) {
+                return Some(n + 1)
+            }
+        }
+    }
+    header
+}
+
+// Return a problem with a server URL that would fail on the first request.
+fn check_url(url: &Url) -> Option<String> {
+    match (url.scheme(), url.host_str()) {
+        ("http", Some(_)) | ("https", Some(_)) => None,
+        (_, None)                              => Some(format!("expected a server host in {}", url)),
+        (scheme, _)                            => Some(format!("expected an http or https URL, found {}", scheme)),
+    }
+}
+
+// Return a problem if the directory can't be used for storing packages.
+fn check_writable_dir(dir: &str) -> Option<String> {
+    match fs::metadata(dir) {
+        Err(err)                     => Some(format!("couldn't read {}: {}", dir, err)),
+        Ok(ref meta) if !meta.is_dir() => Some(format!("{} is not a directory", dir)),
+        Ok(_) => {
+            let path = match CString::new(dir) {
+                Ok(path) => path,
+                Err(_)   => return Some(format!("{} contains a nul byte", dir))
+            };
+            if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+                None
+            } else {
+                Some(format!("{} is not writable", dir))
+            }
+        }
+    }
+}
+
+
 #[derive(RustcEncodable, RustcDecodable)]
 struct CredentialsFile {
     pub client_id:     String,
@@ -92,8 +858,8 @@ struct CredentialsFile {
 
 // Read AuthConfig values from the credentials file if it exists, or write the
 // current AuthConfig values to a new credentials file otherwise.
-fn bootstrap_credentials(auth: ParsedAuthConfig) -> Result<ParsedAuthConfig, Error> {
-    let creds = auth.credentials_file.expect("couldn't get credentials_file");
+fn bootstrap_credentials(auth: ParsedAuthConfig, sources: &mut ConfigSources) -> Result<ParsedAuthConfig, Error> {
+    let creds = auth.credentials_file.clone().unwrap_or(AuthConfig::default().credentials_file);
     let path  = Path::new(&creds);
     debug!("bootstrap_credentials: {:?}", path);
 
@@ -104,14 +870,17 @@ fn bootstrap_credentials(auth: ParsedAuthConfig) -> Result<ParsedAuthConfig, Err
             let table = try!(parse_table(&text));
             let auth  = try!(table.get("auth").ok_or(Error::Config("no [auth] section".to_string())));
             let mut decoder = Decoder::new(auth.clone());
-            try!(CredentialsFile::decode(&mut decoder))
+            let credentials = try!(CredentialsFile::decode(&mut decoder));
+            sources.insert("auth.client_id".to_string(), ConfigSource { value: Value::String(credentials.client_id.clone()), source: creds.clone() });
+            sources.insert("auth.client_secret".to_string(), ConfigSource { value: Value::String(credentials.client_secret.clone()), source: creds.clone() });
+            credentials
         }
 
         Err(ref err) if err.kind() == ErrorKind::NotFound => {
             let mut table   = Table::new();
             let credentials = CredentialsFile {
-                client_id:     auth.client_id.expect("expected client_id"),
-                client_secret: auth.client_secret.expect("expected client_secret")
+                client_id:     try!(auth.client_id.ok_or(Error::Config("[auth] client_id required".to_string()))),
+                client_secret: try!(auth.client_secret.ok_or(Error::Config("[auth] client_secret required".to_string())))
             };
             table.insert("auth".to_string(), toml::encode(&credentials));
 
@@ -174,6 +943,67 @@ trait Defaultify<T: Default> {
 }
 
 
+/// The [access] configuration section with an optional policy per gateway.
+#[derive(RustcDecodable, PartialEq, Eq, Debug, Clone, Default)]
+pub struct AccessConfig {
+    pub http:      Option<AccessPolicy>,
+    pub socket:    Option<AccessPolicy>,
+    pub websocket: Option<AccessPolicy>,
+}
+
+
+/// The [audit] configuration section.
+#[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct AuditConfig {
+    pub enabled:     bool,
+    pub path:        String,
+    pub max_size_kb: u64,
+    pub max_files:   u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> AuditConfig {
+        AuditConfig {
+            enabled:     false,
+            path:        "/tmp/sota-audit.log".to_string(),
+            max_size_kb: 1024,
+            max_files:   5,
+        }
+    }
+}
+
+#[derive(RustcDecodable)]
+struct ParsedAuditConfig {
+    enabled:     Option<bool>,
+    path:        Option<String>,
+    max_size_kb: Option<u64>,
+    max_files:   Option<u32>,
+}
+
+impl Default for ParsedAuditConfig {
+    fn default() -> Self {
+        ParsedAuditConfig {
+            enabled:     None,
+            path:        None,
+            max_size_kb: None,
+            max_files:   None
+        }
+    }
+}
+
+impl Defaultify<AuditConfig> for ParsedAuditConfig {
+    fn defaultify(&mut self) -> AuditConfig {
+        let default = AuditConfig::default();
+        AuditConfig {
+            enabled:     self.enabled.take().unwrap_or(default.enabled),
+            path:        self.path.take().unwrap_or(default.path),
+            max_size_kb: self.max_size_kb.take().unwrap_or(default.max_size_kb),
+            max_files:   self.max_files.take().unwrap_or(default.max_files)
+        }
+    }
+}
+
+
 /// The [auth] configuration section.
 #[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct AuthConfig {
@@ -273,49 +1103,118 @@ impl Defaultify<CoreConfig> for ParsedCoreConfig {
 }
 
 
-/// The [dbus] configuration section.
+/// The message bus used by the DBus gateway.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum DBusBus {
+    Session,
+    System,
+    Address(String),
+}
+
+impl FromStr for DBusBus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DBusBus, Error> {
+        match s {
+            "session"            => Ok(DBusBus::Session),
+            "system"             => Ok(DBusBus::System),
+            _ if s.contains(':') => Ok(DBusBus::Address(s.to_string())),
+            _                    => Err(Error::Parse(format!("unknown dbus bus: {}", s)))
+        }
+    }
+}
+
+impl Decodable for DBusBus {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<DBusBus, D::Error> {
+        let bus = try!(d.read_str());
+        bus.parse().map_err(|err| d.error(&format!("{}", err)))
+    }
+}
+
+impl Display for DBusBus {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            DBusBus::Session           => write!(f, "session"),
+            DBusBus::System            => write!(f, "system"),
+            DBusBus::Address(ref addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+
+/// The [dbus] configuration section. The `timeout` of each software manager
+/// call is in milliseconds.
 #[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct DBusConfig {
+    pub bus:                   DBusBus,
     pub name:                  String,
     pub path:                  String,
     pub interface:             String,
     pub software_manager:      String,
     pub software_manager_path: String,
     pub timeout:               i32,
+    pub retries:               u32,
 }
 
 impl Default for DBusConfig {
     fn default() -> DBusConfig {
         DBusConfig {
+            bus:                   DBusBus::Session,
             name:                  "org.genivi.SotaClient".to_string(),
             path:                  "/org/genivi/SotaClient".to_string(),
             interface:             "org.genivi.SotaClient".to_string(),
             software_manager:      "org.genivi.SoftwareLoadingManager".to_string(),
             software_manager_path: "/org/genivi/SoftwareLoadingManager".to_string(),
-            timeout:               60
+            timeout:               5000,
+            retries:               3
         }
     }
 }
 
+impl DBusConfig {
+    /// Generate a DBus policy file allowing `user` to own the client's name and
+    /// call the software manager, and any local application to call the client.
+    pub fn policy(&self, user: &str) -> String {
+        format!(r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-BUS Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <policy user="{user}">
+    <allow own="{name}"/>
+    <allow send_destination="{name}"/>
+    <allow send_destination="{software_manager}"/>
+  </policy>
+  <policy context="default">
+    <allow send_destination="{name}" send_interface="{interface}"/>
+    <allow receive_sender="{name}"/>
+  </policy>
+</busconfig>
+"#, user = user, name = self.name, interface = self.interface, software_manager = self.software_manager)
+    }
+}
+
 #[derive(RustcDecodable)]
 struct ParsedDBusConfig {
+    bus:                   Option<DBusBus>,
     name:                  Option<String>,
     path:                  Option<String>,
     interface:             Option<String>,
     software_manager:      Option<String>,
     software_manager_path: Option<String>,
     timeout:               Option<i32>,
+    retries:               Option<u32>,
 }
 
 impl Default for ParsedDBusConfig {
     fn default() -> Self {
         ParsedDBusConfig {
+            bus:                   None,
             name:                  None,
             path:                  None,
             interface:             None,
             software_manager:      None,
             software_manager_path: None,
-            timeout:               None
+            timeout:               None,
+            retries:               None
         }
     }
 }
@@ -324,12 +1223,14 @@ impl Defaultify<DBusConfig> for ParsedDBusConfig {
     fn defaultify(&mut self) -> DBusConfig {
         let default = DBusConfig::default();
         DBusConfig {
+            bus:                   self.bus.take().unwrap_or(default.bus),
             name:                  self.name.take().unwrap_or(default.name),
             path:                  self.path.take().unwrap_or(default.path),
             interface:             self.interface.take().unwrap_or(default.interface),
             software_manager:      self.software_manager.take().unwrap_or(default.software_manager),
             software_manager_path: self.software_manager_path.take().unwrap_or(default.software_manager_path),
-            timeout:               self.timeout.take().unwrap_or(default.timeout)
+            timeout:               self.timeout.take().unwrap_or(default.timeout),
+            retries:               self.retries.take().unwrap_or(default.retries)
         }
     }
 }
@@ -344,6 +1245,18 @@ pub struct DeviceConfig {
     pub package_manager:   PackageManager,
     pub certificates_path: String,
     pub system_info:       Option<String>,
+    pub disk_reserve_mb:   u64,
+    pub disk_quota_mb:     Option<u64>,
+}
+
+impl DeviceConfig {
+    /// The disk space limits applied to each package storage directory.
+    pub fn storage_limits(&self) -> StorageLimits {
+        StorageLimits {
+            reserve: self.disk_reserve_mb * 1024 * 1024,
+            quota:   self.disk_quota_mb.map(|quota| quota * 1024 * 1024),
+        }
+    }
 }
 
 impl Default for DeviceConfig {
@@ -354,7 +1267,9 @@ impl Default for DeviceConfig {
             packages_dir:      "/tmp/".to_string(),
             package_manager:   PackageManager::Off,
             certificates_path: "/tmp/sota_certificates".to_string(),
-            system_info:       Some("system_info.sh".to_string())
+            system_info:       Some("system_info.sh".to_string()),
+            disk_reserve_mb:   0,
+            disk_quota_mb:     None,
         }
     }
 }
@@ -368,6 +1283,8 @@ struct ParsedDeviceConfig {
     pub polling_interval:  Option<u64>,
     pub certificates_path: Option<String>,
     pub system_info:       Option<String>,
+    pub disk_reserve_mb:   Option<u64>,
+    pub disk_quota_mb:     Option<u64>,
 }
 
 impl Default for ParsedDeviceConfig {
@@ -380,6 +1297,8 @@ impl Default for ParsedDeviceConfig {
             polling_interval:  None,
             certificates_path: None,
             system_info:       None,
+            disk_reserve_mb:   None,
+            disk_quota_mb:     None,
         }
     }
 }
@@ -393,7 +1312,13 @@ impl Defaultify<DeviceConfig> for ParsedDeviceConfig {
             packages_dir:      self.packages_dir.take().unwrap_or(default.packages_dir),
             package_manager:   self.package_manager.take().unwrap_or(default.package_manager),
             certificates_path: self.certificates_path.take().unwrap_or(default.certificates_path),
-            system_info:       self.system_info.take().or(default.system_info),
+            system_info:       self.system_info.take().or(default.system_info).and_then(|cmd| {
+                if cmd.len() > 0 { Some(cmd) } else { None }
+            }),
+            disk_reserve_mb:   self.disk_reserve_mb.take().unwrap_or(default.disk_reserve_mb),
+            disk_quota_mb:     self.disk_quota_mb.take().or(default.disk_quota_mb).and_then(|quota| {
+                if quota > 0 { Some(quota) } else { None }
+            }),
         }
     }
 }
@@ -405,17 +1330,46 @@ pub struct GatewayConfig {
     pub console:   bool,
     pub dbus:      bool,
     pub http:      bool,
+    pub metrics:   bool,
     pub rvi:       bool,
     pub socket:    bool,
     pub websocket: bool,
 }
 
+impl GatewayConfig {
+    /// Whether commands from the named gateway are currently accepted.
+    /// Sources that are not configurable gateways are always accepted.
+    pub fn allows(&self, gateway: &str) -> bool {
+        match gateway {
+            "console"   => self.console,
+            "dbus"      => self.dbus,
+            "http"      => self.http,
+            "socket"    => self.socket,
+            "websocket" => self.websocket,
+            _           => true
+        }
+    }
+
+    /// Turn off the named gateway, such as after it failed to start.
+    pub fn disable(&mut self, gateway: &str) {
+        match gateway {
+            "console"   => self.console = false,
+            "dbus"      => self.dbus = false,
+            "http"      => self.http = false,
+            "socket"    => self.socket = false,
+            "websocket" => self.websocket = false,
+            _           => ()
+        }
+    }
+}
+
 impl Default for GatewayConfig {
     fn default() -> GatewayConfig {
         GatewayConfig {
             console:   false,
             dbus:      false,
             http:      false,
+            metrics:   false,
             rvi:       false,
             socket:    false,
             websocket: false,
@@ -428,6 +1382,7 @@ struct ParsedGatewayConfig {
     console:   Option<bool>,
     dbus:      Option<bool>,
     http:      Option<bool>,
+    metrics:   Option<bool>,
     rvi:       Option<bool>,
     socket:    Option<bool>,
     websocket: Option<bool>,
@@ -439,6 +1394,7 @@ impl Default for ParsedGatewayConfig {
             console:   None,
             dbus:      None,
             http:      None,
+            metrics:   None,
             rvi:       None,
             socket:    None,
             websocket: None
@@ -453,6 +1409,7 @@ impl Defaultify<GatewayConfig> for ParsedGatewayConfig {
             console:   self.console.take().unwrap_or(default.console),
             dbus:      self.dbus.take().unwrap_or(default.dbus),
             http:      self.http.take().unwrap_or(default.http),
+            metrics:   self.metrics.take().unwrap_or(default.metrics),
             rvi:       self.rvi.take().unwrap_or(default.rvi),
             socket:    self.socket.take().unwrap_or(default.socket),
             websocket: self.websocket.take().unwrap_or(default.websocket)
@@ -461,45 +1418,225 @@ impl Defaultify<GatewayConfig> for ParsedGatewayConfig {
 }
 
 
+/// The [health] configuration section.
+#[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct HealthConfig {
+    pub busy_timeout_sec: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig {
+            busy_timeout_sec: 900,
+        }
+    }
+}
+
+#[derive(RustcDecodable)]
+struct ParsedHealthConfig {
+    busy_timeout_sec: Option<u64>,
+}
+
+impl Default for ParsedHealthConfig {
+    fn default() -> Self {
+        ParsedHealthConfig {
+            busy_timeout_sec: None
+        }
+    }
+}
+
+impl Defaultify<HealthConfig> for ParsedHealthConfig {
+    fn defaultify(&mut self) -> HealthConfig {
+        let default = HealthConfig::default();
+        HealthConfig {
+            busy_timeout_sec: self.busy_timeout_sec.take().unwrap_or(default.busy_timeout_sec)
+        }
+    }
+}
+
+
+/// The format of each log record.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogFormat, Error> {
+        match &*s.to_lowercase() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _      => Err(Error::Parse(format!("unknown log format: {}", s)))
+        }
+    }
+}
+
+impl Decodable for LogFormat {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<LogFormat, D::Error> {
+        let format = try!(d.read_str());
+        format.parse().map_err(|err| d.error(&format!("{}", err)))
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+
+/// Where each log record is written.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum LogSink {
+    Stderr,
+    Journald,
+    Syslog,
+}
+
+impl FromStr for LogSink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogSink, Error> {
+        match &*s.to_lowercase() {
+            "stderr"   => Ok(LogSink::Stderr),
+            "journald" => Ok(LogSink::Journald),
+            "syslog"   => Ok(LogSink::Syslog),
+            _          => Err(Error::Parse(format!("unknown log sink: {}", s)))
+        }
+    }
+}
+
+impl Decodable for LogSink {
+    fn decode<D: RustcDecoder>(d: &mut D) -> Result<LogSink, D::Error> {
+        let sink = try!(d.read_str());
+        sink.parse().map_err(|err| d.error(&format!("{}", err)))
+    }
+}
+
+impl Display for LogSink {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            LogSink::Stderr   => write!(f, "stderr"),
+            LogSink::Journald => write!(f, "journald"),
+            LogSink::Syslog   => write!(f, "syslog"),
+        }
+    }
+}
+
+
+/// The [log] configuration section.
+#[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
+pub struct LogConfig {
+    pub level:  String,
+    pub format: LogFormat,
+    pub sink:   LogSink,
+    pub socket: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            level:  "info".to_string(),
+            format: LogFormat::Text,
+            sink:   LogSink::Stderr,
+            socket: None,
+        }
+    }
+}
+
+#[derive(RustcDecodable)]
+struct ParsedLogConfig {
+    level:  Option<String>,
+    format: Option<LogFormat>,
+    sink:   Option<LogSink>,
+    socket: Option<String>,
+}
+
+impl Default for ParsedLogConfig {
+    fn default() -> Self {
+        ParsedLogConfig {
+            level:  None,
+            format: None,
+            sink:   None,
+            socket: None
+        }
+    }
+}
+
+impl Defaultify<LogConfig> for ParsedLogConfig {
+    fn defaultify(&mut self) -> LogConfig {
+        let default = LogConfig::default();
+        LogConfig {
+            level:  self.level.take().unwrap_or(default.level),
+            format: self.format.take().unwrap_or(default.format),
+            sink:   self.sink.take().unwrap_or(default.sink),
+            socket: self.socket.take().or(default.socket)
+        }
+    }
+}
+
+
 /// The [network] configuration section.
 #[derive(RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct NetworkConfig {
-    pub http_server:          SocketAddr,
-    pub rvi_edge_server:      SocketAddr,
-    pub socket_commands_path: String,
-    pub socket_events_path:   String,
-    pub websocket_server:     String
+    pub http_server:             SocketAddr,
+    pub metrics_server:          SocketAddr,
+    pub rvi_edge_server:         SocketAddr,
+    pub socket_commands_path:    String,
+    pub socket_events_path:      String,
+    pub socket_events_version:   String,
+    pub socket_persistent:       bool,
+    pub websocket_server:        String,
+    pub websocket_keepalive_sec: u64
 }
 
 impl Default for NetworkConfig {
     fn default() -> NetworkConfig {
         NetworkConfig {
-            http_server:          "127.0.0.1:8888".parse().unwrap(),
-            rvi_edge_server:      "127.0.0.1:9080".parse().unwrap(),
-            socket_commands_path: "/tmp/sota-commands.socket".to_string(),
-            socket_events_path:   "/tmp/sota-events.socket".to_string(),
-            websocket_server:     "127.0.0.1:3012".to_string()
+            http_server:             "127.0.0.1:8888".parse().unwrap(),
+            metrics_server:          "127.0.0.1:9100".parse().unwrap(),
+            rvi_edge_server:         "127.0.0.1:9080".parse().unwrap(),
+            socket_commands_path:    "/tmp/sota-commands.socket".to_string(),
+            socket_events_path:      "/tmp/sota-events.socket".to_string(),
+            socket_events_version:   "0.1".to_string(),
+            socket_persistent:       false,
+            websocket_server:        "127.0.0.1:3012".to_string(),
+            websocket_keepalive_sec: 30
         }
     }
 }
 
 #[derive(RustcDecodable)]
 struct ParsedNetworkConfig {
-    http_server:          Option<SocketAddr>,
-    rvi_edge_server:      Option<SocketAddr>,
-    socket_commands_path: Option<String>,
-    socket_events_path:   Option<String>,
-    websocket_server:     Option<String>
+    http_server:             Option<SocketAddr>,
+    metrics_server:          Option<SocketAddr>,
+    rvi_edge_server:         Option<SocketAddr>,
+    socket_commands_path:    Option<String>,
+    socket_events_path:      Option<String>,
+    socket_events_version:   Option<String>,
+    socket_persistent:       Option<bool>,
+    websocket_server:        Option<String>,
+    websocket_keepalive_sec: Option<u64>
 }
 
 impl Default for ParsedNetworkConfig {
     fn default() -> Self {
         ParsedNetworkConfig {
-            http_server:          None,
-            rvi_edge_server:      None,
-            socket_commands_path: None,
-            socket_events_path:   None,
-            websocket_server:     None
+            http_server:             None,
+            metrics_server:          None,
+            rvi_edge_server:         None,
+            socket_commands_path:    None,
+            socket_events_path:      None,
+            socket_events_version:   None,
+            socket_persistent:       None,
+            websocket_server:        None,
+            websocket_keepalive_sec: None
         }
     }
 }
@@ -508,11 +1645,15 @@ impl Defaultify<NetworkConfig> for ParsedNetworkConfig {
     fn defaultify(&mut self) -> NetworkConfig {
         let default = NetworkConfig::default();
         NetworkConfig {
-            http_server:          self.http_server.take().unwrap_or(default.http_server),
-            rvi_edge_server:      self.rvi_edge_server.take().unwrap_or(default.rvi_edge_server),
-            socket_commands_path: self.socket_commands_path.take().unwrap_or(default.socket_commands_path),
-            socket_events_path:   self.socket_events_path.take().unwrap_or(default.socket_events_path),
-            websocket_server:     self.websocket_server.take().unwrap_or(default.websocket_server)
+            http_server:             self.http_server.take().unwrap_or(default.http_server),
+            metrics_server:          self.metrics_server.take().unwrap_or(default.metrics_server),
+            rvi_edge_server:         self.rvi_edge_server.take().unwrap_or(default.rvi_edge_server),
+            socket_commands_path:    self.socket_commands_path.take().unwrap_or(default.socket_commands_path),
+            socket_events_path:      self.socket_events_path.take().unwrap_or(default.socket_events_path),
+            socket_events_version:   self.socket_events_version.take().unwrap_or(default.socket_events_version),
+            socket_persistent:       self.socket_persistent.take().unwrap_or(default.socket_persistent),
+            websocket_server:        self.websocket_server.take().unwrap_or(default.websocket_server),
+            websocket_keepalive_sec: self.websocket_keepalive_sec.take().unwrap_or(default.websocket_keepalive_sec)
         }
     }
 }
@@ -567,9 +1708,23 @@ impl Defaultify<RviConfig> for ParsedRviConfig {
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+
     use super::*;
+    use package_manager::TestDir;
 
 
+    const AUDIT_CONFIG: &'static str =
+        r#"
+        [audit]
+        enabled = false
+        path = "/tmp/sota-audit.log"
+        max_size_kb = 1024
+        max_files = 5
+        "#;
+
     const AUTH_CONFIG: &'static str =
         r#"
         [auth]
@@ -590,12 +1745,14 @@ mod tests {
     const DBUS_CONFIG: &'static str =
         r#"
         [dbus]
+        bus = "session"
         name = "org.genivi.SotaClient"
         path = "/org/genivi/SotaClient"
         interface = "org.genivi.SotaClient"
         software_manager = "org.genivi.SoftwareLoadingManager"
         software_manager_path = "/org/genivi/SoftwareLoadingManager"
-        timeout = 60
+        timeout = 5000
+        retries = 3
         "#;
 
     const DEVICE_CONFIG: &'static str =
@@ -607,6 +1764,7 @@ mod tests {
         package_manager = "off"
         certificates_path = "/tmp/sota_certificates"
         system_info = "system_info.sh"
+        disk_reserve_mb = 0
         "#;
 
     const GATEWAY_CONFIG: &'static str =
@@ -615,19 +1773,38 @@ mod tests {
         console = false
         dbus = false
         http = false
+        metrics = false
         rvi = false
         socket = false
         websocket = false
         "#;
 
+    const HEALTH_CONFIG: &'static str =
+        r#"
+        [health]
+        busy_timeout_sec = 900
+        "#;
+
+    const LOG_CONFIG: &'static str =
+        r#"
+        [log]
+        level = "info"
+        format = "text"
+        sink = "stderr"
+        "#;
+
     const NETWORK_CONFIG: &'static str =
         r#"
         [network]
         http_server = "127.0.0.1:8888"
+        metrics_server = "127.0.0.1:9100"
         rvi_edge_server = "127.0.0.1:9080"
         socket_commands_path = "/tmp/sota-commands.socket"
         socket_events_path = "/tmp/sota-events.socket"
+        socket_events_version = "0.1"
+        socket_persistent = false
         websocket_server = "127.0.0.1:3012"
+        websocket_keepalive_sec = 30
         "#;
 
     const RVI_CONFIG: &'static str =
@@ -647,9 +1824,12 @@ mod tests {
     #[test]
     fn basic_config() {
         let config = String::new()
+            + AUDIT_CONFIG
             + CORE_CONFIG
             + DEVICE_CONFIG
             + GATEWAY_CONFIG
+            + HEALTH_CONFIG
+            + LOG_CONFIG
             + NETWORK_CONFIG;
         assert_eq!(Config::parse(&config).unwrap(), Config::default());
     }
@@ -657,16 +1837,320 @@ mod tests {
     #[test]
     fn default_config() {
         let config = String::new()
+            + AUDIT_CONFIG
             + AUTH_CONFIG
             + CORE_CONFIG
             + DBUS_CONFIG
             + DEVICE_CONFIG
             + GATEWAY_CONFIG
+            + HEALTH_CONFIG
+            + LOG_CONFIG
             + NETWORK_CONFIG
             + RVI_CONFIG;
         assert_eq!(Config::load("tests/toml/default.toml").unwrap(), Config::parse(&config).unwrap());
     }
 
+    #[test]
+    fn access_config() {
+        let config = Config::parse(r#"
+            [access.http]
+            token = "secret"
+            commands = ["GetUpdateRequests", "ListInstalledPackages"]
+
+            [access.socket]
+            uids = [0, 1000]
+            "#).unwrap();
+
+        let http = config.access.http.expect("expected http access policy");
+        assert_eq!(http.token, Some("secret".to_string()));
+        assert_eq!(http.commands, Some(vec!["GetUpdateRequests".to_string(), "ListInstalledPackages".to_string()]));
+        let socket = config.access.socket.expect("expected socket access policy");
+        assert_eq!(socket.uids, Some(vec![0, 1000]));
+        assert_eq!(socket.gids, None);
+        assert_eq!(config.access.websocket, None);
+    }
+
+    #[test]
+    fn dbus_bus_config() {
+        let config = Config::parse("[dbus]\nbus = \"system\"").unwrap();
+        assert_eq!(config.dbus.unwrap().bus, DBusBus::System);
+        let config = Config::parse("[dbus]\nbus = \"unix:path=/run/sota/bus\"").unwrap();
+        assert_eq!(config.dbus.unwrap().bus, DBusBus::Address("unix:path=/run/sota/bus".to_string()));
+        assert!(Config::parse("[dbus]\nbus = \"other\"").is_err());
+    }
+
+    #[test]
+    fn dbus_policy() {
+        let policy = DBusConfig::default().policy("sota");
+        assert!(policy.contains(r#"<policy user="sota">"#));
+        assert!(policy.contains(r#"<allow own="org.genivi.SotaClient"/>"#));
+        assert!(policy.contains(r#"<allow send_destination="org.genivi.SoftwareLoadingManager"/>"#));
+    }
+
+    #[test]
+    fn log_format_config() {
+        let config = Config::parse("[log]\nformat = \"json\"").unwrap();
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert!(Config::parse("[log]\nformat = \"xml\"").is_err());
+    }
+
+    #[test]
+    fn log_sink_config() {
+        let config = Config::parse("[log]\nsink = \"journald\"\nsocket = \"/tmp/journal.socket\"").unwrap();
+        assert_eq!(config.log.sink, LogSink::Journald);
+        assert_eq!(config.log.socket, Some("/tmp/journal.socket".to_string()));
+        assert_eq!(Config::parse("[log]\nsink = \"syslog\"").unwrap().log.sink, LogSink::Syslog);
+        assert!(Config::parse("[log]\nsink = \"file\"").is_err());
+    }
+
+    #[test]
+    fn check_config_keys() {
+        let toml = "[core]\npolling = \"yes\"\n\n[device]\npackage_manager = \"apk\"\ncolour = \"blue\"\n\n[logging]\nlevel = \"debug\"\n";
+        let problems = Config::check(toml);
+        let found    = problems.iter().map(|p| (p.section.as_str(), p.key.clone(), p.line)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("logging", None, Some(8)),
+            ("core", Some("polling".to_string()), Some(2)),
+            ("device", Some("package_manager".to_string()), Some(5)),
+            ("device", Some("colour".to_string()), Some(6)),
+        ]);
+        assert_eq!(format!("{}", problems[2]), "line 5: [device] package_manager: Parse error: unknown package manager: apk");
+        assert_eq!(format!("{}", problems[3]), "line 6: [device] colour: unknown key");
+
+        let problems = Config::check("[core]\nserver = \"http://localhost\"\npolling = ");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(3));
+        assert!(Config::parse("[device]\npackage_manager = \"apk\"").is_err());
+    }
+
+    #[test]
+    fn check_config_environment() {
+        let dir  = TestDir::new("sota-test-check");
+        let toml = format!("[core]\nserver = \"ftp://example.com\"\n\n[device]\npackages_dir = \"{0}/missing\"\n\
+                            certificates_path = \"{0}/ca.crt\"\n\n[gateway]\ndbus = true\n", dir.0);
+        let found = Config::check(&toml).into_iter().map(|p| (p.key.unwrap(), p.line)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            ("server".to_string(), Some(2)),
+            ("certificates_path".to_string(), Some(6)),
+            ("packages_dir".to_string(), Some(5)),
+            ("dbus".to_string(), Some(9)),
+        ]);
+
+        let toml = format!("[device]\ncolour = \"blue\"\ncertificates_path = \"{0}/ca.crt\"\n\
+                            packages_dir = \"{0}\"\n\n[logging]\nlevel = \"debug\"\n", dir.0);
+        let found = Config::check(&toml).into_iter().map(|p| (p.key, p.line)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (None, Some(6)),
+            (Some("colour".to_string()), Some(2)),
+            (Some("certificates_path".to_string()), Some(3)),
+        ]);
+
+        fs::create_dir(format!("{}/missing", dir.0)).unwrap();
+        File::create(format!("{}/ca.crt", dir.0)).unwrap();
+        let toml = toml.replace("ftp:", "https:").replace("dbus = true", "dbus = false");
+        assert_eq!(Config::check(&toml), Vec::new());
+    }
+
+    #[test]
+    fn validate_config() {
+        assert!(Config::default().validate().is_ok());
+        assert!(Config::parse("[gateway]\ndbus = true").unwrap().validate().is_err());
+        assert!(Config::parse("[gateway]\ndbus = true\n[dbus]").unwrap().validate().is_ok());
+        assert!(Config::parse("[gateway]\nrvi = true\n[dbus]").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn reload_config() {
+        let running = Config::default();
+        let loaded  = Config::parse(r#"
+            [core]
+            polling_sec = 60
+
+            [gateway]
+            http = true
+
+            [log]
+            level = "debug"
+
+            [network]
+            http_server = "0.0.0.0:8888"
+            "#).unwrap();
+
+        let (config, changes) = running.reload(loaded);
+        assert_eq!(changes.applied, vec!["core.polling_sec", "gateway.http", "log.level"]);
+        assert_eq!(changes.restart_required, vec!["network"]);
+        assert_eq!(config.core.polling_sec, 60);
+        assert_eq!(config.log.level, "debug");
+        assert_eq!(config.network, running.network);
+        assert_eq!(running.reload(running.clone()).1, ConfigChanges::default());
+
+        // a running gateway is only stopped by a restart
+        let (config, changes) = config.reload(Config::parse("[core]\npolling_sec = 60\n[log]\nlevel = \"debug\"").unwrap());
+        assert_eq!(changes.applied, Vec::<String>::new());
+        assert_eq!(changes.restart_required, vec!["gateway.http"]);
+        assert!(config.gateway.http);
+
+        // the rvi services only read the device settings on start-up
+        let mut rvi = Config::default();
+        rvi.gateway.rvi = true;
+        let mut loaded = rvi.clone();
+        loaded.device.uuid = "new-uuid".to_string();
+        loaded.device.disk_quota_mb = Some(10);
+        let (config, changes) = rvi.reload(loaded.clone());
+        assert_eq!(changes.applied, Vec::<String>::new());
+        assert_eq!(changes.restart_required, vec!["device.uuid", "device.disk_quota_mb"]);
+        assert_eq!(config.device, rvi.device);
+        loaded.gateway.rvi = false;
+        assert_eq!(running.reload(loaded).1.applied, vec!["device.uuid", "device.disk_quota_mb"]);
+    }
+
+    #[test]
+    fn config_overrides() {
+        let vars = vec![
+            ("SOTA_CORE_POLLING_SEC".to_string(), "30".to_string()),
+            ("SOTA_DEVICE_VIN".to_string(), "env_vin".to_string()),
+            ("SOTA_GATEWAY_HTTP".to_string(), "true".to_string()),
+            ("SOTA_UNKNOWN_KEY".to_string(), "ignored".to_string()),
+            ("PATH".to_string(), "/usr/bin".to_string()),
+        ];
+        let mut overrides = ConfigOverride::from_env(vars.into_iter()).unwrap();
+        assert_eq!(overrides.len(), 3);
+
+        let vin = CONFIG_KEYS.iter().find(|key| key.flag() == "device-vin").unwrap();
+        overrides.push(vin.parse("--device-vin", "cli_vin").unwrap());
+        let config = Config::parse_with(r#"
+            [core]
+            polling_sec = 60
+
+            [device]
+            vin = "file_vin"
+            disk_quota_mb = 0
+            "#, &overrides).unwrap();
+        assert_eq!(config.core.polling_sec, 30);
+        assert_eq!(config.device.vin, "cli_vin");
+        assert_eq!(config.device.disk_quota_mb, None);
+        assert_eq!(config.gateway.http, true);
+        assert_eq!(config.dbus, None);
+
+        let rvi     = CONFIG_KEYS.iter().find(|key| key.flag() == "rvi-timeout").unwrap();
+        let timeout = vec![rvi.parse("--rvi-timeout", "5").unwrap()];
+        assert_eq!(Config::parse_with("", &timeout).unwrap().rvi, None);
+        assert_eq!(Config::parse_with("[rvi]", &timeout).unwrap().rvi.unwrap().timeout, Some(5));
+
+        let legacy = Config::parse_with("[device]\npolling_interval = 10", &overrides).unwrap();
+        assert_eq!(legacy.core.polling, true);
+        assert_eq!(legacy.core.polling_sec, 30);
+        let legacy = Config::parse_with("[device]\npolling_interval = 0", &overrides).unwrap();
+        assert_eq!(legacy.core.polling, false);
+        assert_eq!(legacy.core.polling_sec, 30);
+
+        let dir   = TestDir::new("sota-test-overrides");
+        let creds = format!("{}/credentials.toml", dir.0);
+        File::create(&creds).unwrap().write_all(b"[auth]\nclient_id = \"creds_id\"\nclient_secret = \"creds_secret\"\n").unwrap();
+        let vars     = vec![("SOTA_AUTH_CLIENT_ID".to_string(), "env_id".to_string())];
+        let mut auth = ConfigOverride::from_env(vars.into_iter()).unwrap();
+        let secret   = CONFIG_KEYS.iter().find(|key| key.flag() == "auth-client-secret").unwrap();
+        auth.push(secret.parse("--auth-client-secret", "cli_secret").unwrap());
+        let toml   = format!("[auth]\nclient_id = \"file_id\"\nclient_secret = \"file_secret\"\ncredentials_file = \"{}\"\n", creds);
+        let config = Config::parse_with(&toml, &auth).unwrap().auth.unwrap();
+        assert_eq!(config.client_id, "env_id");
+        assert_eq!(config.client_secret, "cli_secret");
+        assert_eq!(Config::parse(&toml).unwrap().auth.unwrap().client_id, "creds_id");
+
+        let bad_int = vec![("SOTA_CORE_POLLING_SEC".to_string(), "often".to_string())];
+        assert!(ConfigOverride::from_env(bad_int.into_iter()).is_err());
+        let bad_bool = vec![("SOTA_GATEWAY_HTTP".to_string(), "yes".to_string())];
+        assert!(ConfigOverride::from_env(bad_bool.into_iter()).is_err());
+    }
+
+    #[test]
+    fn layered_config_files() {
+        let dir  = TestDir::new("sota-test-layers");
+        let base = format!("{}/sota.toml", dir.0);
+        let more = format!("{}/more.toml", dir.0);
+        fs::create_dir(format!("{}.d", base)).unwrap();
+        File::create(&base).unwrap().write_all(b"[core]\npolling_sec = 60\n[device]\nvin = \"base_vin\"\n").unwrap();
+        File::create(format!("{}.d/10-device.toml", base)).unwrap().write_all(b"[device]\nvin = \"drop_in_vin\"\n").unwrap();
+        File::create(format!("{}.d/20-device.toml", base)).unwrap().write_all(b"[device]\nuuid = \"drop_in_uuid\"\n").unwrap();
+        File::create(format!("{}.d/ignored.bak", base)).unwrap().write_all(b"[device]\nuuid = \"backup\"\n").unwrap();
+        File::create(&more).unwrap().write_all(b"[core]\npolling-sec = 30\n").unwrap();
+
+        let (config, sources) = Config::load_files(&[base.clone(), more.clone()], &[]).unwrap();
+        assert_eq!(config.core.polling_sec, 30);
+        assert_eq!(config.device.vin, "drop_in_vin");
+        assert_eq!(config.device.uuid, "drop_in_uuid");
+        assert_eq!(config.device.packages_dir, DeviceConfig::default().packages_dir);
+        assert_eq!(sources["core.polling_sec"].source, more);
+        assert_eq!(sources["device.vin"].source, format!("{}.d/10-device.toml", base));
+        assert_eq!(sources["device.uuid"].value, Value::String("drop_in_uuid".to_string()));
+        assert_eq!(sources.len(), 3);
+
+        let access = format!("{}/access.toml", dir.0);
+        File::create(&access).unwrap().write_all(b"[access.http]\ntoken = \"secret\"\n[access.socket]\nuids = [0]\n").unwrap();
+        File::create(&more).unwrap().write_all(b"[access.http]\ncommands = [\"GetUpdateRequests\"]\n").unwrap();
+        let (config, sources) = Config::load_files(&[access, more.clone()], &[]).unwrap();
+        let http = config.access.http.unwrap();
+        assert_eq!(http.token, Some("secret".to_string()));
+        assert_eq!(http.commands, Some(vec!["GetUpdateRequests".to_string()]));
+        assert_eq!(config.access.socket.unwrap().uids, Some(vec![0]));
+        assert_eq!(sources["access.http.commands"].source, more);
+
+        let vin = CONFIG_KEYS.iter().find(|key| key.flag() == "device-vin").unwrap();
+        let (config, sources) = Config::load_files(&[base], &[vin.parse("--device-vin", "cli_vin").unwrap()]).unwrap();
+        assert_eq!(config.device.vin, "cli_vin");
+        assert_eq!(sources["device.vin"].source, "--device-vin");
+        assert!(Config::load_files(&[format!("{}/missing.toml", dir.0)], &[]).is_err());
+    }
+
+    #[test]
+    fn config_keys_match_sections() {
+        for key in CONFIG_KEYS {
+            let value  = match key.kind {
+                ValueKind::Bool    => "true",
+                ValueKind::Integer => "1",
+                ValueKind::String  => "\"http://localhost\"",
+            };
+            let checks = Config::check(&format!("[{}]\n{} = {}", key.section, key.key, value));
+            assert!(checks.iter().all(|problem| problem.message != "unknown key"), "{}", key.env_var());
+        }
+    }
+
+    #[test]
+    fn derived_config_sources() {
+        let dir    = TestDir::new("sota-test-sources");
+        let base   = format!("{}/sota.toml", dir.0);
+        let creds  = format!("{}/credentials.toml", dir.0);
+        File::create(&base).unwrap().write_all(format!("[auth]\nclient_id = \"file_id\"\nclient_secret = \"file_secret\"\n\
+                                                        credentials_file = \"{}\"\n[device]\npolling_interval = 20\n", creds).as_bytes()).unwrap();
+        File::create(&creds).unwrap().write_all(b"[auth]\nclient_id = \"creds_id\"\nclient_secret = \"creds_secret\"\n").unwrap();
+
+        let (config, sources) = Config::load_files(&[base.clone()], &[]).unwrap();
+        assert_eq!(config.auth.unwrap().client_id, "creds_id");
+        assert_eq!(sources["auth.client_id"].source, creds);
+        assert_eq!(sources["auth.client_secret"].value, Value::String("creds_secret".to_string()));
+        assert_eq!(sources["core.polling_sec"].value, Value::Integer(20));
+        assert_eq!(sources["core.polling_sec"].source, format!("{} as device.polling_interval", base));
+        assert_eq!(sources["core.polling"].value, Value::Boolean(true));
+        assert!(sources.get("device.polling_interval").is_none());
+    }
+
+    #[test]
+    fn check_config_drop_ins() {
+        let dir  = TestDir::new("sota-test-check-drop-ins");
+        let base = format!("{}/sota.toml", dir.0);
+        fs::create_dir(format!("{}.d", base)).unwrap();
+        File::create(format!("{}/ca.crt", dir.0)).unwrap();
+        File::create(&base).unwrap().write_all(format!("[device]\ncertificates_path = \"{0}/ca.crt\"\npackages_dir = \"{0}\"\n", dir.0).as_bytes()).unwrap();
+        File::create(format!("{}.d/10-device.toml", base)).unwrap().write_all(b"[device]\ncolour = \"blue\"\n").unwrap();
+        File::create(format!("{}.d/20-core.toml", base)).unwrap().write_all(b"\n[core]\nserver = \"ftp://example.com\"\n").unwrap();
+
+        let found = Config::check_files(&[base.clone()]).unwrap().into_iter().map(|(file, p)| (file, p.key, p.line)).collect::<Vec<_>>();
+        assert_eq!(found, vec![
+            (format!("{}.d/10-device.toml", base), Some("colour".to_string()), Some(2)),
+            (format!("{}.d/20-core.toml", base), Some("server".to_string()), Some(3)),
+        ]);
+    }
+
     #[test]
     fn backwards_compatible_config() {
         let config = Config::load("tests/toml/old.toml").unwrap();
@@ -6,6 +6,7 @@ use datatype::update_report::{InstalledFirmware, InstalledPackage, OperationResu
 
 static MISSING_ARG: &'static str = "Error.MissingArgument";
 static MALFORMED_ARG: &'static str = "Error.MalformedArgument";
+static FAILED: &'static str = "Error.Failed";
 
 /// Format a `DBus` error message indicating a missing argument.
 pub fn missing_arg() -> (&'static str, String) {
@@ -17,6 +18,11 @@ pub fn malformed_arg() -> (&'static str, String) {
     (MALFORMED_ARG, "Malformed argument".to_string())
 }
 
+/// Format a `DBus` error message indicating that the request failed.
+pub fn failed(reason: String) -> (&'static str, String) {
+    (FAILED, reason)
+}
+
 
 struct DecodedValue(pub Value);
 
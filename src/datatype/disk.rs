@@ -0,0 +1,116 @@
+use libc;
+use std::cmp;
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use datatype::Error;
+
+
+/// Limits on the disk space that downloaded packages may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageLimits {
+    /// The number of bytes to always leave free on the filesystem.
+    pub reserve: u64,
+    /// The maximum number of bytes to store in a storage directory.
+    pub quota:   Option<u64>,
+}
+
+impl Default for StorageLimits {
+    fn default() -> Self {
+        StorageLimits { reserve: 0, quota: None }
+    }
+}
+
+impl StorageLimits {
+    /// Check that `size` more bytes may be stored inside `dir`, returning an
+    /// `Error::DiskFull` otherwise.
+    pub fn check(&self, dir: &str, size: u64) -> Result<(), Error> {
+        try!(fs::create_dir_all(dir));
+        let free = try!(free_space(dir));
+        if free < size + self.reserve {
+            return Err(Error::DiskFull(format!("{} bytes needed in {} but only {} free with {} reserved",
+                                               size, dir, free, self.reserve)));
+        }
+
+        if let Some(quota) = self.quota {
+            let used = try!(used_space(Path::new(dir)));
+            if used + size > quota {
+                return Err(Error::DiskFull(format!("{} bytes needed in {} but {} of the {} byte quota is used",
+                                                   size, dir, used, quota)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the number of bytes that may still be stored inside `dir`.
+    pub fn available(&self, dir: &str) -> Result<u64, Error> {
+        try!(fs::create_dir_all(dir));
+        let available = try!(free_space(dir)).saturating_sub(self.reserve);
+        match self.quota {
+            Some(quota) => Ok(cmp::min(available, quota.saturating_sub(try!(used_space(Path::new(dir)))))),
+            None        => Ok(available)
+        }
+    }
+}
+
+
+/// Return the number of bytes available to unprivileged users at `path`.
+pub fn free_space(path: &str) -> Result<u64, Error> {
+    let cpath = try!(CString::new(path).map_err(|err| Error::Parse(format!("invalid path: {}", err))));
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    match unsafe { libc::statvfs(cpath.as_ptr(), &mut stat) } {
+        0 => Ok(stat.f_bavail as u64 * stat.f_frsize as u64),
+        _ => Err(Error::Io(::std::io::Error::last_os_error()))
+    }
+}
+
+/// Return the total size in bytes of all files beneath `path`.
+pub fn used_space(path: &Path) -> Result<u64, Error> {
+    let meta = try!(fs::symlink_metadata(path));
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+
+    let mut total = 0;
+    for entry in try!(fs::read_dir(path)) {
+        total += try!(used_space(&try!(entry).path()));
+    }
+    Ok(total)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatype::Error;
+    use package_manager::TestDir;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+
+    #[test]
+    fn check_quota() {
+        let dir = TestDir::new("sota-test-disk");
+        let _   = File::create(format!("{}/file", dir.0)).unwrap().write_all(&[0; 100]);
+        assert_eq!(used_space(Path::new(&dir.0)).unwrap(), 100);
+
+        let limits = StorageLimits { reserve: 0, quota: Some(150) };
+        assert!(limits.check(&dir.0, 50).is_ok());
+        match limits.check(&dir.0, 51) {
+            Err(Error::DiskFull(_)) => (),
+            other                   => panic!("expected DiskFull, got {:?}", other)
+        }
+        assert_eq!(limits.available(&dir.0).unwrap(), 50);
+    }
+
+    #[test]
+    fn check_reserve() {
+        let dir    = TestDir::new("sota-test-disk");
+        let limits = StorageLimits { reserve: u64::max_value() / 2, quota: None };
+        assert!(StorageLimits::default().check(&dir.0, 1).is_ok());
+        assert!(limits.check(&dir.0, 1).is_err());
+    }
+}
@@ -21,9 +21,11 @@ use ws::Error as WebsocketError;
 /// System-wide errors that are returned from `Result` type failures.
 #[derive(Debug)]
 pub enum Error {
+    AccessDenied(String),
     Client(String),
     Command(String),
     Config(String),
+    DiskFull(String),
     FromUtf8(FromUtf8Error),
     Http(ResponseData),
     HttpAuth(ResponseData),
@@ -94,9 +96,11 @@ derive_from!([
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         let inner: String = match *self {
+            Error::AccessDenied(ref s)  => format!("Access denied: {}", s.clone()),
             Error::Client(ref s)        => format!("Http client error: {}", s.clone()),
             Error::Command(ref e)       => format!("Unknown Command: {}", e.clone()),
             Error::Config(ref s)        => format!("Bad Config: {}", s.clone()),
+            Error::DiskFull(ref s)      => format!("Disk full: {}", s.clone()),
             Error::FromUtf8(ref e)      => format!("From utf8 error: {}", e.clone()),
             Error::Http(ref r)          => format!("HTTP client error: {}", r.clone()),
             Error::HttpAuth(ref r)      => format!("HTTP authorization error: {}", r.clone()),
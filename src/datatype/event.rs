@@ -1,6 +1,6 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
-use datatype::{DownloadComplete, Package, UpdateAvailable, UpdateReport,
+use datatype::{AuditEntry, ConfigChanges, DownloadComplete, Package, UpdateAvailable, UpdateReport,
                UpdateRequest, UpdateRequestId};
 
 
@@ -31,10 +31,14 @@ pub enum Event {
 
     /// Downloading an update.
     DownloadingUpdate(UpdateRequestId),
+    /// The number of chunks of an update received so far.
+    DownloadProgress(UpdateRequestId, u64),
     /// An update was downloaded.
     DownloadComplete(DownloadComplete),
     /// Downloading an update failed.
     DownloadFailed(UpdateRequestId, String),
+    /// An update was refused as there is not enough disk space to store it.
+    DiskFull(UpdateRequestId, String),
 
     /// Installing an update.
     InstallingUpdate(UpdateRequestId),
@@ -42,6 +46,8 @@ pub enum Event {
     InstallComplete(UpdateReport),
     /// The installation of an update failed.
     InstallFailed(UpdateReport),
+    /// An update was aborted on request.
+    UpdateAborted(UpdateRequestId),
 
     /// An update report was sent to the Core server.
     UpdateReportSent,
@@ -54,6 +60,68 @@ pub enum Event {
 
     /// A broadcast event requesting an update on externally installed software.
     InstalledSoftwareNeeded,
+
+    /// The audit log entries matching a history query.
+    History(Vec<AuditEntry>),
+    /// The config was reloaded with the listed changes.
+    ConfigReloaded(ConfigChanges),
+
+    /// The RVI services were registered with the RVI node.
+    RviConnected,
+    /// The connection to the RVI node was lost.
+    RviDisconnected(String),
+}
+
+impl Event {
+    /// Returns the name of the `Event` variant without any arguments.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Error(_)                  => "Error",
+            Event::Authenticated             => "Authenticated",
+            Event::NotAuthenticated          => "NotAuthenticated",
+            Event::AlreadyAuthenticated      => "AlreadyAuthenticated",
+            Event::UpdatesReceived(_)        => "UpdatesReceived",
+            Event::UpdateAvailable(_)        => "UpdateAvailable",
+            Event::NoUpdateRequests          => "NoUpdateRequests",
+            Event::FoundInstalledPackages(_) => "FoundInstalledPackages",
+            Event::FoundSystemInfo(_)        => "FoundSystemInfo",
+            Event::DownloadingUpdate(_)      => "DownloadingUpdate",
+            Event::DownloadProgress(_, _)    => "DownloadProgress",
+            Event::DownloadComplete(_)       => "DownloadComplete",
+            Event::DownloadFailed(_, _)      => "DownloadFailed",
+            Event::DiskFull(_, _)            => "DiskFull",
+            Event::InstallingUpdate(_)       => "InstallingUpdate",
+            Event::InstallComplete(_)        => "InstallComplete",
+            Event::InstallFailed(_)          => "InstallFailed",
+            Event::UpdateAborted(_)          => "UpdateAborted",
+            Event::UpdateReportSent          => "UpdateReportSent",
+            Event::InstalledPackagesSent     => "InstalledPackagesSent",
+            Event::InstalledSoftwareSent     => "InstalledSoftwareSent",
+            Event::SystemInfoSent            => "SystemInfoSent",
+            Event::InstalledSoftwareNeeded   => "InstalledSoftwareNeeded",
+            Event::History(_)                => "History",
+            Event::ConfigReloaded(_)         => "ConfigReloaded",
+            Event::RviConnected              => "RviConnected",
+            Event::RviDisconnected(_)        => "RviDisconnected",
+        }
+    }
+
+    /// Returns the update request id that the `Event` refers to, if any.
+    pub fn update_id(&self) -> Option<&str> {
+        match *self {
+            Event::UpdateAvailable(ref avail)   => Some(avail.update_id.as_str()),
+            Event::DownloadComplete(ref dl)     => Some(dl.update_id.as_str()),
+            Event::InstallComplete(ref report)  |
+            Event::InstallFailed(ref report)    => Some(report.update_id.as_str()),
+            Event::DownloadingUpdate(ref id)    |
+            Event::DownloadProgress(ref id, _)  |
+            Event::DownloadFailed(ref id, _)    |
+            Event::DiskFull(ref id, _)          |
+            Event::InstallingUpdate(ref id)     |
+            Event::UpdateAborted(ref id)        => Some(id.as_str()),
+            _                                   => None
+        }
+    }
 }
 
 impl Display for Event {
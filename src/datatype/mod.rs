@@ -1,7 +1,10 @@
+pub mod access;
+pub mod audit;
 pub mod auth;
 pub mod command;
 pub mod config;
 pub mod dbus;
+pub mod disk;
 pub mod error;
 pub mod event;
 pub mod json_rpc;
@@ -10,10 +13,15 @@ pub mod shell;
 pub mod update_report;
 pub mod update_request;
 
+pub use self::access::{AccessPolicy, PeerCredentials};
+pub use self::audit::AuditEntry;
 pub use self::auth::{AccessToken, Auth, ClientCredentials};
 pub use self::command::Command;
-pub use self::config::{AuthConfig, CoreConfig, Config, DBusConfig, DeviceConfig,
-                       GatewayConfig, RviConfig};
+pub use self::config::{AccessConfig, AuditConfig, AuthConfig, CONFIG_KEYS, CoreConfig, Config,
+                       ConfigChanges, ConfigKey, ConfigOverride, ConfigProblem, ConfigSource,
+                       ConfigSources, DBusBus, DBusConfig, DeviceConfig, GatewayConfig, HealthConfig,
+                       LogConfig, LogFormat, LogSink, RviConfig, ValueKind};
+pub use self::disk::StorageLimits;
 pub use self::error::Error;
 pub use self::event::Event;
 pub use self::json_rpc::{RpcRequest, RpcOk, RpcErr};
@@ -22,6 +30,6 @@ pub use self::shell::system_info;
 pub use self::update_report::{DeviceReport, InstalledFirmware, InstalledPackage,
                               InstalledSoftware, OperationResult, UpdateResultCode,
                               UpdateReport};
-pub use self::update_request::{ChunkReceived, DownloadComplete, DownloadFailed,
+pub use self::update_request::{ChunkRange, ChunkReceived, DownloadComplete, DownloadFailed,
                                DownloadStarted, Package, UpdateAvailable,
                                UpdateRequest, UpdateRequestId, UpdateRequestStatus};
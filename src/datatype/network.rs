@@ -102,7 +102,7 @@ impl Display for Url {
 
 
 /// Enumerate the supported HTTP methods.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Method {
     Get,
     Post,
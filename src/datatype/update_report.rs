@@ -1,4 +1,6 @@
-use rustc_serialize::{Encodable, Encoder};
+use rustc_serialize::{Decodable, Decoder, Encodable, Encoder};
+use rustc_serialize::json;
+use rustc_serialize::json::Json;
 use std::str::FromStr;
 
 use datatype::{Error, UpdateRequestId};
@@ -52,7 +54,7 @@ impl<'d, 'r> DeviceReport<'d, 'r> {
 
 /// Enumerate the possible outcomes when trying to install a package.
 #[allow(non_camel_case_types)]
-#[derive(RustcDecodable, Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum UpdateResultCode {
     /// Operation executed successfully
     OK = 0,
@@ -132,6 +134,50 @@ impl Encodable for UpdateResultCode {
     }
 }
 
+impl Decodable for UpdateResultCode {
+    fn decode<D: Decoder>(d: &mut D) -> Result<UpdateResultCode, D::Error> {
+        d.read_enum("UpdateResultCode", |d| {
+            d.read_enum_variant(RESULT_CODE_NAMES, |d, idx| {
+                RESULT_CODE_NAMES[idx].parse().map_err(|err: Error| d.error(&format!("{}", err)))
+            })
+        })
+    }
+}
+
+/// The `UpdateResultCode` variant names, indexed by numeric code.
+const RESULT_CODE_NAMES: &'static [&'static str] = &[
+    "OK", "ALREADY_PROCESSED", "DEPENDENCY_FAILURE", "VALIDATION_FAILED", "INSTALL_FAILED",
+    "UPGRADE_FAILED", "REMOVAL_FAILED", "FLASH_FAILED", "CREATE_PARTITION_FAILED",
+    "DELETE_PARTITION_FAILED", "RESIZE_PARTITION_FAILED", "WRITE_PARTITION_FAILED",
+    "PATCH_PARTITION_FAILED", "USER_DECLINED", "SOFTWARE_BLACKLISTED", "DISK_FULL", "NOT_FOUND",
+    "OLD_VERSION", "INTERNAL_ERROR", "GENERAL_ERROR",
+];
+
+/// Decode JSON text where each `result_code` may be either the numeric code
+/// or the variant name.
+pub fn decode_json<D: Decodable>(text: &str) -> Result<D, json::DecoderError> {
+    let data = try!(Json::from_str(text).map_err(json::DecoderError::ParseError));
+    D::decode(&mut json::Decoder::new(name_result_codes(data)))
+}
+
+/// Replace each numeric `result_code` with the variant name so that both
+/// forms decode as an `UpdateResultCode`.
+pub fn name_result_codes(data: Json) -> Json {
+    match data {
+        Json::Object(obj) => Json::Object(obj.into_iter().map(|(key, value)| {
+            let value = match (key.as_str(), value) {
+                ("result_code", Json::U64(code)) if (code as usize) < RESULT_CODE_NAMES.len() => {
+                    Json::String(RESULT_CODE_NAMES[code as usize].to_string())
+                }
+                (_, value) => name_result_codes(value)
+            };
+            (key, value)
+        }).collect()),
+        Json::Array(list) => Json::Array(list.into_iter().map(name_result_codes).collect()),
+        data => data
+    }
+}
+
 
 /// An encodable response of the installation outcome for a particular update ID.
 #[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq, Eq)]
@@ -180,3 +226,40 @@ impl Default for InstalledSoftware {
         InstalledSoftware { packages: Vec::new(), firmwares: Vec::new() }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json;
+
+    use super::*;
+
+
+    #[test]
+    fn decode_named_result_codes() {
+        let result: OperationResult = json::decode(r#"{ "id": "1", "result_code": "DISK_FULL", "result_text": "" }"#).unwrap();
+        assert_eq!(result.result_code, UpdateResultCode::DISK_FULL);
+        let result: OperationResult = decode_json(r#"{ "id": "1", "result_code": "OK", "result_text": "" }"#).unwrap();
+        assert_eq!(result.result_code, UpdateResultCode::OK);
+        assert!(decode_json::<OperationResult>(r#"{ "id": "1", "result_code": "NOPE", "result_text": "" }"#).is_err());
+    }
+
+    #[test]
+    fn decode_numeric_result_codes() {
+        let report: UpdateReport = decode_json(r#"{ "update_id": "1", "operation_results": [
+            { "id": "1", "result_code": 15, "result_text": "" },
+            { "id": "2", "result_code": 0, "result_text": "" }
+        ] }"#).unwrap();
+        let codes = report.operation_results.into_iter().map(|result| result.result_code).collect::<Vec<_>>();
+        assert_eq!(codes, vec![UpdateResultCode::DISK_FULL, UpdateResultCode::OK]);
+        assert!(decode_json::<OperationResult>(r#"{ "id": "1", "result_code": 20, "result_text": "" }"#).is_err());
+    }
+
+    #[test]
+    fn encode_result_codes() {
+        let result = OperationResult { id: "1".to_string(), result_code: UpdateResultCode::DISK_FULL, result_text: "".to_string() };
+        let text   = json::encode(&result).unwrap();
+        assert!(text.contains(r#""result_code":15"#));
+        assert_eq!(decode_json::<OperationResult>(&text).unwrap(), result);
+    }
+}
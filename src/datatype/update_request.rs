@@ -66,6 +66,14 @@ pub struct ChunkReceived {
     pub device:    String,
     pub update_id: UpdateRequestId,
     pub chunks:    Vec<u64>,
+    pub missing:   Vec<ChunkRange>,
+}
+
+/// An inclusive range of chunk indices that have not yet been received.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone)]
+pub struct ChunkRange {
+    pub start: u64,
+    pub end:   u64,
 }
 
 /// A notification to an external package manager that the package was downloaded.
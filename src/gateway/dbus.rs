@@ -1,101 +1,404 @@
-use chan::Sender;
+use chan;
+use chan::{Receiver, Sender};
 use dbus::{Connection, BusType, ConnectionItem, FromMessageItem,
            Message, MessageItem, NameFlag};
-use dbus::obj::{Argument, Interface, Method, MethodResult, ObjectPath};
+use dbus::obj::{Argument, Interface, Method, MethodResult, ObjectPath, Signal};
+use rustc_serialize::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::convert::From;
+use time;
 
-use datatype::{Command, DBusConfig, Event, InstalledFirmware, InstalledPackage,
-               InstalledSoftware, OperationResult, UpdateReport};
+use datatype::{Command, DBusBus, DBusConfig, Event, InstalledFirmware, InstalledPackage,
+               InstalledSoftware, OperationResult, UpdateReport, UpdateRequestId};
 use datatype::dbus;
 use super::{Gateway, Interpret};
 
 
-/// The `DBus` gateway is used with the RVI module for communicating with the
-/// system session bus.
+/// The `DBus` gateway is used with the RVI module for communicating over the
+/// session bus, the system bus, or a bus at an explicit address. Local
+/// applications may also query the client `status` and listen for signals on
+/// the progress of each update.
+///
+/// A single connection is shared by all calls, which are handled on a
+/// dedicated thread. Calls to the software manager don't block, and are retried
+/// up to `dbus_cfg.retries` times before an `Event::Error` is sent to `etx`.
+/// Method calls that wait on the `GlobalInterpreter` are answered once its
+/// outcome arrives, so the thread keeps reading replies in the meantime.
 pub struct DBus {
-    pub dbus_cfg:  DBusConfig,
-    pub itx:       Sender<Interpret>,
+    pub dbus_cfg: DBusConfig,
+    pub itx:      Sender<Interpret>,
+    pub etx:      Sender<Event>,
+    pub status:   Arc<Mutex<ClientStatus>>,
+    pub pulse_tx: Option<Sender<Event>>,
 }
 
 impl Gateway for DBus {
     fn initialize(&mut self, itx: Sender<Interpret>) -> Result<(), String> {
+        let (pulse_tx, pulse_rx) = chan::async::<Event>();
+        let (ready_tx, ready_rx) = chan::sync::<Result<(), String>>(1);
         let dbus_cfg = self.dbus_cfg.clone();
+        let status   = self.status.clone();
+        let etx      = self.etx.clone();
+        self.pulse_tx = Some(pulse_tx);
 
         thread::spawn(move || {
-            let conn = Connection::get_private(BusType::Session).expect("couldn't get dbus session");
-            conn.register_name(&dbus_cfg.name, NameFlag::ReplaceExisting as u32).expect("couldn't register name");
+            let conn = match connect(&dbus_cfg.bus) {
+                Ok(conn) => conn,
+                Err(err) => return ready_tx.send(Err(err))
+            };
+            if let Err(err) = conn.register_name(&dbus_cfg.name, NameFlag::ReplaceExisting as u32) {
+                return ready_tx.send(Err(format!("couldn't register name: {:?}", err)));
+            }
 
             let mut obj_path = ObjectPath::new(&conn, &dbus_cfg.path, true);
-            obj_path.insert_interface(&dbus_cfg.interface, default_interface(itx));
-            obj_path.set_registered(true).expect("couldn't set registration status");
+            obj_path.insert_interface(&dbus_cfg.interface, default_interface(itx.clone(), status));
+            if let Err(err) = obj_path.set_registered(true) {
+                return ready_tx.send(Err(format!("couldn't set registration status: {:?}", err)));
+            }
+            ready_tx.send(Ok(()));
 
+            let mut deferred = DeferredCalls::new(&conn, &dbus_cfg, itx.clone());
+            let mut swm      = SoftwareManager::new(&conn, dbus_cfg, itx, etx);
             loop {
-                for item in conn.iter(1000) {
-                    if let ConnectionItem::MethodCall(mut msg) = item {
-                        match obj_path.handle_message(&mut msg) {
-                            Some(Ok(()))  => info!("DBus message sent: {:?}", msg),
-                            Some(Err(())) => error!("DBus message send failed: {:?}", msg),
-                            None          => debug!("unhandled dbus message: {:?}", msg)
-                        }
+                for item in conn.iter(100) {
+                    match item {
+                        ConnectionItem::MethodCall(msg) => if let Some(mut msg) = deferred.take(msg) {
+                            match obj_path.handle_message(&mut msg) {
+                                Some(Ok(()))  => info!("DBus message sent: {:?}", msg),
+                                Some(Err(())) => error!("DBus message send failed: {:?}", msg),
+                                None          => debug!("unhandled dbus message: {:?}", msg)
+                            }
+                        },
+                        // error replies are also received as a `MethodReturn`
+                        ConnectionItem::MethodReturn(msg) => swm.reply(msg),
+                        // only expire calls once every queued reply has been read
+                        ConnectionItem::Nothing => swm.expire(now_ms()),
+                        _ => ()
                     }
+                    deferred.drain();
+                    swm.drain(&pulse_rx);
                 }
             }
         });
 
-        Ok(info!("DBus gateway started."))
+        try!(ready_rx.recv().unwrap_or(Err("dbus thread exited".to_string())));
+        Ok(info!("DBus gateway started on the {} bus.", self.dbus_cfg.bus))
     }
 
     fn pulse(&self, event: Event) {
+        self.status.lock().unwrap().update(&event);
+        self.pulse_tx.as_ref().map(|tx| tx.send(event));
+    }
+}
+
+
+/// Open a private connection to the configured message bus.
+pub fn connect(bus: &DBusBus) -> Result<Connection, String> {
+    let conn = match *bus {
+        DBusBus::Session => Connection::get_private(BusType::Session),
+        DBusBus::System  => Connection::get_private(BusType::System),
+        DBusBus::Address(ref addr) => {
+            Connection::open_private(addr).and_then(|conn| conn.register().map(|_| conn))
+        }
+    };
+    conn.map_err(|err| format!("couldn't connect to the {} bus: {:?}", bus, err))
+}
+
+
+// An outstanding method call to the software manager, which expires after
+// `dbus.timeout` milliseconds.
+struct PendingCall {
+    method:   &'static str,
+    args:     Vec<MessageItem>,
+    attempts: u32,
+    expires:  i64,
+}
+
+// The current time in milliseconds.
+fn now_ms() -> i64 {
+    (time::precise_time_ns() / 1_000_000) as i64
+}
+
+// Sends signals and software manager calls over a shared connection, tracking
+// each call until a reply is received or the call times out.
+struct SoftwareManager<'c> {
+    conn:    &'c Connection,
+    cfg:     DBusConfig,
+    itx:     Sender<Interpret>,
+    etx:     Sender<Event>,
+    pending: HashMap<u32, PendingCall>,
+}
+
+impl<'c> SoftwareManager<'c> {
+    fn new(conn: &'c Connection, cfg: DBusConfig, itx: Sender<Interpret>, etx: Sender<Event>) -> Self {
+        SoftwareManager { conn: conn, cfg: cfg, itx: itx, etx: etx, pending: HashMap::new() }
+    }
+
+    // Handle any events received by `pulse` since the last call.
+    fn drain(&mut self, pulse_rx: &Receiver<Event>) {
+        loop {
+            chan_select! {
+                default => { return },
+                pulse_rx.recv() -> event => {
+                    match event {
+                        Some(event) => self.notify(event),
+                        None        => return
+                    }
+                },
+            }
+        }
+    }
+
+    fn notify(&mut self, event: Event) {
+        if let Some((name, args)) = signal_for(&event) {
+            let mut msg = Message::new_signal(&self.cfg.path, &self.cfg.interface, name)
+                .expect("couldn't create dbus signal");
+            msg.append_items(&args);
+            let _ = self.conn.send(msg).map_err(|_| error!("couldn't send {} signal", name));
+        }
+
         match event {
-            Event::UpdateAvailable(avail) => {
-                let msg = self.new_swm_message("updateAvailable", &[
-                    MessageItem::from(avail.update_id),
-                    MessageItem::from(avail.signature),
-                    MessageItem::from(avail.description),
-                    MessageItem::from(avail.request_confirmation)
-                ]);
-                let conn = Connection::get_private(BusType::Session).expect("couldn't get dbus session");
-                let _    = conn.send(msg).map_err(|_| error!("couldn't send updateAvailable message"));
+            Event::UpdateAvailable(avail) => self.call("updateAvailable", vec![
+                MessageItem::from(avail.update_id),
+                MessageItem::from(avail.signature),
+                MessageItem::from(avail.description),
+                MessageItem::from(avail.request_confirmation)
+            ], 1),
+
+            Event::DownloadComplete(comp) => self.call("downloadComplete", vec![
+                MessageItem::from(comp.update_image),
+                MessageItem::from(comp.signature)
+            ], 1),
+
+            Event::InstalledSoftwareNeeded => self.call("getInstalledPackages", vec![
+                MessageItem::from(true), // include packages?
+                MessageItem::from(false) // include firmware?
+            ], 1),
+
+            _ => ()
+        }
+    }
+
+    fn call(&mut self, method: &'static str, args: Vec<MessageItem>, attempts: u32) {
+        let mgr     = self.cfg.software_manager.clone();
+        let path    = self.cfg.software_manager_path.clone();
+        let mut msg = Message::new_method_call(&mgr, &path, &mgr, method).expect("couldn't create dbus message");
+        msg.append_items(&args);
+
+        let call = PendingCall {
+            method:   method,
+            args:     args,
+            attempts: attempts,
+            expires:  now_ms() + self.cfg.timeout as i64
+        };
+        match self.conn.send(msg) {
+            Ok(serial) => { self.pending.insert(serial, call); }
+            Err(_)     => self.retry(call, "couldn't send message")
+        }
+    }
+
+    fn retry(&mut self, call: PendingCall, reason: &str) {
+        if call.attempts <= self.cfg.retries {
+            debug!("retrying dbus {} call: {}", call.method, reason);
+            self.call(call.method, call.args, call.attempts + 1);
+        } else {
+            let err = format!("dbus {} call failed after {} attempts: {}", call.method, call.attempts, reason);
+            error!("{}", err);
+            self.etx.send(Event::Error(err));
+        }
+    }
+
+    fn reply(&mut self, mut msg: Message) {
+        let call = match msg.get_reply_serial().and_then(|serial| self.pending.remove(&serial)) {
+            Some(call) => call,
+            None       => return debug!("unexpected dbus reply: {:?}", msg)
+        };
+
+        if let Err(err) = msg.as_result() {
+            let reason = err.message().or(err.name()).unwrap_or("unknown error").to_string();
+            let err    = format!("dbus {} call failed: {}", call.method, reason);
+            error!("{}", err);
+            return self.etx.send(Event::Error(err));
+        }
+
+        if call.method == "getInstalledPackages" {
+            match installed_software(&msg) {
+                Ok(inst) => send(&self.itx, Command::SendInstalledSoftware(inst)),
+                Err(_)   => self.etx.send(Event::Error("malformed getInstalledPackages reply".to_string()))
+            }
+        }
+    }
+
+    // Retry any calls that haven't received a reply before their deadline.
+    fn expire(&mut self, now: i64) {
+        let expired = self.pending.iter()
+            .filter(|&(_, call)| call.expires < now)
+            .map(|(serial, _)| *serial)
+            .collect::<Vec<u32>>();
+        for serial in expired {
+            let call = self.pending.remove(&serial).expect("expired call");
+            self.retry(call, "timed out");
+        }
+    }
+}
+
+
+/// The methods answered with the outcome of a `Command` sent to the
+/// `GlobalInterpreter`.
+const DEFERRED_METHODS: &'static [&'static str] = &["getPendingUpdates", "getInstalledPackages", "abortUpdate", "getHistory"];
+
+// Method calls waiting on the `GlobalInterpreter`, each of which is sent from a
+// separate thread so that a long command doesn't block the connection thread.
+struct DeferredCalls<'c> {
+    conn:      &'c Connection,
+    path:      String,
+    interface: String,
+    itx:       Sender<Interpret>,
+    next_id:   u64,
+    calls:     HashMap<u64, (&'static str, Message)>,
+    done_tx:   Sender<(u64, Event)>,
+    done_rx:   Receiver<(u64, Event)>,
+}
+
+impl<'c> DeferredCalls<'c> {
+    fn new(conn: &'c Connection, cfg: &DBusConfig, itx: Sender<Interpret>) -> Self {
+        let (done_tx, done_rx) = chan::async::<(u64, Event)>();
+        DeferredCalls {
+            conn:      conn,
+            path:      cfg.path.clone(),
+            interface: cfg.interface.clone(),
+            itx:       itx,
+            next_id:   0,
+            calls:     HashMap::new(),
+            done_tx:   done_tx,
+            done_rx:   done_rx,
+        }
+    }
+
+    // Take a method call that waits on the `GlobalInterpreter`, returning any
+    // other message for the `ObjectPath` to handle.
+    fn take(&mut self, mut msg: Message) -> Option<Message> {
+        let method = {
+            let (_, path, interface, member) = msg.headers();
+            if path.as_ref() != Some(&self.path) || interface.map_or(false, |iface| iface != self.interface) {
+                return Some(msg)
+            }
+            match member.and_then(|member| DEFERRED_METHODS.iter().find(|method| **method == member)) {
+                Some(method) => *method,
+                None         => return Some(msg)
+            }
+        };
+
+        match deferred_command(method, &mut msg) {
+            Ok(cmd) => {
+                let id      = self.next_id;
+                let itx     = self.itx.clone();
+                let done_tx = self.done_tx.clone();
+                self.next_id += 1;
+                self.calls.insert(id, (method, msg));
+                thread::spawn(move || done_tx.send((id, request(&itx, cmd))));
+            }
+            Err(err) => self.respond(&msg, Err(err))
+        }
+        None
+    }
+
+    // Answer each call whose outcome has arrived since the last call.
+    fn drain(&mut self) {
+        let done_rx = self.done_rx.clone();
+        loop {
+            chan_select! {
+                default => { return },
+                done_rx.recv() -> done => {
+                    let (id, event) = match done {
+                        Some(done) => done,
+                        None       => return
+                    };
+                    if let Some((method, msg)) = self.calls.remove(&id) {
+                        self.respond(&msg, deferred_reply(method, event));
+                    }
+                },
+            }
+        }
+    }
+
+    fn respond(&self, call: &Message, result: MethodResult) {
+        let reply = match result {
+            Ok(items) => Message::new_method_return(call).map(|mut reply| {
+                reply.append_items(&items);
+                reply
+            }),
+            Err((name, text)) => Message::new_error(call, name, &text)
+        };
+        match reply {
+            Some(reply) => { let _ = self.conn.send(reply).map_err(|_| error!("couldn't send dbus reply: {:?}", call)); }
+            None        => error!("couldn't create dbus reply: {:?}", call)
+        }
+    }
+}
+
+fn installed_software(reply: &Message) -> Result<InstalledSoftware, ()> {
+    let mut args = reply.get_items().into_iter();
+
+    let pkg_arg  = try!(args.next().ok_or(()));
+    let msgs: &Vec<MessageItem> = try!(FromMessageItem::from(&pkg_arg));
+    let packages = try!(msgs.into_iter()
+                        .map(|item| -> Result<InstalledPackage, ()> {
+                            FromMessageItem::from(item)
+                        }).collect::<Result<Vec<InstalledPackage>, ()>>());
+
+    let firm_arg = try!(args.next().ok_or(()));
+    let msgs: &Vec<MessageItem> = try!(FromMessageItem::from(&firm_arg));
+    let firmwares = try!(msgs.into_iter()
+                         .map(|item| -> Result<InstalledFirmware, ()> {
+                             FromMessageItem::from(item)
+                         }).collect::<Result<Vec<InstalledFirmware>, ()>>());
+
+    Ok(InstalledSoftware::new(packages, firmwares))
+}
+
+
+/// The state of the client as observed from the system-wide `Event`s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientStatus {
+    pub auth:      AuthState,
+    pub operation: Operation,
+    pub update_id: Option<UpdateRequestId>,
+}
+
+impl Default for ClientStatus {
+    fn default() -> Self {
+        ClientStatus { auth: AuthState::Unknown, operation: Operation::Idle, update_id: None }
+    }
+}
+
+impl ClientStatus {
+    /// Update the current status from the next `Event`.
+    pub fn update(&mut self, event: &Event) {
+        match *event {
+            Event::Authenticated | Event::AlreadyAuthenticated => self.auth = AuthState::Authenticated,
+            Event::NotAuthenticated                            => self.auth = AuthState::NotAuthenticated,
+
+            Event::DownloadingUpdate(ref id) | Event::DownloadProgress(ref id, _) => {
+                self.operation = Operation::Downloading;
+                self.update_id = Some(id.clone());
             }
 
-            Event::DownloadComplete(comp) => {
-                let msg = self.new_swm_message("downloadComplete", &[
-                    MessageItem::from(comp.update_image),
-                    MessageItem::from(comp.signature)
-                ]);
-                let conn = Connection::get_private(BusType::Session).expect("couldn't get dbus session");
-                let _    = conn.send(msg).map_err(|_| error!("couldn't send downloadComplete message"));
+            Event::InstallingUpdate(ref id) => {
+                self.operation = Operation::Installing;
+                self.update_id = Some(id.clone());
             }
 
-            Event::InstalledSoftwareNeeded => {
-                let msg = self.new_swm_message("getInstalledPackages", &[
-                    MessageItem::from(true), // include packages?
-                    MessageItem::from(false) // include firmware?
-                ]);
-                let conn  = Connection::get_private(BusType::Session).expect("couldn't get dbus session");
-                let reply = conn.send_with_reply_and_block(msg, self.dbus_cfg.timeout).unwrap();
-
-                let _ = || -> Result<InstalledSoftware, ()> {
-                    let mut args = reply.get_items().into_iter();
-
-                    let pkg_arg  = try!(args.next().ok_or(()));
-                    let msgs: &Vec<MessageItem> = try!(FromMessageItem::from(&pkg_arg));
-                    let packages = try!(msgs.into_iter()
-                                        .map(|item| -> Result<InstalledPackage, ()> {
-                                            FromMessageItem::from(item)
-                                        }).collect::<Result<Vec<InstalledPackage>, ()>>());
-
-                    let firm_arg = try!(args.next().ok_or(()));
-                    let msgs: &Vec<MessageItem> = try!(FromMessageItem::from(&firm_arg));
-                    let firmwares = try!(msgs.into_iter()
-                                         .map(|item| -> Result<InstalledFirmware, ()> {
-                                             FromMessageItem::from(item)
-                                         }).collect::<Result<Vec<InstalledFirmware>, ()>>());
-
-                    Ok(InstalledSoftware::new(packages, firmwares))
-                }().map(|inst| send(&self.itx, Command::SendInstalledSoftware(inst)))
-                   .map_err(|_| error!("unable to ReportInstalledSoftware"));
+            Event::DownloadComplete(_)    |
+            Event::DownloadFailed(_, _)   |
+            Event::DiskFull(_, _)         |
+            Event::InstallComplete(_)     |
+            Event::InstallFailed(_)       |
+            Event::UpdateAborted(_)       => {
+                self.operation = Operation::Idle;
+                self.update_id = None;
             }
 
             _ => ()
@@ -103,18 +406,93 @@ impl Gateway for DBus {
     }
 }
 
-impl DBus {
-    fn new_swm_message(&self, method: &str, args: &[MessageItem]) -> Message {
-        let mgr     = self.dbus_cfg.software_manager.clone();
-        let path    = self.dbus_cfg.software_manager_path.clone();
-        let result  = Message::new_method_call(&mgr, &path, &mgr, method);
-        let mut msg = result.expect("couldn't create dbus message");
-        msg.append_items(args);
-        msg
+/// Whether the client is authenticated with the Core server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthState {
+    Unknown,
+    Authenticated,
+    NotAuthenticated,
+}
+
+impl AuthState {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            AuthState::Unknown          => "unknown",
+            AuthState::Authenticated    => "authenticated",
+            AuthState::NotAuthenticated => "unauthenticated",
+        }
+    }
+}
+
+/// The update operation currently in progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Idle,
+    Downloading,
+    Installing,
+}
+
+impl Operation {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Operation::Idle        => "idle",
+            Operation::Downloading => "downloading",
+            Operation::Installing  => "installing",
+        }
+    }
+}
+
+
+// Map an `Event` to the name and arguments of a DBus signal to emit.
+fn signal_for(event: &Event) -> Option<(&'static str, Vec<MessageItem>)> {
+    match *event {
+        Event::Authenticated | Event::AlreadyAuthenticated => {
+            Some(("authStateChanged", vec![MessageItem::from(AuthState::Authenticated.as_str())]))
+        }
+        Event::NotAuthenticated => {
+            Some(("authStateChanged", vec![MessageItem::from(AuthState::NotAuthenticated.as_str())]))
+        }
+
+        Event::DownloadingUpdate(ref id) => {
+            Some(("downloadStarted", vec![MessageItem::from(id.clone())]))
+        }
+        Event::DownloadProgress(ref id, chunks) => {
+            Some(("downloadProgress", vec![MessageItem::from(id.clone()), MessageItem::from(chunks)]))
+        }
+        Event::DownloadComplete(ref dl) => {
+            Some(("downloadFinished", vec![
+                MessageItem::from(dl.update_id.clone()),
+                MessageItem::from(true),
+                MessageItem::from("")
+            ]))
+        }
+        Event::DownloadFailed(ref id, ref reason) | Event::DiskFull(ref id, ref reason) => {
+            Some(("downloadFinished", vec![
+                MessageItem::from(id.clone()),
+                MessageItem::from(false),
+                MessageItem::from(reason.clone())
+            ]))
+        }
+
+        Event::InstallingUpdate(ref id) => {
+            Some(("installStarted", vec![MessageItem::from(id.clone())]))
+        }
+        Event::InstallComplete(ref report) => {
+            Some(("installCompleted", vec![MessageItem::from(report.update_id.clone()), MessageItem::from(true)]))
+        }
+        Event::InstallFailed(ref report) => {
+            Some(("installCompleted", vec![MessageItem::from(report.update_id.clone()), MessageItem::from(false)]))
+        }
+
+        Event::UpdateAborted(ref id) => {
+            Some(("updateAborted", vec![MessageItem::from(id.clone())]))
+        }
+
+        _ => None
     }
 }
 
-fn default_interface<'i>(itx: Sender<Interpret>) -> Interface<'i> {
+fn default_interface<'i>(itx: Sender<Interpret>, status: Arc<Mutex<ClientStatus>>) -> Interface<'i> {
     let initiate_itx      = itx.clone();
     let initiate_download = Method::new(
         "initiateDownload",
@@ -131,11 +509,66 @@ fn default_interface<'i>(itx: Sender<Interpret>) -> Interface<'i> {
         Box::new(move |msg| handle_update_report(&update_itx, msg))
     );
 
-    Interface::new(vec![initiate_download, update_report], vec![], vec![])
+    let get_status = Method::new(
+        "getStatus",
+        vec![],
+        vec![Argument::new("auth_state", "s"), Argument::new("operation", "s"), Argument::new("update_id", "s")],
+        Box::new(move |msg| handle_get_status(&status, msg))
+    );
+
+    // the deferred methods are listed for introspection but answered by `DeferredCalls`
+    let get_pending_updates = Method::new(
+        "getPendingUpdates",
+        vec![],
+        vec![Argument::new("update_ids", "as")],
+        Box::new(handle_deferred)
+    );
+
+    let get_installed_packages = Method::new(
+        "getInstalledPackages",
+        vec![],
+        vec![Argument::new("packages", "a(ss)")],
+        Box::new(handle_deferred)
+    );
+
+    let abort_update = Method::new(
+        "abortUpdate",
+        vec![Argument::new("update_id", "s")],
+        vec![],
+        Box::new(handle_deferred)
+    );
+
+    let get_history = Method::new(
+        "getHistory",
+        vec![Argument::new("since", "s"), Argument::new("limit", "t"), Argument::new("filter", "s")],
+        vec![Argument::new("entries", "as")],
+        Box::new(handle_deferred)
+    );
+
+    let signals = vec![
+        Signal::new("authStateChanged", vec![Argument::new("auth_state", "s")]),
+        Signal::new("downloadStarted", vec![Argument::new("update_id", "s")]),
+        Signal::new("downloadProgress", vec![Argument::new("update_id", "s"), Argument::new("chunks", "t")]),
+        Signal::new("downloadFinished", vec![Argument::new("update_id", "s"), Argument::new("success", "b"),
+                                             Argument::new("reason", "s")]),
+        Signal::new("installStarted", vec![Argument::new("update_id", "s")]),
+        Signal::new("installCompleted", vec![Argument::new("update_id", "s"), Argument::new("success", "b")]),
+        Signal::new("updateAborted", vec![Argument::new("update_id", "s")]),
+    ];
+
+    Interface::new(vec![initiate_download, update_report, get_status, get_pending_updates,
+                        get_installed_packages, abort_update, get_history], vec![], signals)
 }
 
 fn send(itx: &Sender<Interpret>, cmd: Command) {
-    itx.send(Interpret { command: cmd, response_tx: None });
+    itx.send(Interpret::new(cmd, None, "dbus"));
+}
+
+// Send a `Command` and wait for the outcome `Event`.
+fn request(itx: &Sender<Interpret>, cmd: Command) -> Event {
+    let (etx, erx) = chan::async::<Event>();
+    itx.send(Interpret::new(cmd, Some(Arc::new(Mutex::new(etx))), "dbus"));
+    erx.recv().unwrap_or(Event::Error("internal receiver error".to_string()))
 }
 
 fn handle_initiate_download(itx: &Sender<Interpret>, msg: &mut Message) -> MethodResult {
@@ -169,3 +602,272 @@ fn handle_update_report(itx: &Sender<Interpret>, msg: &mut Message) -> MethodRes
 
     Ok(vec![])
 }
+
+fn handle_get_status(status: &Mutex<ClientStatus>, msg: &mut Message) -> MethodResult {
+    debug!("dbus handle_get_status: msg={:?}", msg);
+    let status = status.lock().unwrap();
+    Ok(vec![
+        MessageItem::from(status.auth.as_str()),
+        MessageItem::from(status.operation.as_str()),
+        MessageItem::from(status.update_id.clone().unwrap_or("".to_string()))
+    ])
+}
+
+fn handle_deferred(msg: &mut Message) -> MethodResult {
+    Err(dbus::failed(format!("unexpected deferred method call: {:?}", msg)))
+}
+
+// Parse the `Command` to send for a deferred method call.
+fn deferred_command(method: &str, msg: &mut Message) -> Result<Command, (&'static str, String)> {
+    debug!("dbus {}: msg={:?}", method, msg);
+    match method {
+        "getPendingUpdates"    => Ok(Command::GetUpdateRequests),
+        "getInstalledPackages" => Ok(Command::ListInstalledPackages),
+        "abortUpdate"          => abort_update_command(msg),
+        "getHistory"           => get_history_command(msg),
+        _                      => Err(dbus::failed(format!("unknown deferred method: {}", method)))
+    }
+}
+
+// Convert the outcome `Event` of a deferred method call into its reply.
+fn deferred_reply(method: &str, event: Event) -> MethodResult {
+    match method {
+        "getPendingUpdates"    => get_pending_updates_reply(event),
+        "getInstalledPackages" => get_installed_packages_reply(event),
+        "abortUpdate"          => abort_update_reply(event),
+        "getHistory"           => get_history_reply(event),
+        _                      => Err(dbus::failed(format!("unknown deferred method: {}", method)))
+    }
+}
+
+fn get_pending_updates_reply(event: Event) -> MethodResult {
+    let ids = match event {
+        Event::UpdatesReceived(requests) => requests.into_iter().map(|req| req.requestId).collect(),
+        Event::NoUpdateRequests          => Vec::new(),
+        event                            => return Err(dbus::failed(format!("{}", event)))
+    };
+    Ok(vec![MessageItem::from(&ids[..])])
+}
+
+fn get_installed_packages_reply(event: Event) -> MethodResult {
+    let packages = match event {
+        Event::FoundInstalledPackages(packages) => packages,
+        event                                   => return Err(dbus::failed(format!("{}", event)))
+    };
+
+    let items = packages.into_iter().map(|pkg| {
+        MessageItem::Struct(vec![MessageItem::from(pkg.name), MessageItem::from(pkg.version)])
+    }).collect::<Vec<MessageItem>>();
+    if items.is_empty() {
+        Ok(vec![MessageItem::Array(items, "a(ss)".into())])
+    } else {
+        Ok(vec![try!(MessageItem::new_array(items).map_err(|err| dbus::failed(format!("{:?}", err))))])
+    }
+}
+
+fn abort_update_command(msg: &mut Message) -> Result<Command, (&'static str, String)> {
+    let sender = try!(msg.sender().map(|s| s.to_string()).ok_or(dbus::missing_arg()));
+    debug!("dbus abort_update_command: sender={:?}, msg={:?}", sender, msg);
+
+    let mut args = msg.get_items().into_iter();
+    let arg_id   = try!(args.next().ok_or(dbus::missing_arg()));
+    let update_id: &String = try!(FromMessageItem::from(&arg_id).or(Err(dbus::malformed_arg())));
+    Ok(Command::AbortUpdate(update_id.clone()))
+}
+
+fn abort_update_reply(event: Event) -> MethodResult {
+    match event {
+        Event::UpdateAborted(_) => Ok(vec![]),
+        event                   => Err(dbus::failed(format!("{}", event)))
+    }
+}
+
+// Empty strings and a zero limit are treated as unset arguments.
+fn get_history_command(msg: &mut Message) -> Result<Command, (&'static str, String)> {
+    let mut args = msg.get_items().into_iter();
+    let since_arg = try!(args.next().ok_or(dbus::missing_arg()));
+    let since: &String = try!(FromMessageItem::from(&since_arg).or(Err(dbus::malformed_arg())));
+    let limit_arg = try!(args.next().ok_or(dbus::missing_arg()));
+    let limit: u64 = try!(FromMessageItem::from(&limit_arg).or(Err(dbus::malformed_arg())));
+    let filter_arg = try!(args.next().ok_or(dbus::missing_arg()));
+    let filter: &String = try!(FromMessageItem::from(&filter_arg).or(Err(dbus::malformed_arg())));
+
+    Ok(Command::GetHistory {
+        since:  if since.is_empty() { None } else { Some(since.clone()) },
+        limit:  if limit == 0 { None } else { Some(limit) },
+        filter: if filter.is_empty() { None } else { Some(filter.clone()) },
+    })
+}
+
+fn get_history_reply(event: Event) -> MethodResult {
+    let entries = match event {
+        Event::History(entries) => entries,
+        event                   => return Err(dbus::failed(format!("{}", event)))
+    };
+
+    let lines = try!(entries.iter()
+                     .map(|entry| json::encode(entry))
+                     .collect::<Result<Vec<String>, _>>()
+                     .map_err(|err| dbus::failed(format!("{}", err))));
+    Ok(vec![MessageItem::from(&lines[..])])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chan;
+    use dbus::{Message, MessageItem};
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command as Process, Stdio};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use datatype::{Command, DBusBus, DBusConfig, Event, Package, UpdateReport, UpdateResultCode};
+    use gateway::{Gateway, Interpret};
+    use health::Heartbeat;
+    use super::*;
+
+
+    #[test]
+    fn status_from_events() {
+        let mut status = ClientStatus::default();
+        status.update(&Event::Authenticated);
+        status.update(&Event::DownloadingUpdate("1".to_string()));
+        assert_eq!(status, ClientStatus {
+            auth:      AuthState::Authenticated,
+            operation: Operation::Downloading,
+            update_id: Some("1".to_string())
+        });
+
+        status.update(&Event::InstallingUpdate("1".to_string()));
+        assert_eq!(status.operation, Operation::Installing);
+        status.update(&Event::InstallComplete(UpdateReport::single("1".to_string(), UpdateResultCode::OK, "".to_string())));
+        status.update(&Event::NotAuthenticated);
+        assert_eq!(status, ClientStatus {
+            auth:      AuthState::NotAuthenticated,
+            operation: Operation::Idle,
+            update_id: None
+        });
+    }
+
+    #[test]
+    fn signals_from_events() {
+        let (name, args) = signal_for(&Event::DownloadProgress("1".to_string(), 3)).unwrap();
+        assert_eq!(name, "downloadProgress");
+        assert_eq!(args, vec![MessageItem::from("1"), MessageItem::from(3u64)]);
+
+        let (name, args) = signal_for(&Event::DownloadFailed("1".to_string(), "bad".to_string())).unwrap();
+        assert_eq!(name, "downloadFinished");
+        assert_eq!(args, vec![MessageItem::from("1"), MessageItem::from(false), MessageItem::from("bad")]);
+
+        assert!(signal_for(&Event::InstalledPackagesSent).is_none());
+    }
+
+    #[test]
+    fn deferred_replies() {
+        assert_eq!(deferred_reply("getPendingUpdates", Event::NoUpdateRequests).unwrap(),
+                   vec![MessageItem::Array(vec![], "as".into())]);
+        assert_eq!(deferred_reply("abortUpdate", Event::UpdateAborted("1".to_string())).unwrap(), vec![]);
+        assert!(deferred_reply("abortUpdate", Event::Error("no transfer".to_string())).is_err());
+        assert!(deferred_reply("getStatus", Event::NoUpdateRequests).is_err());
+    }
+
+    // Kills the private bus when the test finishes, even on a failed assertion.
+    struct Daemon(Child);
+
+    impl Drop for Daemon {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[test]
+    fn dbus_gateway_on_private_bus() {
+        let spawned = Process::new("dbus-daemon")
+            .args(&["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut daemon = match spawned {
+            Ok(child) => Daemon(child),
+            Err(err)  => return println!("skipping dbus_gateway_on_private_bus: couldn't start dbus-daemon: {}", err)
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.0.stdout.as_mut().unwrap()).read_line(&mut address).expect("couldn't read dbus address");
+        let bus = DBusBus::Address(address.trim().to_string());
+
+        let (etx, erx) = chan::sync::<Event>(0);
+        let (itx, irx) = chan::async::<Interpret>();
+        let (ctx, crx) = chan::async::<Command>();
+        let (btx, brx) = chan::async::<Event>();
+        let cfg        = DBusConfig { bus: bus.clone(), timeout: 100, retries: 1, ..DBusConfig::default() };
+        let mut dbus   = DBus {
+            dbus_cfg: cfg.clone(),
+            itx:      itx.clone(),
+            etx:      btx,
+            status:   Arc::new(Mutex::new(ClientStatus::default())),
+            pulse_tx: None
+        };
+        thread::spawn(move || dbus.start(itx, erx, Heartbeat::default()));
+        thread::sleep(Duration::from_millis(500)); // wait until the name is registered
+
+        thread::spawn(move || {
+            loop {
+                let interpret = irx.recv().expect("itx is closed");
+                let event = match interpret.command.clone() {
+                    Command::GetUpdateRequests     => Event::NoUpdateRequests,
+                    Command::ListInstalledPackages => Event::FoundInstalledPackages(vec![Package {
+                        name:    "name".to_string(),
+                        version: "1.0".to_string()
+                    }]),
+                    Command::AbortUpdate(ref id) if id == "1" => Event::UpdateAborted(id.clone()),
+                    Command::AbortUpdate(id)       => Event::Error(format!("no transfer for update {}", id)),
+                    _                              => panic!("unexpected command")
+                };
+                ctx.send(interpret.command);
+                interpret.response_tx.map(|tx| tx.lock().unwrap().send(event));
+            }
+        });
+
+        etx.send(Event::DownloadingUpdate("1".to_string()));
+        thread::sleep(Duration::from_millis(100)); // wait until the status is updated
+
+        let conn = connect(&bus).expect("couldn't connect to private bus");
+        let call = |method: &str, args: &[MessageItem]| -> Vec<MessageItem> {
+            let mut msg = Message::new_method_call(&cfg.name, &cfg.path, &cfg.interface, method)
+                .expect("couldn't create dbus message");
+            msg.append_items(args);
+            conn.send_with_reply_and_block(msg, 5000).expect("couldn't call dbus method").get_items()
+        };
+
+        assert_eq!(call("getStatus", &[]), vec![
+            MessageItem::from("unknown"),
+            MessageItem::from("downloading"),
+            MessageItem::from("1")
+        ]);
+
+        assert_eq!(call("getPendingUpdates", &[]), vec![MessageItem::Array(vec![], "as".into())]);
+        assert_eq!(crx.recv(), Some(Command::GetUpdateRequests));
+
+        let package = MessageItem::Struct(vec![MessageItem::from("name"), MessageItem::from("1.0")]);
+        assert_eq!(call("getInstalledPackages", &[]), vec![MessageItem::new_array(vec![package]).unwrap()]);
+        assert_eq!(crx.recv(), Some(Command::ListInstalledPackages));
+
+        assert_eq!(call("abortUpdate", &[MessageItem::from("1")]), vec![]);
+        assert_eq!(crx.recv(), Some(Command::AbortUpdate("1".to_string())));
+
+        let mut msg = Message::new_method_call(&cfg.name, &cfg.path, &cfg.interface, "abortUpdate")
+            .expect("couldn't create dbus message");
+        msg.append_items(&[MessageItem::from("2")]);
+        assert!(conn.send_with_reply_and_block(msg, 5000).is_err());
+        assert_eq!(crx.recv(), Some(Command::AbortUpdate("2".to_string())));
+
+        // no software manager is registered so the bus replies with an error
+        etx.send(Event::InstalledSoftwareNeeded);
+        match brx.recv() {
+            Some(Event::Error(err)) => assert!(err.contains("getInstalledPackages call failed")),
+            other                   => panic!("expected an error event: {:?}", other)
+        }
+    }
+}
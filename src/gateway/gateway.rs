@@ -3,28 +3,57 @@ use std::process;
 use std::sync::{Arc, Mutex};
 
 use datatype::{Command, Event};
+use health::Heartbeat;
+use logging::new_correlation_id;
 
 
 /// Encapsulates a `Command` to be sent to the `GlobalInterpreter` for processing,
-/// with an optional channel to receive the outcome `Event`.
+/// with an optional channel to receive the outcome `Event`. The gateway names
+/// the origin of the command, and the correlation id tags all log records of
+/// the processing.
 pub struct Interpret {
-    pub command:     Command,
-    pub response_tx: Option<Arc<Mutex<Sender<Event>>>>,
+    pub command:        Command,
+    pub response_tx:    Option<Arc<Mutex<Sender<Event>>>>,
+    pub gateway:        &'static str,
+    pub correlation_id: String,
+}
+
+impl Interpret {
+    /// Wrap the command from this gateway with a new correlation id.
+    pub fn new(command: Command, response_tx: Option<Arc<Mutex<Sender<Event>>>>, gateway: &'static str) -> Interpret {
+        let id = new_correlation_id();
+        debug!("correlation id {} for command from {}: {}", id, gateway, command);
+        Interpret { command: command, response_tx: response_tx, gateway: gateway, correlation_id: id }
+    }
+
+    /// Wrap a follow-up command with the correlation id of its cause.
+    pub fn correlated(command: Command, gateway: &'static str, id: String) -> Interpret {
+        debug!("correlation id {} for command from {}: {}", id, gateway, command);
+        Interpret { command: command, response_tx: None, gateway: gateway, correlation_id: id }
+    }
 }
 
 /// A `Gateway` may send `Command`s to the `GlobalInterpreter`, as well as listen
-/// to the system-wide `Event` messages.
+/// to the system-wide `Event` messages. The `Heartbeat` is busy while each
+/// `Event` is handled.
 pub trait Gateway {
     fn initialize(&mut self, itx: Sender<Interpret>) -> Result<(), String>;
 
-    fn start(&mut self, itx: Sender<Interpret>, erx: Receiver<Event>) {
+    fn start(&mut self, itx: Sender<Interpret>, erx: Receiver<Event>, heartbeat: Heartbeat) {
         self.initialize(itx).unwrap_or_else(|err| {
             error!("couldn't start gateway: {}", err);
             process::exit(1);
         });
+        self.run(erx, heartbeat)
+    }
 
+    /// Handle each global `Event` after a successful `initialize`.
+    fn run(&mut self, erx: Receiver<Event>, heartbeat: Heartbeat) {
         loop {
-            self.pulse(erx.recv().expect("all gateway event transmitters are closed"));
+            let event = erx.recv().expect("all gateway event transmitters are closed");
+            heartbeat.busy();
+            self.pulse(event);
+            heartbeat.idle();
         }
     }
 
@@ -1,6 +1,7 @@
 use chan;
 use chan::{Sender, Receiver};
 use hyper::StatusCode;
+use hyper::header::{Authorization, Bearer};
 use hyper::net::{HttpStream, Transport};
 use hyper::server::{Server as HyperServer, Request as HyperRequest};
 use rustc_serialize::json;
@@ -8,62 +9,121 @@ use std::net::SocketAddr;
 use std::thread;
 use std::sync::{Arc, Mutex};
 
-use datatype::{Command, Event};
+use datatype::{AccessPolicy, Command, Error, Event};
+use datatype::update_report::decode_json;
 use gateway::{Gateway, Interpret};
+use health::Health;
 use http::{Server, ServerHandler};
 
 
 /// The `Http` gateway parses `Command`s from the body of incoming requests.
+/// An optional `AccessPolicy` restricts who may send which commands. A `GET`
+/// request to `/health` returns the `HealthReport` of the client instead.
+///
+/// Once initialized, `server` holds the address actually bound, so a port of
+/// `0` may be used to listen on any free port.
 pub struct Http {
-    pub server: SocketAddr
+    pub server: SocketAddr,
+    pub access: Option<AccessPolicy>,
+    pub health: Health,
 }
 
 impl Gateway for Http {
     fn initialize(&mut self, itx: Sender<Interpret>) -> Result<(), String> {
         let itx    = Arc::new(Mutex::new(itx));
+        let access = self.access.clone();
+        let health = self.health.clone();
         let server = try!(HyperServer::http(&self.server).map_err(|err| {
             format!("couldn't start http gateway: {}", err)
         }));
 
+        let (addr_tx, addr_rx) = chan::sync::<SocketAddr>(0);
         thread::spawn(move || {
-            let (_, server) = server.handle(move |_| HttpHandler::new(itx.clone())).unwrap();
+            let (listening, server) = server.handle(move |_| {
+                HttpHandler::new(itx.clone(), access.clone(), health.clone())
+            }).unwrap();
+            addr_tx.send(*listening.addr());
             server.run();
         });
 
+        self.server = try!(addr_rx.recv().ok_or("couldn't get http gateway address".to_string()));
         Ok(info!("HTTP gateway listening at http://{}", self.server))
     }
 }
 
 
 struct HttpHandler {
-    itx:         Arc<Mutex<Sender<Interpret>>>,
-    response_rx: Option<Receiver<Event>>
+    itx:          Arc<Mutex<Sender<Interpret>>>,
+    access:       Option<AccessPolicy>,
+    health:       Health,
+    health_check: bool,
+    token:        Option<String>,
+    rejected:     Option<(StatusCode, Event)>,
+    response_rx:  Option<Receiver<Event>>
 }
 
 impl HttpHandler {
-    fn new(itx: Arc<Mutex<Sender<Interpret>>>) -> ServerHandler<HttpStream> {
-        ServerHandler::new(Box::new(HttpHandler { itx: itx, response_rx: None }))
+    fn new(itx: Arc<Mutex<Sender<Interpret>>>, access: Option<AccessPolicy>, health: Health) -> ServerHandler<HttpStream> {
+        ServerHandler::new(Box::new(HttpHandler {
+            itx:          itx,
+            access:       access,
+            health:       health,
+            health_check: false,
+            token:        None,
+            rejected:     None,
+            response_rx:  None
+        }))
+    }
+
+    fn authorize(&self, cmd: &Command) -> Result<(), (StatusCode, Error)> {
+        self.access.as_ref().map_or(Ok(()), |access| {
+            try!(access.check_token(self.token.as_ref().map(|token| token.as_str()))
+                 .map_err(|err| (StatusCode::Unauthorized, err)));
+            access.check_command(cmd).map_err(|err| (StatusCode::Forbidden, err))
+        })
     }
 }
 
 impl<T: Transport> Server<T> for HttpHandler {
-    fn headers(&mut self, _: HyperRequest<T>) {}
+    fn headers(&mut self, req: HyperRequest<T>) {
+        self.health_check = format!("{}", req.method()) == "GET" && format!("{}", req.uri()) == "/health";
+        self.token        = req.headers().get::<Authorization<Bearer>>().map(|auth| auth.0.token.clone());
+    }
 
     fn request(&mut self, body: Vec<u8>) {
+        if self.health_check {
+            return
+        }
+
         String::from_utf8(body).map(|body| {
-            json::decode::<Command>(&body).map(|cmd| {
+            decode_json::<Command>(&body).map(|cmd| {
                 info!("Incoming HTTP request command: {}", cmd);
+                if let Err((code, err)) = self.authorize(&cmd) {
+                    error!("rejected http request command {}: {}", cmd, err);
+                    self.rejected = Some((code, Event::Error(format!("{}", err))));
+                    return
+                }
+
                 let (etx, erx)   = chan::async::<Event>();
                 self.response_rx = Some(erx);
-                self.itx.lock().unwrap().send(Interpret {
-                    command:     cmd,
-                    response_tx: Some(Arc::new(Mutex::new(etx))),
-                });
+                self.itx.lock().unwrap().send(Interpret::new(cmd, Some(Arc::new(Mutex::new(etx))), "http"));
             }).unwrap_or_else(|err| error!("http request parse json: {}", err))
         }).unwrap_or_else(|err| error!("http request parse string: {}", err))
     }
 
     fn response(&mut self) -> (StatusCode, Option<Vec<u8>>) {
+        if self.health_check {
+            let report = self.health.report();
+            let code   = if report.healthy { StatusCode::Ok } else { StatusCode::ServiceUnavailable };
+            let body   = json::encode(&report).expect("couldn't encode health report");
+            return (code, Some(body.into_bytes()))
+        }
+
+        if let Some((code, event)) = self.rejected.take() {
+            let body = json::encode(&event).expect("couldn't encode rejection event");
+            return (code, Some(body.into_bytes()))
+        }
+
         self.response_rx.as_ref().map_or((StatusCode::BadRequest, None), |rx| {
             rx.recv().map_or_else(|| {
                 error!("on_response receiver error");
@@ -86,12 +146,15 @@ mod tests {
     use chan;
     use crossbeam;
     use rustc_serialize::json;
+    use hyper::StatusCode;
     use std::path::Path;
     use std::thread;
+    use std::time::Duration;
 
     use super::*;
     use gateway::{Gateway, Interpret};
-    use datatype::{Command, Event};
+    use datatype::{Command, Event, Url};
+    use health::{Health, Heartbeat};
     use http::{AuthClient, Client, Response, set_ca_certificates};
 
 
@@ -102,7 +165,11 @@ mod tests {
         let (etx, erx) = chan::sync::<Event>(0);
         let (itx, irx) = chan::sync::<Interpret>(0);
 
-        thread::spawn(move || Http { server: "127.0.0.1:8888".parse().unwrap() }.start(itx, erx));
+        thread::spawn(move || Http {
+            server: "127.0.0.1:8888".parse().unwrap(),
+            access: None,
+            health: Health::default(),
+        }.start(itx, erx, Heartbeat::default()));
         thread::spawn(move || {
             let _ = etx; // move into this scope
             loop {
@@ -137,4 +204,43 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn http_health_check() {
+        let (itx, _irx) = chan::sync::<Interpret>(0);
+        let health      = Health::new(Duration::from_millis(50));
+        let heartbeat   = health.register("global_interpreter");
+        let mut gateway = Http {
+            server: "127.0.0.1:0".parse().unwrap(),
+            access: None,
+            health: health.clone(),
+        };
+        gateway.initialize(itx).expect("couldn't initialize http gateway");
+
+        let client = AuthClient::default();
+        let url    = format!("http://{}/health", gateway.server).parse::<Url>().unwrap();
+        match client.get(url.clone(), None).recv().unwrap() {
+            Response::Success(data) => assert!(String::from_utf8(data.body).unwrap().contains(r#""healthy":true"#)),
+            other                   => panic!("expected healthy response, got {:?}", other)
+        }
+
+        heartbeat.busy();
+        let deadline = chan::after(Duration::from_secs(5));
+        let tick     = chan::tick(Duration::from_millis(20));
+        loop {
+            match client.get(url.clone(), None).recv().unwrap() {
+                Response::Failed(data) => {
+                    assert_eq!(data.code, StatusCode::ServiceUnavailable);
+                    assert!(String::from_utf8(data.body).unwrap().contains(r#""state":"busy""#));
+                    break
+                }
+                Response::Success(_) => (),
+                other                => panic!("expected unhealthy response, got {:?}", other)
+            }
+            chan_select! {
+                deadline.recv() => panic!("health check never reported the busy interpreter"),
+                tick.recv()     => (),
+            }
+        }
+    }
 }
@@ -2,12 +2,14 @@ pub mod console;
 pub mod dbus;
 pub mod gateway;
 pub mod http;
+pub mod protocol;
 pub mod socket;
 pub mod websocket;
 
 pub use self::console::Console;
-pub use self::dbus::DBus;
+pub use self::dbus::{ClientStatus, DBus};
 pub use self::gateway::{Gateway, Interpret};
 pub use self::http::Http;
+pub use self::protocol::{CommandMessage, EventMessage};
 pub use self::socket::Socket;
 pub use self::websocket::Websocket;
@@ -0,0 +1,446 @@
+use rustc_serialize::{json, Decodable, Encodable};
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+
+use datatype::{AuditEntry, ClientCredentials, Command, ConfigChanges, DownloadComplete,
+               DownloadFailed, Error, Event, InstalledSoftware, Package, UpdateAvailable, UpdateReport,
+               UpdateRequest, UpdateRequestId};
+use datatype::update_report::name_result_codes;
+
+
+/// The version of the original socket events, which only covered the
+/// `DownloadComplete` and `DownloadFailed` events.
+pub const LEGACY_VERSION: &'static str = "0.1";
+
+/// The current version of the JSON protocol.
+pub const CURRENT_VERSION: &'static str = "1";
+
+/// All protocol versions understood by the client, in order of preference.
+pub const SUPPORTED_VERSIONS: &'static [&'static str] = &["1"];
+
+
+/// A v1 request containing a single `Command`:
+///
+/// `{ "version": "1", "command": "StartDownload", "data": "<update-id>" }`
+///
+/// Instead of `version` a client may send a list of acceptable `versions` in
+/// order of preference, and the first supported version will be chosen. The
+/// `data` field is omitted or `null` for commands without arguments. Otherwise
+/// it holds the JSON encoding of the command's argument:
+///
+/// * `Authenticate`: `null` or `{ "client_id": "...", "client_secret": "..." }`
+/// * `StartDownload`, `StartInstall`, `AbortUpdate`: the update id as a string
+/// * `SendInstalledPackages`: `[{ "name": "...", "version": "..." }]`
+/// * `SendInstalledSoftware`: `{ "packages": [...], "firmwares": [...] }`
+/// * `SendUpdateReport`: `{ "update_id": "...", "operation_results": [...] }`
+/// * `GetHistory`: `null` or `{ "since": "...", "limit": 10, "filter": "..." }`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CommandMessage {
+    pub version: String,
+    pub command: Command,
+}
+
+impl CommandMessage {
+    /// Parse a JSON request, negotiating the protocol version to use.
+    pub fn from_json(text: &str) -> Result<CommandMessage, Error> {
+        let data    = try!(Json::from_str(text).map_err(|err| Error::Parse(format!("{}", err))));
+        let mut obj = try!(data.into_object().ok_or(Error::Parse("expected a JSON object".to_string())));
+        let version = try!(negotiate(&obj));
+        let name    = try!(obj.remove("command").and_then(|name| name.as_string().map(String::from))
+                           .ok_or(Error::Parse("expected a command name".to_string())));
+        let args    = obj.remove("data").unwrap_or(Json::Null);
+        let command = try!(decode_command(&name, args));
+        Ok(CommandMessage { version: version, command: command })
+    }
+
+    /// Encode this request as JSON.
+    pub fn to_json(&self) -> String {
+        let mut obj = BTreeMap::new();
+        obj.insert("version".to_string(), Json::String(self.version.clone()));
+        obj.insert("command".to_string(), Json::String(self.command.name().to_string()));
+        obj.insert("data".to_string(), command_data(&self.command));
+        Json::Object(obj).to_string()
+    }
+}
+
+
+/// A v1 message containing a single `Event`:
+///
+/// `{ "version": "1", "event": "DownloadComplete", "reply": false, "data": { ... } }`
+///
+/// This is used both for responses on the commands socket and for broadcasts
+/// to the events socket. The `reply` field is `true` only for the response to
+/// a command, so that clients of a persistent connection can tell responses
+/// apart from the broadcast events sent to the same connection. The `data`
+/// field is `null` for events without any arguments. Otherwise it holds the
+/// JSON encoding of the event's argument:
+///
+/// * `Error`, `FoundSystemInfo`, `RviDisconnected`: a string
+/// * `DownloadingUpdate`, `InstallingUpdate`, `UpdateAborted`: the update id as a string
+/// * `UpdatesReceived`: a list of update requests
+/// * `UpdateAvailable`, `DownloadComplete`: an object with the update details
+/// * `DownloadProgress`: `{ "update_id": "...", "chunks": 5 }`
+/// * `DownloadFailed`, `DiskFull`: `{ "update_id": "...", "reason": "..." }`
+/// * `FoundInstalledPackages`: `[{ "name": "...", "version": "..." }]`
+/// * `InstallComplete`, `InstallFailed`: an update report
+/// * `History`: a list of audit log entries
+/// * `ConfigReloaded`: `{ "applied": [...], "restart_required": [...], "failed": [...] }`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct EventMessage {
+    pub version: String,
+    pub event:   Event,
+    pub reply:   bool,
+}
+
+impl EventMessage {
+    /// Wrap a broadcast `Event` using the current protocol version.
+    pub fn new(event: Event) -> EventMessage {
+        EventMessage { version: CURRENT_VERSION.to_string(), event: event, reply: false }
+    }
+
+    /// Wrap the `Event` in response to a command using the negotiated version.
+    pub fn reply(version: String, event: Event) -> EventMessage {
+        EventMessage { version: version, event: event, reply: true }
+    }
+
+    /// Parse a JSON event message.
+    pub fn from_json(text: &str) -> Result<EventMessage, Error> {
+        let data    = try!(Json::from_str(text).map_err(|err| Error::Parse(format!("{}", err))));
+        let mut obj = try!(data.into_object().ok_or(Error::Parse("expected a JSON object".to_string())));
+        let version = try!(negotiate(&obj));
+        let name    = try!(obj.remove("event").and_then(|name| name.as_string().map(String::from))
+                           .ok_or(Error::Parse("expected an event name".to_string())));
+        let reply   = obj.get("reply").and_then(|reply| reply.as_boolean()).unwrap_or(false);
+        let args    = obj.remove("data").unwrap_or(Json::Null);
+        let event   = try!(decode_event(&name, args));
+        Ok(EventMessage { version: version, event: event, reply: reply })
+    }
+
+    /// Encode this event as JSON.
+    pub fn to_json(&self) -> String {
+        let mut obj = BTreeMap::new();
+        obj.insert("version".to_string(), Json::String(self.version.clone()));
+        obj.insert("event".to_string(), Json::String(self.event.name().to_string()));
+        obj.insert("reply".to_string(), Json::Boolean(self.reply));
+        obj.insert("data".to_string(), event_data(&self.event));
+        Json::Object(obj).to_string()
+    }
+}
+
+
+// The number of chunks of an update received so far.
+#[derive(RustcDecodable, RustcEncodable)]
+struct DownloadProgress {
+    update_id: UpdateRequestId,
+    chunks:    u64,
+}
+
+
+// The optional arguments of a history query.
+#[derive(RustcDecodable, RustcEncodable)]
+struct HistoryQuery {
+    since:  Option<String>,
+    limit:  Option<u64>,
+    filter: Option<String>,
+}
+
+
+// Choose the protocol version from either the `version` or `versions` fields.
+fn negotiate(obj: &BTreeMap<String, Json>) -> Result<String, Error> {
+    let requested = match (obj.get("version"), obj.get("versions")) {
+        (Some(&Json::String(ref version)), _) => vec![version.clone()],
+        (None, Some(&Json::Array(ref versions))) => {
+            versions.iter().filter_map(|v| v.as_string().map(String::from)).collect()
+        }
+        _ => return Err(Error::Parse("expected a protocol version".to_string()))
+    };
+
+    requested.iter()
+        .find(|version| SUPPORTED_VERSIONS.iter().any(|supported| supported == version))
+        .cloned()
+        .ok_or_else(|| Error::Parse(format!("unsupported protocol version {:?}, supported: {:?}",
+                                            requested, SUPPORTED_VERSIONS)))
+}
+
+fn to_json<E: Encodable>(data: &E) -> Json {
+    let text = json::encode(data).expect("couldn't encode message data");
+    Json::from_str(&text).expect("couldn't parse encoded message data")
+}
+
+fn from_json<D: Decodable>(data: Json) -> Result<D, Error> {
+    let mut decoder = json::Decoder::new(name_result_codes(data));
+    Ok(try!(D::decode(&mut decoder)))
+}
+
+fn expect_null(name: &str, data: Json) -> Result<(), Error> {
+    match data {
+        Json::Null => Ok(()),
+        _          => Err(Error::Parse(format!("{} expects no data", name)))
+    }
+}
+
+fn command_data(cmd: &Command) -> Json {
+    match *cmd {
+        Command::Authenticate(ref creds)          => to_json(creds),
+        Command::StartDownload(ref id)            => Json::String(id.clone()),
+        Command::StartInstall(ref id)             => Json::String(id.clone()),
+        Command::AbortUpdate(ref id)              => Json::String(id.clone()),
+        Command::SendInstalledPackages(ref pkgs)  => to_json(pkgs),
+        Command::SendInstalledSoftware(ref soft)  => to_json(soft),
+        Command::SendUpdateReport(ref report)     => to_json(report),
+        Command::GetHistory { ref since, limit, ref filter } => {
+            to_json(&HistoryQuery { since: since.clone(), limit: limit, filter: filter.clone() })
+        }
+
+        Command::Shutdown              |
+        Command::ReloadConfig          |
+        Command::GetUpdateRequests     |
+        Command::ListInstalledPackages |
+        Command::ListSystemInfo        |
+        Command::SendSystemInfo        => Json::Null,
+    }
+}
+
+/// Decode a `Command` from its name and the JSON encoding of its argument.
+pub fn decode_command(name: &str, data: Json) -> Result<Command, Error> {
+    match name {
+        "Authenticate"          => Ok(Command::Authenticate(try!(from_json::<Option<ClientCredentials>>(data)))),
+        "Shutdown"              => expect_null(name, data).map(|_| Command::Shutdown),
+        "ReloadConfig"          => expect_null(name, data).map(|_| Command::ReloadConfig),
+        "GetUpdateRequests"     => expect_null(name, data).map(|_| Command::GetUpdateRequests),
+        "GetHistory"            => {
+            let query = match data {
+                Json::Null => HistoryQuery { since: None, limit: None, filter: None },
+                _          => try!(from_json::<HistoryQuery>(data))
+            };
+            Ok(Command::GetHistory { since: query.since, limit: query.limit, filter: query.filter })
+        }
+        "ListInstalledPackages" => expect_null(name, data).map(|_| Command::ListInstalledPackages),
+        "ListSystemInfo"        => expect_null(name, data).map(|_| Command::ListSystemInfo),
+        "StartDownload"         => Ok(Command::StartDownload(try!(from_json(data)))),
+        "StartInstall"          => Ok(Command::StartInstall(try!(from_json(data)))),
+        "AbortUpdate"           => Ok(Command::AbortUpdate(try!(from_json(data)))),
+        "SendInstalledPackages" => Ok(Command::SendInstalledPackages(try!(from_json::<Vec<Package>>(data)))),
+        "SendInstalledSoftware" => Ok(Command::SendInstalledSoftware(try!(from_json::<InstalledSoftware>(data)))),
+        "SendSystemInfo"        => expect_null(name, data).map(|_| Command::SendSystemInfo),
+        "SendUpdateReport"      => Ok(Command::SendUpdateReport(try!(from_json::<UpdateReport>(data)))),
+        _                       => Err(Error::Command(format!("unknown command: {}", name)))
+    }
+}
+
+fn event_data(event: &Event) -> Json {
+    match *event {
+        Event::Error(ref msg)                 => Json::String(msg.clone()),
+        Event::UpdatesReceived(ref requests)  => to_json(requests),
+        Event::UpdateAvailable(ref avail)     => to_json(avail),
+        Event::FoundInstalledPackages(ref ps) => to_json(ps),
+        Event::FoundSystemInfo(ref info)      => Json::String(info.clone()),
+        Event::DownloadingUpdate(ref id)      => Json::String(id.clone()),
+        Event::DownloadProgress(ref id, chunks) => {
+            to_json(&DownloadProgress { update_id: id.clone(), chunks: chunks })
+        }
+        Event::DownloadComplete(ref dl)       => to_json(dl),
+        Event::DownloadFailed(ref id, ref reason) => {
+            to_json(&DownloadFailed { update_id: id.clone(), reason: reason.clone() })
+        }
+        Event::DiskFull(ref id, ref reason) => {
+            to_json(&DownloadFailed { update_id: id.clone(), reason: reason.clone() })
+        }
+        Event::InstallingUpdate(ref id)       => Json::String(id.clone()),
+        Event::InstallComplete(ref report)    => to_json(report),
+        Event::InstallFailed(ref report)      => to_json(report),
+        Event::UpdateAborted(ref id)          => Json::String(id.clone()),
+        Event::RviDisconnected(ref reason)    => Json::String(reason.clone()),
+        Event::History(ref entries)           => to_json(entries),
+        Event::ConfigReloaded(ref changes)    => to_json(changes),
+
+        Event::Authenticated           |
+        Event::NotAuthenticated        |
+        Event::AlreadyAuthenticated    |
+        Event::NoUpdateRequests        |
+        Event::UpdateReportSent        |
+        Event::InstalledPackagesSent   |
+        Event::InstalledSoftwareSent   |
+        Event::SystemInfoSent          |
+        Event::InstalledSoftwareNeeded |
+        Event::RviConnected            => Json::Null,
+    }
+}
+
+/// Decode an `Event` from its name and the JSON encoding of its argument.
+pub fn decode_event(name: &str, data: Json) -> Result<Event, Error> {
+    match name {
+        "Error"                   => Ok(Event::Error(try!(from_json(data)))),
+        "Authenticated"           => expect_null(name, data).map(|_| Event::Authenticated),
+        "NotAuthenticated"        => expect_null(name, data).map(|_| Event::NotAuthenticated),
+        "AlreadyAuthenticated"    => expect_null(name, data).map(|_| Event::AlreadyAuthenticated),
+        "UpdatesReceived"         => Ok(Event::UpdatesReceived(try!(from_json::<Vec<UpdateRequest>>(data)))),
+        "UpdateAvailable"         => Ok(Event::UpdateAvailable(try!(from_json::<UpdateAvailable>(data)))),
+        "NoUpdateRequests"        => expect_null(name, data).map(|_| Event::NoUpdateRequests),
+        "FoundInstalledPackages"  => Ok(Event::FoundInstalledPackages(try!(from_json::<Vec<Package>>(data)))),
+        "FoundSystemInfo"         => Ok(Event::FoundSystemInfo(try!(from_json(data)))),
+        "DownloadingUpdate"       => Ok(Event::DownloadingUpdate(try!(from_json(data)))),
+        "DownloadProgress"        => {
+            let progress = try!(from_json::<DownloadProgress>(data));
+            Ok(Event::DownloadProgress(progress.update_id, progress.chunks))
+        }
+        "DownloadComplete"        => Ok(Event::DownloadComplete(try!(from_json::<DownloadComplete>(data)))),
+        "DownloadFailed"          => {
+            let failed = try!(from_json::<DownloadFailed>(data));
+            Ok(Event::DownloadFailed(failed.update_id, failed.reason))
+        }
+        "DiskFull"                => {
+            let failed = try!(from_json::<DownloadFailed>(data));
+            Ok(Event::DiskFull(failed.update_id, failed.reason))
+        }
+        "InstallingUpdate"        => Ok(Event::InstallingUpdate(try!(from_json(data)))),
+        "InstallComplete"         => Ok(Event::InstallComplete(try!(from_json::<UpdateReport>(data)))),
+        "InstallFailed"           => Ok(Event::InstallFailed(try!(from_json::<UpdateReport>(data)))),
+        "UpdateAborted"           => Ok(Event::UpdateAborted(try!(from_json(data)))),
+        "UpdateReportSent"        => expect_null(name, data).map(|_| Event::UpdateReportSent),
+        "InstalledPackagesSent"   => expect_null(name, data).map(|_| Event::InstalledPackagesSent),
+        "InstalledSoftwareSent"   => expect_null(name, data).map(|_| Event::InstalledSoftwareSent),
+        "SystemInfoSent"          => expect_null(name, data).map(|_| Event::SystemInfoSent),
+        "InstalledSoftwareNeeded" => expect_null(name, data).map(|_| Event::InstalledSoftwareNeeded),
+        "RviConnected"            => expect_null(name, data).map(|_| Event::RviConnected),
+        "RviDisconnected"         => Ok(Event::RviDisconnected(try!(from_json(data)))),
+        "History"                 => Ok(Event::History(try!(from_json::<Vec<AuditEntry>>(data)))),
+        "ConfigReloaded"          => Ok(Event::ConfigReloaded(try!(from_json::<ConfigChanges>(data)))),
+        _                         => Err(Error::Parse(format!("unknown event: {}", name)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatype::{AuditEntry, ClientCredentials, Command, ConfigChanges, DownloadComplete, Event,
+                   InstalledSoftware, Package, UpdateAvailable, UpdateReport, UpdateRequest,
+                   UpdateRequestStatus, UpdateResultCode};
+
+
+    fn package() -> Package {
+        Package { name: "name".to_string(), version: "1.0".to_string() }
+    }
+
+    fn report() -> UpdateReport {
+        UpdateReport::single("1".to_string(), UpdateResultCode::OK, "done".to_string())
+    }
+
+    #[test]
+    fn all_commands_round_trip() {
+        let commands = vec![
+            Command::Authenticate(None),
+            Command::Authenticate(Some(ClientCredentials {
+                client_id:     "id".to_string(),
+                client_secret: "secret".to_string()
+            })),
+            Command::Shutdown,
+            Command::ReloadConfig,
+            Command::GetUpdateRequests,
+            Command::GetHistory { since: None, limit: None, filter: None },
+            Command::GetHistory {
+                since:  Some("2016-10-01T00:00:00Z".to_string()),
+                limit:  Some(10),
+                filter: Some("1".to_string())
+            },
+            Command::ListInstalledPackages,
+            Command::ListSystemInfo,
+            Command::StartDownload("1".to_string()),
+            Command::StartInstall("1".to_string()),
+            Command::AbortUpdate("1".to_string()),
+            Command::SendInstalledPackages(vec![package()]),
+            Command::SendInstalledSoftware(InstalledSoftware::default()),
+            Command::SendSystemInfo,
+            Command::SendUpdateReport(report()),
+        ];
+
+        for cmd in commands {
+            let msg = CommandMessage { version: CURRENT_VERSION.to_string(), command: cmd };
+            assert_eq!(CommandMessage::from_json(&msg.to_json()).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    fn all_events_round_trip() {
+        let events = vec![
+            Event::Error("error".to_string()),
+            Event::Authenticated,
+            Event::NotAuthenticated,
+            Event::AlreadyAuthenticated,
+            Event::UpdatesReceived(vec![UpdateRequest {
+                requestId:  "1".to_string(),
+                status:     UpdateRequestStatus::Pending,
+                packageId:  package(),
+                installPos: 0,
+                createdAt:  "2010-01-01".to_string()
+            }]),
+            Event::UpdateAvailable(UpdateAvailable {
+                update_id:            "1".to_string(),
+                signature:            "sig".to_string(),
+                description:          "desc".to_string(),
+                request_confirmation: false,
+                size:                 100
+            }),
+            Event::NoUpdateRequests,
+            Event::FoundInstalledPackages(vec![package()]),
+            Event::FoundSystemInfo("info".to_string()),
+            Event::DownloadingUpdate("1".to_string()),
+            Event::DownloadProgress("1".to_string(), 5),
+            Event::DownloadComplete(DownloadComplete {
+                update_id:    "1".to_string(),
+                update_image: "/tmp/1".to_string(),
+                signature:    "sig".to_string()
+            }),
+            Event::DownloadFailed("1".to_string(), "reason".to_string()),
+            Event::DiskFull("1".to_string(), "reason".to_string()),
+            Event::InstallingUpdate("1".to_string()),
+            Event::InstallComplete(report()),
+            Event::InstallFailed(report()),
+            Event::UpdateAborted("1".to_string()),
+            Event::UpdateReportSent,
+            Event::InstalledPackagesSent,
+            Event::InstalledSoftwareSent,
+            Event::SystemInfoSent,
+            Event::InstalledSoftwareNeeded,
+            Event::RviConnected,
+            Event::RviDisconnected("reason".to_string()),
+            Event::History(vec![AuditEntry::command(&Command::StartDownload("1".to_string()), "socket", "abc")]),
+            Event::ConfigReloaded(ConfigChanges {
+                applied:          vec!["core.polling_sec".to_string()],
+                restart_required: vec!["network".to_string()],
+                failed:           vec!["gateway.http: address in use".to_string()]
+            }),
+        ];
+
+        for event in events {
+            let msg = EventMessage::new(event);
+            assert_eq!(EventMessage::from_json(&msg.to_json()).unwrap(), msg);
+        }
+
+        let reply = EventMessage::reply("1".to_string(), Event::NoUpdateRequests);
+        assert!(reply.to_json().contains(r#""reply":true"#));
+        assert_eq!(EventMessage::from_json(&reply.to_json()).unwrap(), reply);
+    }
+
+    #[test]
+    fn parse_command_request() {
+        let msg = CommandMessage::from_json(r#"{ "version": "1", "command": "StartDownload", "data": "123" }"#).unwrap();
+        assert_eq!(msg.command, Command::StartDownload("123".to_string()));
+        let msg = CommandMessage::from_json(r#"{ "version": "1", "command": "GetUpdateRequests" }"#).unwrap();
+        assert_eq!(msg.command, Command::GetUpdateRequests);
+        let msg = CommandMessage::from_json(r#"{ "version": "1", "command": "GetHistory", "data": { "limit": 5 } }"#).unwrap();
+        assert_eq!(msg.command, Command::GetHistory { since: None, limit: Some(5), filter: None });
+        assert!(CommandMessage::from_json(r#"{ "version": "1", "command": "Shutdown", "data": 1 }"#).is_err());
+        assert!(CommandMessage::from_json(r#"{ "version": "1", "command": "Unknown" }"#).is_err());
+    }
+
+    #[test]
+    fn negotiate_version() {
+        let msg = CommandMessage::from_json(r#"{ "versions": ["2", "1"], "command": "Shutdown" }"#).unwrap();
+        assert_eq!(msg.version, "1".to_string());
+        assert!(CommandMessage::from_json(r#"{ "version": "2", "command": "Shutdown" }"#).is_err());
+        assert!(CommandMessage::from_json(r#"{ "versions": ["0.1"], "command": "Shutdown" }"#).is_err());
+        assert!(CommandMessage::from_json(r#"{ "command": "Shutdown" }"#).is_err());
+    }
+}
@@ -1,46 +1,81 @@
 use chan;
 use chan::Sender;
+use libc;
 use rustc_serialize::{Encodable, json};
-use std::io::{BufReader, Read, Write};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::{fs, io, mem, thread};
 use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
 use std::sync::{Arc, Mutex};
-use std::{fs, thread};
+use std::time::Duration;
 
-use datatype::{Command, DownloadFailed, Error, Event};
+use datatype::{AccessPolicy, Command, DownloadFailed, Error, Event, PeerCredentials};
 use super::{Gateway, Interpret};
+use super::protocol::{CommandMessage, EventMessage, CURRENT_VERSION, LEGACY_VERSION,
+                      SUPPORTED_VERSIONS};
 use unix_socket::{UnixListener, UnixStream};
 
 
 /// The `Socket` gateway is used for communication via Unix Domain Sockets.
+/// An optional `AccessPolicy` restricts which local users may send commands.
+///
+/// Requests beginning with `{` are parsed as a versioned `CommandMessage` and
+/// answered with an `EventMessage`. All other input is parsed with the legacy
+/// text command syntax. Events are sent with either the legacy "0.1" format or
+/// the current JSON protocol, depending on `events_version`.
+///
+/// When `persistent` is set, each connection to the commands socket stays open
+/// for newline-delimited commands. Each reply is written back as a single line,
+/// and every event is also streamed to all open connections. A connection that
+/// doesn't accept a line within `WRITE_TIMEOUT_SECS` is closed.
 pub struct Socket {
-    pub commands_path: String,
-    pub events_path:   String,
+    pub commands_path:  String,
+    pub events_path:    String,
+    pub events_version: String,
+    pub persistent:     bool,
+    pub clients:        Arc<Mutex<HashMap<usize, Arc<Mutex<UnixStream>>>>>,
+    pub access:         Option<AccessPolicy>,
 }
 
+/// How long a write to a socket client may block before it is dropped.
+pub const WRITE_TIMEOUT_SECS: u64 = 5;
+
 impl Gateway for Socket {
     fn initialize(&mut self, itx: Sender<Interpret>) -> Result<(), String> {
+        if self.events_version != LEGACY_VERSION
+            && !SUPPORTED_VERSIONS.iter().any(|version| *version == self.events_version) {
+            return Err(format!("unsupported socket events version: {}", self.events_version))
+        }
+
         let _ = fs::remove_file(&self.commands_path);
         let commands = match UnixListener::bind(&self.commands_path) {
             Ok(sock) => sock,
             Err(err) => return Err(format!("couldn't open commands socket: {}", err))
         };
 
-        let itx = Arc::new(Mutex::new(itx));
+        let itx        = Arc::new(Mutex::new(itx));
+        let access     = self.access.clone();
+        let clients    = self.clients.clone();
+        let persistent = self.persistent;
         thread::spawn(move || {
-            for conn in commands.incoming() {
+            for (id, conn) in commands.incoming().enumerate() {
                 if let Err(err) = conn {
                     error!("couldn't get commands socket connection: {}", err);
                     continue
                 }
                 let mut stream = conn.unwrap();
-                let itx = itx.clone();
+                let itx     = itx.clone();
+                let access  = access.clone();
+                let clients = clients.clone();
 
                 thread::spawn(move || {
-                    let resp = handle_client(&mut stream, itx)
-                        .map(|ev| json::encode(&ev).expect("couldn't encode Event").into_bytes())
-                        .unwrap_or_else(|err| format!("{}", err).into_bytes());
+                    if persistent {
+                        return handle_persistent(stream, id, clients, itx, access.as_ref());
+                    }
 
-                    stream.write_all(&resp)
+                    let resp = handle_client(&mut stream, itx, access.as_ref());
+                    stream.write_all(&resp.into_bytes())
                         .unwrap_or_else(|err| error!("couldn't write to commands socket: {}", err));
                     stream.shutdown(Shutdown::Write)
                         .unwrap_or_else(|err| error!("couldn't close commands socket: {}", err));
@@ -53,27 +88,34 @@ impl Gateway for Socket {
     }
 
     fn pulse(&self, event: Event) {
-        let output = match event {
-            Event::DownloadComplete(dl) => {
-                json::encode(&EventWrapper {
-                    version: "0.1".to_string(),
-                    event:   "DownloadComplete".to_string(),
-                    data:    dl
-                }).expect("couldn't encode DownloadComplete event")
+        let output = if self.events_version == CURRENT_VERSION {
+            EventMessage::new(event).to_json()
+        } else {
+            match legacy_event(event) {
+                Some(output) => output,
+                None         => return
             }
+        };
 
-            Event::DownloadFailed(id, reason) => {
-                json::encode(&EventWrapper {
-                    version: "0.1".to_string(),
-                    event:   "DownloadFailed".to_string(),
-                    data:    DownloadFailed { update_id: id, reason: reason }
-                }).expect("couldn't encode DownloadFailed event")
+        if self.persistent {
+            // write without holding the clients lock so one slow client can't
+            // block the others or any new connections
+            let line    = format!("{}\n", output);
+            let clients = self.clients.lock().unwrap().iter()
+                .map(|(id, stream)| (*id, stream.clone()))
+                .collect::<Vec<_>>();
+            for (id, stream) in clients {
+                let mut stream = stream.lock().unwrap();
+                if let Err(err) = stream.write_all(line.as_bytes()) {
+                    error!("dropping socket connection {}: {}", id, err);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    self.clients.lock().unwrap().remove(&id);
+                }
             }
-
-            _ => return
-        };
+        }
 
         let _ = UnixStream::connect(&self.events_path).map(|mut stream| {
+            let _ = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS)));
             stream.write_all(&output.into_bytes())
                 .unwrap_or_else(|err| error!("couldn't write to events socket: {}", err));
             stream.shutdown(Shutdown::Write)
@@ -82,24 +124,153 @@ impl Gateway for Socket {
     }
 }
 
-fn handle_client(stream: &mut UnixStream, itx: Arc<Mutex<Sender<Interpret>>>) -> Result<Event, Error> {
+fn handle_client(stream: &mut UnixStream, itx: Arc<Mutex<Sender<Interpret>>>,
+                 access: Option<&AccessPolicy>) -> String {
     info!("New domain socket connection");
-    let mut reader = BufReader::new(stream);
-    let mut input  = String::new();
-    try!(reader.read_to_string(&mut input));
+    if let Err(err) = check_peer(stream, access) {
+        error!("rejected domain socket connection: {}", err);
+        return EventMessage::reply(CURRENT_VERSION.to_string(), Event::Error(format!("{}", err))).to_json()
+    }
+
+    let mut input = String::new();
+    if let Err(err) = BufReader::new(&mut *stream).read_to_string(&mut input) {
+        return format!("{}", Error::Io(err))
+    }
     debug!("socket input: {}", input);
+    handle_input(&input, itx, access)
+}
+
+// Read newline-delimited commands until the client closes the connection.
+fn handle_persistent(mut stream: UnixStream, id: usize, clients: Arc<Mutex<HashMap<usize, Arc<Mutex<UnixStream>>>>>,
+                     itx: Arc<Mutex<Sender<Interpret>>>, access: Option<&AccessPolicy>) {
+    info!("New persistent domain socket connection {}", id);
+    if let Err(err) = check_peer(&stream, access) {
+        error!("rejected persistent domain socket connection {}: {}", id, err);
+        let reply = EventMessage::reply(CURRENT_VERSION.to_string(), Event::Error(format!("{}", err))).to_json();
+        let _ = stream.write_all(format!("{}\n", reply).as_bytes());
+        return
+    }
+
+    if let Err(err) = stream.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT_SECS))) {
+        return error!("couldn't set commands socket write timeout: {}", err);
+    }
+    let mut reader = match stream.try_clone() {
+        Ok(clone) => BufReader::new(clone),
+        Err(err)  => return error!("couldn't clone commands socket: {}", err)
+    };
+    let writer = Arc::new(Mutex::new(stream));
+    clients.lock().unwrap().insert(id, writer.clone());
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0)    => break,
+            Ok(_)    => if line.trim().is_empty() { continue },
+            Err(err) => { error!("couldn't read from commands socket: {}", err); break }
+        }
+        debug!("socket input: {}", line.trim());
+
+        let resp = handle_input(line.trim(), itx.clone(), access);
+        if let Err(err) = writer.lock().unwrap().write_all(format!("{}\n", resp).as_bytes()) {
+            error!("couldn't write to commands socket: {}", err);
+            break
+        }
+    }
+
+    clients.lock().unwrap().remove(&id);
+    info!("Closed persistent domain socket connection {}", id);
+}
+
+// Parse either a versioned JSON request or a legacy text command and return
+// the encoded reply.
+fn handle_input(input: &str, itx: Arc<Mutex<Sender<Interpret>>>,
+                access: Option<&AccessPolicy>) -> String {
+    if input.trim_left().starts_with('{') {
+        let reply = match CommandMessage::from_json(input) {
+            Ok(msg) => {
+                let event = run_command(itx, access, msg.command)
+                    .unwrap_or_else(|err| Event::Error(format!("{}", err)));
+                EventMessage::reply(msg.version, event)
+            }
+            Err(err) => EventMessage::reply(CURRENT_VERSION.to_string(), Event::Error(format!("{}", err)))
+        };
+        reply.to_json()
+    } else {
+        match input.parse::<Command>().and_then(|cmd| run_command(itx, access, cmd)) {
+            Ok(event) => json::encode(&event).expect("couldn't encode Event"),
+            Err(err @ Error::AccessDenied(_)) => {
+                json::encode(&Event::Error(format!("{}", err))).expect("couldn't encode Event")
+            }
+            Err(err) => format!("{}", err)
+        }
+    }
+}
+
+// Check the connecting process before reading any of its input.
+fn check_peer(stream: &UnixStream, access: Option<&AccessPolicy>) -> Result<(), Error> {
+    match access {
+        Some(access) => {
+            let peer = try!(peer_credentials(stream));
+            debug!("socket peer: {:?}", peer);
+            access.check_peer(&peer)
+        }
+        None => Ok(())
+    }
+}
+
+fn run_command(itx: Arc<Mutex<Sender<Interpret>>>, access: Option<&AccessPolicy>,
+               cmd: Command) -> Result<Event, Error> {
+    if let Some(access) = access {
+        try!(access.check_command(&cmd));
+    }
 
-    let cmd = try!(input.parse::<Command>());
     let (etx, erx) = chan::async::<Event>();
-    itx.lock().unwrap().send(Interpret {
-        command:     cmd,
-        response_tx: Some(Arc::new(Mutex::new(etx))),
-    });
+    itx.lock().unwrap().send(Interpret::new(cmd, Some(Arc::new(Mutex::new(etx))), "socket"));
     erx.recv().ok_or(Error::Socket("internal receiver error".to_string()))
 }
 
+// Encode the subset of events supported by the legacy "0.1" format.
+fn legacy_event(event: Event) -> Option<String> {
+    match event {
+        Event::DownloadComplete(dl) => {
+            Some(json::encode(&EventWrapper {
+                version: LEGACY_VERSION.to_string(),
+                event:   "DownloadComplete".to_string(),
+                data:    dl
+            }).expect("couldn't encode DownloadComplete event"))
+        }
+
+        Event::DownloadFailed(id, reason) => {
+            Some(json::encode(&EventWrapper {
+                version: LEGACY_VERSION.to_string(),
+                event:   "DownloadFailed".to_string(),
+                data:    DownloadFailed { update_id: id, reason: reason }
+            }).expect("couldn't encode DownloadFailed event"))
+        }
+
+        _ => None
+    }
+}
+
+/// Read the process, user and group id of the connected peer with `SO_PEERCRED`.
+pub fn peer_credentials(stream: &UnixStream) -> Result<PeerCredentials, Error> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len  = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+
+    if ret == 0 {
+        Ok(PeerCredentials { pid: cred.pid, uid: cred.uid, gid: cred.gid })
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
 
-// FIXME(PRO-1322): create a proper JSON api
+/// The legacy "0.1" event format, superseded by `protocol::EventMessage`.
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug)]
 pub struct EventWrapper<E: Encodable> {
     pub version: String,
@@ -112,14 +283,19 @@ pub struct EventWrapper<E: Encodable> {
 mod tests {
     use chan;
     use crossbeam;
+    use libc;
     use rustc_serialize::json;
+    use std::collections::HashMap;
     use std::{fs, thread};
-    use std::io::{Read, Write};
+    use std::io::{BufRead, BufReader, Read, Write};
     use std::net::Shutdown;
+    use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
-    use datatype::{Command, DownloadComplete, Event};
+    use datatype::{AccessPolicy, Command, DownloadComplete, Event};
     use gateway::{Gateway, Interpret};
+    use gateway::protocol::{CommandMessage, EventMessage};
+    use health::Heartbeat;
     use super::*;
     use unix_socket::{UnixListener, UnixStream};
 
@@ -130,9 +306,13 @@ mod tests {
         let (itx, irx) = chan::sync::<Interpret>(0);
 
         thread::spawn(move || Socket {
-            commands_path: "/tmp/sota-commands.socket".to_string(),
-            events_path:   "/tmp/sota-events.socket".to_string(),
-        }.start(itx, erx));
+            commands_path:  "/tmp/sota-commands.socket".to_string(),
+            events_path:    "/tmp/sota-events.socket".to_string(),
+            events_version: "0.1".to_string(),
+            persistent:     false,
+            clients:        Arc::new(Mutex::new(HashMap::new())),
+            access:         None,
+        }.start(itx, erx, Heartbeat::default()));
         thread::sleep(Duration::from_millis(100)); // wait until socket gateway is created
 
         let path = "/tmp/sota-events.socket";
@@ -183,4 +363,163 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn socket_versioned_protocol() {
+        let (etx, erx) = chan::sync::<Event>(0);
+        let (itx, irx) = chan::sync::<Interpret>(0);
+
+        thread::spawn(move || Socket {
+            commands_path:  "/tmp/sota-commands-v1.socket".to_string(),
+            events_path:    "/tmp/sota-events-v1.socket".to_string(),
+            events_version: "1".to_string(),
+            persistent:     false,
+            clients:        Arc::new(Mutex::new(HashMap::new())),
+            access:         None,
+        }.start(itx, erx, Heartbeat::default()));
+        thread::sleep(Duration::from_millis(100)); // wait until socket gateway is created
+
+        let path = "/tmp/sota-events-v1.socket";
+        let _ = fs::remove_file(&path);
+        let server = UnixListener::bind(&path).expect("couldn't create events socket for testing");
+        etx.send(Event::InstalledSoftwareNeeded);
+
+        let (mut stream, _) = server.accept().expect("couldn't read from events socket");
+        let mut text = String::new();
+        stream.read_to_string(&mut text).unwrap();
+        let msg = EventMessage::from_json(&text).expect("couldn't decode EventMessage");
+        assert_eq!(msg, EventMessage::new(Event::InstalledSoftwareNeeded));
+
+        thread::spawn(move || {
+            let _ = etx; // move into this scope
+            loop {
+                let interpret = irx.recv().expect("gtx is closed");
+                match interpret.command {
+                    Command::StartInstall(id) => {
+                        let tx = interpret.response_tx.unwrap();
+                        tx.lock().unwrap().send(Event::InstallingUpdate(id));
+                    }
+                    _ => panic!("expected StartInstall"),
+                }
+            }
+        });
+
+        let request = |input: &str| -> EventMessage {
+            let mut stream = UnixStream::connect("/tmp/sota-commands-v1.socket").expect("couldn't connect to socket");
+            stream.write_all(input.as_bytes()).expect("couldn't write to stream");
+            stream.shutdown(Shutdown::Write).expect("couldn't shut down writing");
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).expect("couldn't read from stream");
+            EventMessage::from_json(&resp).expect("couldn't decode EventMessage")
+        };
+
+        let cmd = CommandMessage { version: "1".to_string(), command: Command::StartInstall("1".to_string()) };
+        assert_eq!(request(&cmd.to_json()), EventMessage::reply("1".to_string(), Event::InstallingUpdate("1".to_string())));
+
+        match request(r#"{ "version": "2", "command": "StartInstall", "data": "1" }"#).event {
+            Event::Error(err) => assert!(err.contains("supported")),
+            _                 => panic!("expected an unsupported version error")
+        }
+    }
+
+    #[test]
+    fn socket_persistent_connection() {
+        let (etx, erx) = chan::sync::<Event>(0);
+        let (itx, irx) = chan::sync::<Interpret>(0);
+
+        thread::spawn(move || Socket {
+            commands_path:  "/tmp/sota-commands-persistent.socket".to_string(),
+            events_path:    "/tmp/sota-events-persistent.socket".to_string(),
+            events_version: "1".to_string(),
+            persistent:     true,
+            clients:        Arc::new(Mutex::new(HashMap::new())),
+            access:         None,
+        }.start(itx, erx, Heartbeat::default()));
+        thread::sleep(Duration::from_millis(100)); // wait until socket gateway is created
+
+        thread::spawn(move || {
+            loop {
+                let interpret = irx.recv().expect("gtx is closed");
+                match interpret.command {
+                    Command::StartDownload(id) => {
+                        let tx = interpret.response_tx.unwrap();
+                        tx.lock().unwrap().send(Event::DownloadingUpdate(id));
+                    }
+                    _ => panic!("expected StartDownload"),
+                }
+            }
+        });
+
+        let mut stream = UnixStream::connect("/tmp/sota-commands-persistent.socket").expect("couldn't connect to socket");
+        let mut reader = BufReader::new(stream.try_clone().expect("couldn't clone stream"));
+        let mut read_line = || -> String {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("couldn't read from stream");
+            line
+        };
+
+        for id in 0..3 {
+            stream.write_all(format!("dl {}\n", id).as_bytes()).expect("couldn't write to stream");
+            let ev: Event = json::decode(&read_line()).expect("couldn't decode json event");
+            assert_eq!(ev, Event::DownloadingUpdate(format!("{}", id)));
+        }
+
+        let cmd = CommandMessage { version: "1".to_string(), command: Command::StartDownload("3".to_string()) };
+        stream.write_all(format!("{}\n", cmd.to_json()).as_bytes()).expect("couldn't write to stream");
+        let msg = EventMessage::from_json(&read_line()).expect("couldn't decode EventMessage");
+        assert_eq!(msg.event, Event::DownloadingUpdate("3".to_string()));
+        assert!(msg.reply);
+
+        etx.send(Event::InstalledSoftwareNeeded);
+        let msg = EventMessage::from_json(&read_line()).expect("couldn't decode EventMessage");
+        assert_eq!(msg.event, Event::InstalledSoftwareNeeded);
+        assert!(!msg.reply);
+    }
+
+    #[test]
+    fn socket_drops_stalled_clients() {
+        let path = "/tmp/sota-test-stalled.socket";
+        let _    = fs::remove_file(&path);
+        let _    = fs::remove_file("/tmp/sota-test-stalled-events.socket");
+        let server  = UnixListener::bind(&path).expect("couldn't create socket for testing");
+        let _client = UnixStream::connect(&path).expect("couldn't connect to socket"); // never reads
+        let (stream, _) = server.accept().expect("couldn't accept connection");
+        stream.set_write_timeout(Some(Duration::from_millis(100))).expect("couldn't set write timeout");
+
+        let socket = Socket {
+            commands_path:  "/tmp/sota-test-stalled-commands.socket".to_string(),
+            events_path:    "/tmp/sota-test-stalled-events.socket".to_string(),
+            events_version: "1".to_string(),
+            persistent:     true,
+            clients:        Arc::new(Mutex::new(HashMap::new())),
+            access:         None,
+        };
+        socket.clients.lock().unwrap().insert(0, Arc::new(Mutex::new(stream)));
+
+        let info = String::from_utf8(vec![b'x'; 64 * 1024]).unwrap();
+        for _ in 0..100 {
+            if socket.clients.lock().unwrap().is_empty() { break }
+            socket.pulse(Event::FoundSystemInfo(info.clone()));
+        }
+        assert!(socket.clients.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn socket_peer_credentials() {
+        let path = "/tmp/sota-test-peercred.socket";
+        let _    = fs::remove_file(&path);
+        let server = UnixListener::bind(&path).expect("couldn't create socket for testing");
+        let client = UnixStream::connect(&path).expect("couldn't connect to socket");
+        let (stream, _) = server.accept().expect("couldn't accept connection");
+
+        let peer = peer_credentials(&stream).expect("couldn't read peer credentials");
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+        assert_eq!(peer.gid, unsafe { libc::getgid() });
+
+        let allow = AccessPolicy { uids: Some(vec![peer.uid]), ..AccessPolicy::default() };
+        assert!(allow.check_peer(&peer).is_ok());
+        let deny  = AccessPolicy { uids: Some(vec![peer.uid + 1]), ..AccessPolicy::default() };
+        assert!(deny.check_peer(&peer).is_err());
+        drop(client);
+    }
 }
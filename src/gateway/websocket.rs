@@ -1,77 +1,250 @@
 use chan;
 use chan::Sender;
 use rustc_serialize::json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::str;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+use time;
+use url::percent_encoding::percent_decode;
 use ws;
-use ws::{CloseCode, Handler, Handshake, Message, Sender as WsSender};
+use ws::{CloseCode, Frame, Handler, Handshake, Message, Request, Sender as WsSender};
 use ws::util::Token;
 
-use datatype::{Command, Error, Event};
+use datatype::{AccessPolicy, Command, Error, Event};
+use datatype::update_report::decode_json;
 use super::gateway::{Gateway, Interpret};
 
 
 /// The `Websocket` gateway allows connected clients to listen to `Event`s that
-/// happen in the SOTA client.
+/// happen in the SOTA client. An optional `AccessPolicy` requires clients to
+/// present a token on connection and restricts the commands they may send.
+///
+/// Clients are pinged every `keepalive` seconds and removed if no frame is
+/// received within two intervals. A `keepalive` of 0 disables the pings.
 pub struct Websocket {
-    pub server:  String,
-    pub clients: Arc<Mutex<HashMap<Token, WsSender>>>
+    pub server:    String,
+    pub clients:   Arc<Mutex<HashMap<Token, WsClient>>>,
+    pub access:    Option<AccessPolicy>,
+    pub keepalive: u64,
 }
 
 impl Gateway for Websocket {
     fn initialize(&mut self, itx: Sender<Interpret>) -> Result<(), String> {
         let clients = self.clients.clone();
         let addr    = self.server.clone();
+        let access  = self.access.clone();
+
+        let (ready_tx, ready_rx) = chan::sync::<Result<(), String>>(1);
 
         thread::spawn(move || {
-            ws::listen(&addr as &str, |out| {
+            let server = ws::WebSocket::new(|out| {
                 WebsocketHandler {
-                    out:     out,
-                    itx:     itx.clone(),
-                    clients: clients.clone()
+                    out:        out,
+                    itx:        itx.clone(),
+                    clients:    clients.clone(),
+                    access:     access.clone(),
+                    authorized: false
+                }
+            }).and_then(|server| server.bind(&addr as &str));
+
+            match server {
+                Ok(server) => {
+                    ready_tx.send(Ok(()));
+                    server.run().expect("couldn't run websocket listener");
                 }
-            }).expect("couldn't start websocket listener");
+                Err(err) => ready_tx.send(Err(format!("couldn't start websocket listener: {}", err)))
+            }
         });
+        try!(ready_rx.recv().unwrap_or(Err("websocket listener exited".to_string())));
+
+        if self.keepalive > 0 {
+            let clients  = self.clients.clone();
+            let interval = self.keepalive;
+            thread::spawn(move || start_keepalive(clients, interval));
+        }
 
         Ok(info!("Websocket gateway started at {}.", self.server))
     }
 
     fn pulse(&self, event: Event) {
-        let json = encode(event);
-        for (_, out) in self.clients.lock().unwrap().iter() {
-            let _ = out.send(Message::Text(json.clone()));
+        let json = encode(event.clone());
+        for (_, client) in self.clients.lock().unwrap().iter() {
+            if client.subscriptions.wants(event.name()) {
+                let _ = client.out.send(Message::Text(json.clone()));
+            }
+        }
+    }
+}
+
+// Ping each client every `interval` seconds, closing any client that hasn't
+// sent a frame within the last two intervals.
+fn start_keepalive(clients: Arc<Mutex<HashMap<Token, WsClient>>>, interval: u64) {
+    let tick = chan::tick(Duration::from_secs(interval));
+    loop {
+        let _ = tick.recv();
+        let now = time::get_time().sec;
+        let mut clients = clients.lock().unwrap();
+
+        let dead = clients.iter()
+            .filter(|&(_, client)| now - client.last_seen > 2 * interval as i64)
+            .map(|(token, _)| *token)
+            .collect::<Vec<Token>>();
+        for token in dead {
+            debug!("removing unresponsive websocket client: {:?}", token);
+            clients.remove(&token).map(|client| client.out.close(CloseCode::Away));
+        }
+
+        for (_, client) in clients.iter() {
+            let _ = client.out.ping(Vec::new());
+        }
+    }
+}
+
+
+/// A connected websocket client and the `Event`s it has subscribed to.
+pub struct WsClient {
+    pub out:           WsSender,
+    pub subscriptions: Subscriptions,
+    pub last_seen:     i64,
+}
+
+impl WsClient {
+    /// Create a new client that is subscribed to all events.
+    pub fn new(out: WsSender) -> WsClient {
+        WsClient { out: out, subscriptions: Subscriptions::default(), last_seen: time::get_time().sec }
+    }
+}
+
+
+/// The set of `Event` names that a client wishes to receive. When `all` is set
+/// then `events` holds the exclusions, otherwise it holds the inclusions.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Subscriptions {
+    all:    bool,
+    events: HashSet<String>,
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Subscriptions { all: true, events: HashSet::new() }
+    }
+}
+
+impl Subscriptions {
+    /// Start receiving these events, or all events when a name is "*".
+    pub fn subscribe(&mut self, names: &[String]) {
+        for name in names {
+            match (name.as_str(), self.all) {
+                ("*", _)   => { self.all = true; self.events.clear(); }
+                (_, true)  => { self.events.remove(name); }
+                (_, false) => { self.events.insert(name.clone()); }
+            }
+        }
+    }
+
+    /// Stop receiving these events, or all events when a name is "*".
+    pub fn unsubscribe(&mut self, names: &[String]) {
+        for name in names {
+            match (name.as_str(), self.all) {
+                ("*", _)   => { self.all = false; self.events.clear(); }
+                (_, true)  => { self.events.insert(name.clone()); }
+                (_, false) => { self.events.remove(name); }
+            }
         }
     }
+
+    /// Indicates whether the event name should be sent to the client.
+    pub fn wants(&self, name: &str) -> bool {
+        self.all != self.events.contains(name)
+    }
+}
+
+
+/// A `Command` with a client-chosen id that is echoed back in the `WsResponse`.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone)]
+pub struct WsRequest {
+    pub id:      Option<String>,
+    pub command: Command,
+}
+
+/// The outcome `Event` of a `WsRequest` with the id of the original request.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone)]
+pub struct WsResponse {
+    pub id:    String,
+    pub event: Event,
+}
+
+/// A control message for changing the `Event`s sent to a client.
+#[derive(RustcDecodable, RustcEncodable, PartialEq, Eq, Debug, Clone)]
+pub struct WsControl {
+    pub subscribe:   Option<Vec<String>>,
+    pub unsubscribe: Option<Vec<String>>,
+}
+
+/// Each incoming message is either a plain `Command`, a `WsRequest` or a
+/// `WsControl` message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum WsIncoming {
+    Command(Command),
+    Request(WsRequest),
+    Control(WsControl),
 }
 
 
 pub struct WebsocketHandler {
-    out:     WsSender,
-    itx:     Sender<Interpret>,
-    clients: Arc<Mutex<HashMap<Token, WsSender>>>
+    out:        WsSender,
+    itx:        Sender<Interpret>,
+    clients:    Arc<Mutex<HashMap<Token, WsClient>>>,
+    access:     Option<AccessPolicy>,
+    authorized: bool
 }
 
 impl Handler for WebsocketHandler {
     fn on_message(&mut self, msg: Message) -> ws::Result<()> {
         debug!("received websocket message: {:?}", msg);
-        msg.as_text().or_else(|err| {
+        let text = try!(msg.as_text().map_err(|err| {
             error!("websocket on_message text error: {}", err);
-            Err(err)
-        }).and_then(|msg| match decode(msg) {
-            Ok(cmd) => Ok(self.forward_command(cmd)),
+            err
+        }));
+
+        if !self.authorized {
+            error!("ignoring websocket message from unauthorized client {:?}", self.out.token());
+            let err = Error::AccessDenied("unauthorized client".to_string());
+            let _ = self.out.send(Message::Text(encode(Event::Error(format!("{}", err)))));
+            return Ok(())
+        }
 
-            Err(Error::Websocket(err)) => {
+        match decode(text) {
+            Ok(WsIncoming::Command(cmd)) => self.forward_command(None, cmd),
+            Ok(WsIncoming::Request(req)) => self.forward_command(req.id, req.command),
+            Ok(WsIncoming::Control(ctl)) => self.update_subscriptions(ctl),
+            Err(err) => {
                 error!("websocket on_message error: {}", err);
-                Err(err)
+                let _ = self.out.send(Message::Text(encode(Event::Error(format!("{}", err)))));
             }
+        }
+        Ok(())
+    }
 
-            Err(err) => panic!("unexpected websocket on_message error: {}", err)
-        })
+    fn on_frame(&mut self, frame: Frame) -> ws::Result<Option<Frame>> {
+        self.clients.lock().unwrap().get_mut(&self.out.token())
+            .map(|client| client.last_seen = time::get_time().sec);
+        Ok(Some(frame))
     }
 
-    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
-        let _ = self.clients.lock().unwrap().insert(self.out.token(), self.out.clone());
+    fn on_open(&mut self, shake: Handshake) -> ws::Result<()> {
+        if let Some(ref access) = self.access {
+            if let Err(err) = access.check_token(request_token(&shake.request).as_ref().map(|t| t.as_str())) {
+                error!("rejected websocket client {:?}: {}", self.out.token(), err);
+                let _ = self.out.send(Message::Text(encode(Event::Error(format!("{}", err)))));
+                return self.out.close(CloseCode::Policy);
+            }
+        }
+
+        self.authorized = true;
+        let _ = self.clients.lock().unwrap().insert(self.out.token(), WsClient::new(self.out.clone()));
         Ok(debug!("new websocket client: {:?}", self.out.token()))
     }
 
@@ -86,22 +259,84 @@ impl Handler for WebsocketHandler {
 }
 
 impl WebsocketHandler {
-    fn forward_command(&self, cmd: Command) {
-        let (etx, erx) = chan::sync::<Event>(0);
-        let etx        = Arc::new(Mutex::new(etx.clone()));
-        self.itx.send(Interpret { command: cmd, response_tx: Some(etx) });
+    fn forward_command(&self, id: Option<String>, cmd: Command) {
+        if let Some(ref access) = self.access {
+            if let Err(err) = access.check_command(&cmd) {
+                error!("rejected websocket command {}: {}", cmd, err);
+                let _ = self.out.send(Message::Text(encode_response(id, Event::Error(format!("{}", err)))));
+                return
+            }
+        }
+
+        // wait for the reply off the event loop so other clients are still read
+        let out = self.out.clone();
+        let itx = self.itx.clone();
+        thread::spawn(move || {
+            let (etx, erx) = chan::sync::<Event>(0);
+            let etx        = Arc::new(Mutex::new(etx.clone()));
+            itx.send(Interpret::new(cmd, Some(etx), "websocket"));
 
-        let e = erx.recv().expect("websocket response_tx is closed");
-        let _ = self.out.send(Message::Text(encode(e)));
+            match erx.recv() {
+                Some(e) => { let _ = out.send(Message::Text(encode_response(id, e))); }
+                None    => error!("websocket response_tx is closed")
+            }
+        });
+    }
+
+    fn update_subscriptions(&self, ctl: WsControl) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.get_mut(&self.out.token()).map(|client| {
+            ctl.subscribe.as_ref().map(|names| client.subscriptions.subscribe(names));
+            ctl.unsubscribe.as_ref().map(|names| client.subscriptions.unsubscribe(names));
+            debug!("websocket client {:?} subscriptions: {:?}", self.out.token(), client.subscriptions);
+        });
     }
 }
 
+// Read the token from either an `Authorization: Bearer` header or a `token`
+// query parameter for clients that can't set headers.
+fn request_token(req: &Request) -> Option<String> {
+    req.header("Authorization")
+        .and_then(|value| str::from_utf8(value).ok())
+        .and_then(|value| if value.starts_with("Bearer ") { Some(value[7..].to_string()) } else { None })
+        .or_else(|| query_token(req.resource()))
+}
+
+// Percent-decode the `token` query parameter of the requested resource.
+fn query_token(resource: &str) -> Option<String> {
+    resource.splitn(2, '?').nth(1).and_then(|query| {
+        query.split('&')
+            .find(|param| param.starts_with("token="))
+            .and_then(|param| percent_decode(param[6..].as_bytes()).decode_utf8().ok())
+            .map(|token| token.into_owned())
+    })
+}
+
 fn encode(event: Event) -> String {
     json::encode(&event).expect("Error encoding event into JSON")
 }
 
-fn decode(s: &str) -> Result<Command, Error> {
-    Ok(try!(json::decode::<Command>(s)))
+fn encode_response(id: Option<String>, event: Event) -> String {
+    match id {
+        Some(id) => json::encode(&WsResponse { id: id, event: event }).expect("Error encoding response into JSON"),
+        None     => encode(event)
+    }
+}
+
+// Any JSON object decodes as a `WsControl` since every field is optional, so
+// a control message must set at least one field to be accepted.
+fn decode(s: &str) -> Result<WsIncoming, Error> {
+    let cmd_err = match decode_json::<Command>(s) {
+        Ok(cmd)  => return Ok(WsIncoming::Command(cmd)),
+        Err(err) => err
+    };
+    if let Ok(req) = decode_json::<WsRequest>(s) {
+        return Ok(WsIncoming::Request(req));
+    }
+    match json::decode::<WsControl>(s) {
+        Ok(ref ctl) if ctl.subscribe.is_some() || ctl.unsubscribe.is_some() => Ok(WsIncoming::Control(ctl.clone())),
+        _ => Err(Error::Command(format!("couldn't decode websocket message: {}", cmd_err)))
+    }
 }
 
 
@@ -118,6 +353,7 @@ mod tests {
 
     use datatype::{Command, Event};
     use gateway::{Gateway, Interpret};
+    use health::Heartbeat;
     use super::*;
 
 
@@ -129,9 +365,11 @@ mod tests {
 
         thread::spawn(move || {
             Websocket {
-                server:  "localhost:3012".to_string(),
-                clients: Arc::new(Mutex::new(HashMap::new()))
-            }.start(itx, erx);
+                server:    "localhost:3012".to_string(),
+                clients:   Arc::new(Mutex::new(HashMap::new())),
+                access:    None,
+                keepalive: 0
+            }.start(itx, erx, Heartbeat::default());
         });
         thread::spawn(move || {
             let _ = etx; // move into this scope
@@ -164,4 +402,54 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn decode_incoming_messages() {
+        assert_eq!(decode(r#"{ "variant": "StartDownload", "fields": ["1"] }"#).unwrap(),
+                   WsIncoming::Command(Command::StartDownload("1".to_string())));
+        assert_eq!(decode(r#"{ "id": "abc", "command": { "variant": "GetUpdateRequests", "fields": [] } }"#).unwrap(),
+                   WsIncoming::Request(WsRequest { id: Some("abc".to_string()), command: Command::GetUpdateRequests }));
+        assert_eq!(decode(r#"{ "subscribe": ["DownloadComplete"] }"#).unwrap(),
+                   WsIncoming::Control(WsControl { subscribe: Some(vec!["DownloadComplete".to_string()]), unsubscribe: None }));
+        assert!(decode("not json").is_err());
+        assert!(decode(r#"{ "variant": "NoSuchCommand", "fields": [] }"#).is_err());
+        assert!(decode(r#"{ "variant": "StartDownload", "fields": [1, 2] }"#).is_err());
+        assert!(decode(r#"{ "subscribe": null }"#).is_err());
+        assert!(decode("{}").is_err());
+    }
+
+    #[test]
+    fn encode_correlated_response() {
+        let resp = encode_response(Some("abc".to_string()), Event::NoUpdateRequests);
+        assert_eq!(json::decode::<WsResponse>(&resp).unwrap(),
+                   WsResponse { id: "abc".to_string(), event: Event::NoUpdateRequests });
+        assert_eq!(json::decode::<Event>(&encode_response(None, Event::NoUpdateRequests)).unwrap(),
+                   Event::NoUpdateRequests);
+    }
+
+    #[test]
+    fn decode_query_token() {
+        assert_eq!(query_token("/?token=abc"), Some("abc".to_string()));
+        assert_eq!(query_token("/events?id=1&token=a%2Bb%26c%3D"), Some("a+b&c=".to_string()));
+        assert_eq!(query_token("/?token=%FF"), None);
+        assert_eq!(query_token("/"), None);
+    }
+
+    #[test]
+    fn event_subscriptions() {
+        let mut subs = Subscriptions::default();
+        assert!(subs.wants("DownloadComplete"));
+
+        subs.unsubscribe(&["DownloadComplete".to_string()]);
+        assert!(!subs.wants("DownloadComplete"));
+        assert!(subs.wants("InstallComplete"));
+
+        subs.unsubscribe(&["*".to_string()]);
+        subs.subscribe(&["InstallComplete".to_string()]);
+        assert!(subs.wants("InstallComplete"));
+        assert!(!subs.wants("DownloadComplete"));
+
+        subs.subscribe(&["*".to_string()]);
+        assert!(subs.wants("DownloadComplete"));
+    }
 }
@@ -0,0 +1,316 @@
+use libc;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use time;
+use unix_socket::UnixDatagram;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    Busy(u64),
+    Stopped,
+}
+
+/// The liveness of a single component.
+#[derive(RustcEncodable, Debug, PartialEq, Eq)]
+pub struct ComponentHealth {
+    pub name:     String,
+    pub state:    String,
+    pub busy_sec: u64,
+    pub healthy:  bool,
+}
+
+/// The liveness of all registered components as returned by `GET /health`.
+#[derive(RustcEncodable, Debug, PartialEq, Eq)]
+pub struct HealthReport {
+    pub healthy:    bool,
+    pub components: Vec<ComponentHealth>,
+}
+
+
+/// A registry of the interpreter and gateway threads. A component is healthy
+/// while idle or when it has been busy for less than the timeout, and becomes
+/// unhealthy once its thread stops. Cloned instances share the same registry.
+#[derive(Clone)]
+pub struct Health {
+    components: Arc<Mutex<BTreeMap<String, State>>>,
+    timeout_ns: u64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new(Duration::from_secs(900))
+    }
+}
+
+impl Health {
+    /// Create a new registry where components busy for longer than the
+    /// timeout are considered stuck.
+    pub fn new(timeout: Duration) -> Health {
+        Health {
+            components: Arc::new(Mutex::new(BTreeMap::new())),
+            timeout_ns: timeout.as_secs() * 1_000_000_000 + timeout.subsec_nanos() as u64,
+        }
+    }
+
+    /// Register a new component, returning the `Heartbeat` it should update.
+    pub fn register(&self, name: &str) -> Heartbeat {
+        self.set(name, State::Idle);
+        Heartbeat { name: name.to_string(), health: self.clone() }
+    }
+
+    fn set(&self, name: &str, state: State) {
+        self.components.lock().unwrap().insert(name.to_string(), state);
+    }
+
+    fn progress(&self, name: &str) {
+        let mut components = self.components.lock().unwrap();
+        if let Some(state) = components.get_mut(name) {
+            if let State::Busy(_) = *state {
+                *state = State::Busy(time::precise_time_ns());
+            }
+        }
+    }
+
+    /// Report the current liveness of each component.
+    pub fn report(&self) -> HealthReport {
+        let now        = time::precise_time_ns();
+        let components = self.components.lock().unwrap().iter().map(|(name, state)| {
+            let (label, busy_ns, healthy) = match *state {
+                State::Idle          => ("idle", 0, true),
+                State::Busy(started) => {
+                    let busy = now.saturating_sub(started);
+                    ("busy", busy, busy <= self.timeout_ns)
+                }
+                State::Stopped       => ("stopped", 0, false),
+            };
+            ComponentHealth {
+                name:     name.clone(),
+                state:    label.to_string(),
+                busy_sec: busy_ns / 1_000_000_000,
+                healthy:  healthy,
+            }
+        }).collect::<Vec<_>>();
+
+        HealthReport {
+            healthy:    components.iter().all(|component| component.healthy),
+            components: components,
+        }
+    }
+}
+
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Health, String)>> = RefCell::new(None);
+}
+
+/// Mark the busy component of the current thread as still making progress,
+/// such as while a long download is being received, so that it is not
+/// considered stuck.
+pub fn progress() {
+    CURRENT.with(|current| {
+        current.borrow().as_ref().map(|&(ref health, ref name)| health.progress(name));
+    });
+}
+
+
+/// Updates the liveness of a single registered component. The component is
+/// marked as stopped when the `Heartbeat` is dropped, such as on a panic.
+pub struct Heartbeat {
+    name:   String,
+    health: Health,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Health::default().register("unmonitored")
+    }
+}
+
+impl Heartbeat {
+    /// Mark the start of some work on the current thread.
+    pub fn busy(&self) {
+        self.health.set(&self.name, State::Busy(time::precise_time_ns()));
+        CURRENT.with(|current| *current.borrow_mut() = Some((self.health.clone(), self.name.clone())));
+    }
+
+    /// Mark the end of some work.
+    pub fn idle(&self) {
+        self.health.set(&self.name, State::Idle);
+        CURRENT.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.health.set(&self.name, State::Stopped);
+    }
+}
+
+
+/// Sends service status updates to systemd with the `sd_notify` protocol.
+pub struct Notifier {
+    socket:   String,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Create a new notifier for the socket, sending watchdog keep-alives at
+    /// half the watchdog interval.
+    pub fn new(socket: &str, watchdog: Option<Duration>) -> Notifier {
+        let socket = if socket.starts_with('@') { format!("\0{}", &socket[1..]) } else { socket.to_string() };
+        Notifier { socket: socket, watchdog: watchdog }
+    }
+
+    /// Read the socket from `$NOTIFY_SOCKET` and the watchdog interval from
+    /// `$WATCHDOG_USEC` when `$WATCHDOG_PID` is unset or matches this process.
+    pub fn from_env() -> Option<Notifier> {
+        env::var("NOTIFY_SOCKET").ok().map(|socket| {
+            let pid      = unsafe { libc::getpid() };
+            let for_us   = env::var("WATCHDOG_PID").ok()
+                .map_or(true, |watchdog_pid| watchdog_pid.parse::<libc::pid_t>().ok() == Some(pid));
+            let watchdog = env::var("WATCHDOG_USEC").ok()
+                .and_then(|usec| usec.parse::<u64>().ok())
+                .and_then(|usec| if for_us && usec > 0 { Some(Duration::from_millis(usec / 1000)) } else { None });
+            Notifier::new(&socket, watchdog)
+        })
+    }
+
+    /// Send a newline-separated list of `KEY=VALUE` assignments.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        let socket = try!(UnixDatagram::unbound());
+        socket.send_to(state.as_bytes(), &self.socket).map(|_| ())
+    }
+
+    /// Tell systemd that start-up is finished.
+    pub fn ready(&self) -> io::Result<()> {
+        self.notify("READY=1")
+    }
+
+    /// Send watchdog keep-alives in the background while all components are
+    /// healthy, otherwise update the service status with the stuck components.
+    pub fn start_watchdog(self, health: Health) {
+        let interval = match self.watchdog {
+            Some(watchdog) => watchdog / 2,
+            None           => return
+        };
+
+        info!("Sending systemd watchdog notifications every {:?}.", interval);
+        thread::spawn(move || loop {
+            let report = health.report();
+            let result = if report.healthy {
+                self.notify("WATCHDOG=1")
+            } else {
+                let stuck = report.components.iter()
+                    .filter(|component| !component.healthy)
+                    .map(|component| format!("{} ({})", component.name, component.state))
+                    .collect::<Vec<_>>();
+                error!("withholding watchdog notification for unhealthy components: {}", stuck.join(", "));
+                self.notify(&format!("STATUS=unhealthy: {}", stuck.join(", ")))
+            };
+            result.unwrap_or_else(|err| error!("couldn't notify systemd: {}", err));
+            thread::sleep(interval);
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{str, thread};
+    use std::time::Duration;
+    use unix_socket::UnixDatagram;
+
+    use super::*;
+    use package_manager::TestDir;
+
+
+    #[test]
+    fn test_health_report() {
+        let health = Health::new(Duration::from_millis(50));
+        let first  = health.register("first");
+        let second = health.register("second");
+        assert!(health.report().healthy);
+
+        first.busy();
+        assert!(health.report().healthy);
+        thread::sleep(Duration::from_millis(100));
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(report.components[0].state, "busy");
+        assert!(!report.components[0].healthy);
+        assert!(report.components[1].healthy);
+
+        first.idle();
+        assert!(health.report().healthy);
+        drop(second);
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(report.components[1].state, "stopped");
+    }
+
+    #[test]
+    fn test_busy_with_progress() {
+        let health    = Health::new(Duration::from_millis(100));
+        let heartbeat = health.register("global_interpreter");
+        heartbeat.busy();
+        for _ in 0..6 {
+            thread::sleep(Duration::from_millis(40));
+            progress();
+            assert!(health.report().healthy);
+        }
+        assert_eq!(health.report().components[0].state, "busy");
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(!health.report().healthy);
+        heartbeat.idle();
+        progress();
+        assert_eq!(health.report().components[0].state, "idle");
+
+        let other = health.clone();
+        thread::spawn(move || {
+            let heartbeat = other.register("other");
+            heartbeat.busy();
+            thread::sleep(Duration::from_millis(150));
+            progress();
+            assert!(other.report().healthy);
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn test_notify_protocol() {
+        let dir      = TestDir::new("sota-test-notify");
+        let path     = format!("{}/notify.socket", dir.0);
+        let socket   = UnixDatagram::bind(&path).expect("couldn't bind notify socket");
+        let mut buf  = [0; 256];
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut recv = || {
+            let n = socket.recv(&mut buf).expect("couldn't receive notification");
+            str::from_utf8(&buf[..n]).unwrap().to_string()
+        };
+
+        let health    = Health::new(Duration::from_millis(50));
+        let heartbeat = health.register("global_interpreter");
+        let notifier  = Notifier::new(&path, Some(Duration::from_millis(20)));
+        notifier.ready().unwrap();
+        assert_eq!(recv(), "READY=1");
+
+        notifier.start_watchdog(health.clone());
+        assert_eq!(recv(), "WATCHDOG=1");
+        heartbeat.busy();
+        loop {
+            let msg = recv();
+            if msg.starts_with("STATUS=") {
+                assert_eq!(msg, "STATUS=unhealthy: global_interpreter (busy)");
+                break
+            }
+            assert_eq!(msg, "WATCHDOG=1");
+        }
+    }
+}
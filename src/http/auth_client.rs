@@ -10,6 +10,7 @@ use hyper::status::StatusCode;
 use std::{io, mem};
 use std::io::{ErrorKind, Write};
 use std::str;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 use time;
 
@@ -161,6 +162,9 @@ impl Handler<Stream> for AuthHandler {
         } else if let None = resp.headers().get::<ContentLength>() {
             self.send_response(ResponseData { code: *resp.status(), body: Vec::new() });
             Next::end()
+        } else if let Some(err) = resp.headers().get::<ContentLength>().and_then(|len| self.check_size(len.0)) {
+            self.resp_tx.send(Response::Error(err));
+            Next::end()
         } else {
             self.resp_code = *resp.status();
             Next::read()
@@ -179,7 +183,11 @@ impl Handler<Stream> for AuthHandler {
 
             Ok(n) => {
                 trace!("{} more response bytes read", n);
-                Next::read()
+                self.req.received.as_ref().map(|received| received.fetch_add(n as usize, Ordering::SeqCst));
+                match self.check_size(self.resp_body.len() as u64) {
+                    Some(err) => { self.resp_tx.send(Response::Error(err)); Next::end() }
+                    None      => Next::read()
+                }
             }
 
             Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
@@ -203,6 +211,14 @@ impl Handler<Stream> for AuthHandler {
 }
 
 impl AuthHandler {
+    fn check_size(&self, size: u64) -> Option<Error> {
+        self.req.max_body.and_then(|max| if size > max {
+            Some(Error::DiskFull(format!("response of {} bytes exceeds the {} bytes available", size, max)))
+        } else {
+            None
+        })
+    }
+
     fn send_response(&mut self, resp: ResponseData) {
         if resp.code == StatusCode::Unauthorized || resp.code == StatusCode::Forbidden {
             self.resp_tx.send(Response::Error(Error::HttpAuth(resp)));
@@ -220,9 +236,11 @@ impl AuthHandler {
                 // drop Authorization Header on redirect
                 let client  = AuthClient::default();
                 let resp_rx = client.send_request(Request {
-                    url:    url,
-                    method: self.req.method.clone(),
-                    body:   mem::replace(&mut self.req.body, None),
+                    url:      url,
+                    method:   self.req.method.clone(),
+                    body:     mem::replace(&mut self.req.body, None),
+                    max_body: self.req.max_body,
+                    received: self.req.received.clone(),
                 });
                 self.resp_tx.send(resp_rx.recv().expect("no redirect_request response"))
             }).unwrap_or_else(|err| self.resp_tx.send(Response::Error(Error::from(err)))),
@@ -3,6 +3,8 @@ use chan::{Sender, Receiver};
 use hyper::status::StatusCode;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 
 use datatype::{Error, Method, Url};
 
@@ -19,15 +21,28 @@ pub trait Client {
     }
 
     fn get(&self, url: Url, body: Option<Vec<u8>>) -> Receiver<Response> {
-        self.send_request(Request { method: Method::Get, url: url, body: body })
+        self.send_request(Request { method: Method::Get, url: url, body: body, max_body: None, received: None })
+    }
+
+    /// Send a GET request that fails with `Error::DiskFull` instead of reading
+    /// a response body larger than `max_body` bytes. The number of body bytes
+    /// read so far is added to `received`.
+    fn download(&self, url: Url, max_body: u64, received: Arc<AtomicUsize>) -> Receiver<Response> {
+        self.send_request(Request {
+            method:   Method::Get,
+            url:      url,
+            body:     None,
+            max_body: Some(max_body),
+            received: Some(received),
+        })
     }
 
     fn post(&self, url: Url, body: Option<Vec<u8>>) -> Receiver<Response> {
-        self.send_request(Request { method: Method::Post, url: url, body: body })
+        self.send_request(Request { method: Method::Post, url: url, body: body, max_body: None, received: None })
     }
 
     fn put(&self, url: Url, body: Option<Vec<u8>>) -> Receiver<Response> {
-        self.send_request(Request { method: Method::Put, url: url, body: body })
+        self.send_request(Request { method: Method::Put, url: url, body: body, max_body: None, received: None })
     }
 
     fn is_testing(&self) -> bool { false }
@@ -35,11 +50,15 @@ pub trait Client {
 
 
 /// A simplified representation of an HTTP request for use in the client.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Request {
     pub method: Method,
-    pub url:    Url,
-    pub body:   Option<Vec<u8>>
+    pub url:      Url,
+    pub body:     Option<Vec<u8>>,
+    /// The maximum size of the response body to accept.
+    pub max_body: Option<u64>,
+    /// A running count of the response body bytes read.
+    pub received: Option<Arc<AtomicUsize>>,
 }
 
 
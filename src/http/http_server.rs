@@ -15,6 +15,10 @@ pub trait Server<T: Transport>: Send {
     fn headers(&mut self, req: HyperRequest<T>);
     fn request(&mut self, body: Vec<u8>);
     fn response(&mut self) -> (StatusCode, Option<Vec<u8>>);
+
+    fn content_type(&self) -> Mime {
+        Mime(TopLevel::Application, SubLevel::Json, vec![(Attr::Charset, Value::Utf8)])
+    }
 }
 
 
@@ -77,8 +81,7 @@ impl<T: Transport> Handler<T> for ServerHandler<T> {
         info!("on_response: status {}", resp.status());
 
         let mut headers = resp.headers_mut();
-        headers.set(ContentType(Mime(TopLevel::Application, SubLevel::Json,
-                                     vec![(Attr::Charset, Value::Utf8)])));
+        headers.set(ContentType(self.server.content_type()));
         body.map_or_else(Next::end, |body| {
             headers.set(ContentLength(body.len() as u64));
             self.resp_body = body;
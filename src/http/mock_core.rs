@@ -0,0 +1,219 @@
+use chan;
+use hyper::StatusCode;
+use hyper::net::{HttpStream, Transport};
+use hyper::server::{Server as HyperServer, Request as HyperRequest};
+use rustc_serialize::json;
+use std::collections::HashMap;
+use std::{mem, str, thread};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use datatype::{AccessToken, UpdateRequest, UpdateRequestId, Url};
+use http::{Server, ServerHandler};
+
+
+/// A request received by the `MockCore` server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MockRequest {
+    pub method: String,
+    pub path:   String,
+    pub auth:   Option<String>,
+    pub body:   Vec<u8>,
+}
+
+impl MockRequest {
+    /// The request body as a UTF-8 string.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+struct Failure {
+    method:    String,
+    path:      String,
+    code:      StatusCode,
+    remaining: usize,
+}
+
+struct MockState {
+    device:    String,
+    token:     AccessToken,
+    updates:   Vec<UpdateRequest>,
+    downloads: HashMap<UpdateRequestId, Vec<u8>>,
+    failures:  Vec<Failure>,
+    requests:  Vec<MockRequest>,
+}
+
+
+/// A local HTTP server standing in for the Core and auth servers. Update
+/// requests and their downloads are programmable, failures may be injected
+/// for specific endpoints, and every request received is logged.
+pub struct MockCore {
+    pub addr: SocketAddr,
+    state:    Arc<Mutex<MockState>>,
+}
+
+impl MockCore {
+    /// Start a new server in the background for the device with this uuid.
+    pub fn start(device: &str) -> MockCore {
+        let state = Arc::new(Mutex::new(MockState {
+            device:    device.to_string(),
+            token:     AccessToken {
+                access_token: "mock-token".to_string(),
+                token_type:   "bearer".to_string(),
+                expires_in:   3600,
+                scope:        "".to_string(),
+            },
+            updates:   Vec::new(),
+            downloads: HashMap::new(),
+            failures:  Vec::new(),
+            requests:  Vec::new(),
+        }));
+        let (addr_tx, addr_rx) = chan::sync::<SocketAddr>(0);
+
+        let shared = state.clone();
+        thread::spawn(move || {
+            let addr   = "127.0.0.1:0".parse().unwrap();
+            let server = HyperServer::http(&addr)
+                .unwrap_or_else(|err| panic!("couldn't start mock core server: {}", err));
+            let (listening, server) = server.handle(move |_| MockHandler::new(shared.clone())).unwrap();
+            addr_tx.send(*listening.addr());
+            server.run();
+        });
+
+        let addr = addr_rx.recv().expect("couldn't get mock core address");
+        info!("Mock Core server listening at http://{}.", addr);
+        MockCore { addr: addr, state: state }
+    }
+
+    /// The URL of the server.
+    pub fn url(&self) -> Url {
+        format!("http://{}", self.addr).parse().expect("couldn't parse mock core url")
+    }
+
+    /// Change the access token returned on authentication.
+    pub fn set_token(&self, token: AccessToken) {
+        self.state.lock().unwrap().token = token;
+    }
+
+    /// Add a new update request along with the package to download for it.
+    pub fn add_update(&self, request: UpdateRequest, package: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.downloads.insert(request.requestId.clone(), package);
+        state.updates.push(request);
+    }
+
+    /// Respond to the next `times` requests with this method and path suffix
+    /// with an HTTP error `code` instead of handling them.
+    pub fn fail(&self, method: &str, path: &str, code: u16, times: usize) {
+        self.state.lock().unwrap().failures.push(Failure {
+            method:    method.to_string(),
+            path:      path.to_string(),
+            code:      StatusCode::from_u16(code),
+            remaining: times,
+        });
+    }
+
+    /// Return all requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Return the requests received so far with this method and path suffix.
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<MockRequest> {
+        self.requests().into_iter()
+            .filter(|req| req.method == method && req.path.ends_with(path))
+            .collect()
+    }
+}
+
+
+struct MockHandler {
+    state:     Arc<Mutex<MockState>>,
+    method:    String,
+    path:      String,
+    auth:      Option<String>,
+    resp_code: StatusCode,
+    resp_body: Option<Vec<u8>>
+}
+
+impl MockHandler {
+    fn new(state: Arc<Mutex<MockState>>) -> ServerHandler<HttpStream> {
+        ServerHandler::new(Box::new(MockHandler {
+            state:     state,
+            method:    String::new(),
+            path:      String::new(),
+            auth:      None,
+            resp_code: StatusCode::InternalServerError,
+            resp_body: None,
+        }))
+    }
+
+    fn handle(&self, state: &mut MockState, body: &[u8]) -> (StatusCode, Vec<u8>) {
+        for failure in state.failures.iter_mut() {
+            if failure.remaining > 0 && failure.method == self.method && self.path.ends_with(&failure.path) {
+                failure.remaining -= 1;
+                return (failure.code, br#"{"error": "injected failure"}"#.to_vec())
+            }
+        }
+
+        let prefix = format!("/api/v1/mydevice/{}", state.device);
+        let route  = if self.path.starts_with(&prefix) { &self.path[prefix.len()..] } else { &self.path[..] };
+        let parts  = route.trim_matches('/').split('/').collect::<Vec<_>>();
+        let part   = |n: usize| parts.get(n).cloned().unwrap_or("");
+
+        match (self.method.as_str(), parts.len(), part(0), part(2)) {
+            ("POST", 1, "token", _) => {
+                (StatusCode::Ok, json::encode(&state.token).expect("couldn't encode token").into_bytes())
+            }
+
+            ("GET", 1, "updates", _) => {
+                (StatusCode::Ok, json::encode(&state.updates).expect("couldn't encode updates").into_bytes())
+            }
+
+            ("GET", 3, "updates", "download") => match state.downloads.get(part(1)) {
+                Some(package) => (StatusCode::Ok, package.clone()),
+                None          => (StatusCode::NotFound, Vec::new())
+            },
+
+            ("POST", 2, "updates", _) => {
+                let id = part(1);
+                let _  = str::from_utf8(body).map(|text| debug!("update report for {}: {}", id, text));
+                state.updates.retain(|update| update.requestId != id);
+                (StatusCode::Ok, Vec::new())
+            }
+
+            ("PUT", 1, "installed", _) | ("PUT", 1, "system_info", _) => (StatusCode::Ok, Vec::new()),
+
+            _ => (StatusCode::NotFound, Vec::new())
+        }
+    }
+}
+
+impl<T: Transport> Server<T> for MockHandler {
+    fn headers(&mut self, req: HyperRequest<T>) {
+        self.method = format!("{}", req.method());
+        self.path   = format!("{}", req.uri());
+        self.auth   = req.headers().get_raw("Authorization")
+            .and_then(|values| values.first())
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+    }
+
+    fn request(&mut self, body: Vec<u8>) {
+        let state        = self.state.clone();
+        let mut state    = state.lock().unwrap();
+        let (code, resp) = self.handle(&mut state, &body);
+        state.requests.push(MockRequest {
+            method: self.method.clone(),
+            path:   self.path.clone(),
+            auth:   self.auth.clone(),
+            body:   body,
+        });
+        self.resp_code = code;
+        self.resp_body = Some(resp);
+    }
+
+    fn response(&mut self) -> (StatusCode, Option<Vec<u8>>) {
+        (self.resp_code, mem::replace(&mut self.resp_body, None))
+    }
+}
@@ -1,11 +1,13 @@
 pub mod auth_client;
 pub mod http_client;
 pub mod http_server;
+pub mod mock_core;
 pub mod openssl;
 pub mod test_client;
 
 pub use self::auth_client::{AuthClient, AuthHandler};
 pub use self::http_client::{Client, Request, Response, ResponseData};
 pub use self::http_server::{Server, ServerHandler};
+pub use self::mock_core::{MockCore, MockRequest};
 pub use self::openssl::{get_openssl, set_ca_certificates};
-pub use self::test_client::TestClient;
+pub use self::test_client::{TestClient, TestReply};
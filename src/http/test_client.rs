@@ -1,39 +1,156 @@
 use chan::Sender;
 use hyper::status::StatusCode;
-use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use datatype::Error;
+use datatype::{Error, Method};
 use http::{Client, Request, Response, ResponseData};
 
 
-/// The `TestClient` will return HTTP responses from an existing list of strings.
+/// A scripted reply to a request sent to the `TestClient`.
+#[derive(Clone, Debug)]
+pub enum TestReply {
+    /// Respond with an HTTP status code and body.
+    Status(u16, String),
+    /// Fail with a transport error before any response is received.
+    Error(String),
+    /// Wait for the duration before sending the inner reply.
+    Delay(Duration, Box<TestReply>),
+}
+
+struct Script {
+    method: Method,
+    path:   String,
+    reply:  TestReply,
+}
+
+
+/// The `TestClient` returns scripted replies for requests matching a method
+/// and URL path suffix, falling back to returning HTTP responses from an
+/// existing list of strings. Every request sent is recorded.
+#[derive(Clone)]
 pub struct TestClient {
-    responses: RefCell<Vec<String>>
+    responses: Arc<Mutex<Vec<String>>>,
+    scripts:   Arc<Mutex<Vec<Script>>>,
+    requests:  Arc<Mutex<Vec<Request>>>,
 }
 
 impl Default for TestClient {
     fn default() -> Self {
-        TestClient { responses: RefCell::new(Vec::new()) }
+        TestClient::from(Vec::new())
     }
 }
 
 impl TestClient {
     /// Create a new `TestClient` that will return these responses.
     pub fn from(responses: Vec<String>) -> TestClient {
-        TestClient { responses: RefCell::new(responses) }
+        TestClient {
+            responses: Arc::new(Mutex::new(responses)),
+            scripts:   Arc::new(Mutex::new(Vec::new())),
+            requests:  Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Send the reply to the next request with this method and URL path
+    /// suffix. Scripts are matched in the order they were added.
+    pub fn script(&self, method: Method, path: &str, reply: TestReply) {
+        self.scripts.lock().unwrap().push(Script {
+            method: method,
+            path:   path.to_string(),
+            reply:  reply,
+        });
+    }
+
+    /// Return all requests sent so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn next_reply(&self, req: &Request) -> Option<TestReply> {
+        let mut scripts = self.scripts.lock().unwrap();
+        let position    = scripts.iter().position(|script| {
+            script.method == req.method && req.url.path().ends_with(&script.path)
+        });
+        match position {
+            Some(n) => Some(scripts.remove(n).reply),
+            None    => self.responses.lock().unwrap().pop().map(|body| TestReply::Status(200, body))
+        }
     }
 }
 
 impl Client for TestClient {
     fn chan_request(&self, req: Request, resp_tx: Sender<Response>) {
-        match self.responses.borrow_mut().pop() {
-            Some(body) => resp_tx.send(Response::Success(ResponseData {
-                code: StatusCode::Ok,
-                body: body.as_bytes().to_vec()
-            })),
-            None => resp_tx.send(Response::Error(Error::Client(req.url.to_string())))
+        self.requests.lock().unwrap().push(req.clone());
+        match self.next_reply(&req) {
+            Some(reply) => send_reply(reply, req.max_body, resp_tx),
+            None        => resp_tx.send(Response::Error(Error::Client(req.url.to_string())))
         }
     }
 
     fn is_testing(&self) -> bool { true }
 }
+
+/// Send the reply in the same way as the `AuthClient` would.
+fn send_reply(reply: TestReply, max_body: Option<u64>, resp_tx: Sender<Response>) {
+    match reply {
+        TestReply::Status(_, ref body) if max_body.map_or(false, |max| body.len() as u64 > max) => {
+            let max = max_body.unwrap_or(0);
+            let err = format!("response of {} bytes exceeds the {} bytes available", body.len(), max);
+            resp_tx.send(Response::Error(Error::DiskFull(err)));
+        }
+
+        TestReply::Status(code, body) => {
+            let data = ResponseData { code: StatusCode::from_u16(code), body: body.into_bytes() };
+            if data.code == StatusCode::Unauthorized || data.code == StatusCode::Forbidden {
+                resp_tx.send(Response::Error(Error::HttpAuth(data)));
+            } else if data.code.is_success() {
+                resp_tx.send(Response::Success(data));
+            } else {
+                resp_tx.send(Response::Failed(data));
+            }
+        }
+
+        TestReply::Error(err) => resp_tx.send(Response::Error(Error::Client(err))),
+
+        TestReply::Delay(duration, reply) => {
+            thread::sleep(duration);
+            send_reply(*reply, max_body, resp_tx);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatype::{Error, Method, Url};
+    use http::{Client, Response};
+
+
+    #[test]
+    fn test_scripted_replies() {
+        let client = TestClient::from(vec!["fallback".to_string()]);
+        let url    = "http://localhost/api/v1/updates".parse::<Url>().unwrap();
+        client.script(Method::Get, "/updates", TestReply::Status(500, "oops".to_string()));
+        client.script(Method::Get, "/updates", TestReply::Error("refused".to_string()));
+
+        match client.get(url.clone(), None).recv() {
+            Some(Response::Failed(data)) => assert_eq!(data.body, b"oops".to_vec()),
+            other                        => panic!("expected failed response, got {:?}", other)
+        }
+        match client.get(url.clone(), None).recv() {
+            Some(Response::Error(Error::Client(err))) => assert_eq!(err, "refused"),
+            other                                     => panic!("expected client error, got {:?}", other)
+        }
+        match client.post(url.clone(), Some(b"body".to_vec())).recv() {
+            Some(Response::Success(data)) => assert_eq!(data.body, b"fallback".to_vec()),
+            other                         => panic!("expected success response, got {:?}", other)
+        }
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].method, Method::Post);
+        assert_eq!(requests[2].body, Some(b"body".to_vec()));
+    }
+}
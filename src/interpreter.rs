@@ -1,15 +1,20 @@
 use chan;
 use chan::{Sender, Receiver, WaitGroup};
-use std::{process, thread};
+use std::{fs, process, thread};
 use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use time;
 
+use audit::AuditLog;
 use datatype::{AccessToken, Auth, ClientCredentials, Command, Config, Error, Event,
                Package, UpdateReport, UpdateRequestStatus as Status, UpdateResultCode,
                system_info};
 use gateway::Interpret;
+use health::Heartbeat;
 use http::{AuthClient, Client};
+use logging::{Correlations, LogScope};
+use metrics::Metrics;
 use oauth2::authenticate;
 use package_manager::PackageManager;
 use rvi::Services;
@@ -18,10 +23,11 @@ use sota::Sota;
 
 /// An `Interpreter` loops over any incoming values, on receipt of which it
 /// delegates to the `interpret` function which will respond with output values.
+/// The `Heartbeat` is busy while each value is interpreted.
 pub trait Interpreter<I, O> {
     fn interpret(&mut self, input: I, otx: &Sender<O>);
 
-    fn run(&mut self, irx: Receiver<I>, otx: Sender<O>, wg: WaitGroup) {
+    fn run(&mut self, irx: Receiver<I>, otx: Sender<O>, wg: WaitGroup, heartbeat: Heartbeat) {
         let cooldown = Duration::from_millis(100);
 
         loop {
@@ -29,8 +35,10 @@ pub trait Interpreter<I, O> {
             let started = time::precise_time_ns();
 
             wg.add(1);
+            heartbeat.busy();
             trace!("interpreter starting: {}", started);
             self.interpret(input, &otx);
+            heartbeat.idle();
 
             thread::sleep(cooldown); // let any further work commence
             trace!("interpreter stopping: {}", started);
@@ -41,44 +49,56 @@ pub trait Interpreter<I, O> {
 
 
 /// The `EventInterpreter` listens for `Event`s and optionally responds with
-/// `Command`s that may be sent to the `CommandInterpreter`.
+/// `Command`s that may be sent to the `CommandInterpreter`, which keep the
+/// correlation id of the command that caused the event.
 pub struct EventInterpreter {
-    pub pacman:  PackageManager,
-    pub sysinfo: Option<String>,
+    pub pacman:       PackageManager,
+    pub sysinfo:      Option<String>,
+    pub metrics:      Metrics,
+    pub running:      Option<Arc<RwLock<Config>>>,
+    pub correlations: Correlations,
 }
 
 impl Interpreter<Event, Command> for EventInterpreter {
     fn interpret(&mut self, event: Event, ctx: &Sender<Command>) {
+        let id     = self.correlations.event_received(&event);
+        let _scope = LogScope::event(&event, id.as_ref().map(|id| id.as_str()));
         info!("EventInterpreter received: {}", event);
 
+        let correlations = self.correlations.clone();
+        let send = |cmd: Command| {
+            id.as_ref().map(|id| correlations.command_sent(&cmd, id));
+            ctx.send(cmd);
+        };
+
         match event {
             Event::Authenticated => {
                 if self.pacman != PackageManager::Off {
                     self.pacman.installed_packages().map(|packages| {
-                        ctx.send(Command::SendInstalledPackages(packages));
+                        send(Command::SendInstalledPackages(packages));
                     }).unwrap_or_else(|err| error!("couldn't send a list of packages: {}", err));
                 }
 
-                self.sysinfo.as_ref().map(|_| ctx.send(Command::SendSystemInfo));
+                self.sysinfo.as_ref().map(|_| send(Command::SendSystemInfo));
             }
 
             Event::NotAuthenticated => {
                 info!("Trying to authenticate again...");
-                ctx.send(Command::Authenticate(None));
+                send(Command::Authenticate(None));
             }
 
             Event::UpdatesReceived(requests) => {
                 for request in requests {
                     let id = request.requestId.clone();
                     match request.status {
-                        Status::Pending => ctx.send(Command::StartDownload(id)),
+                        Status::Pending => send(Command::StartDownload(id)),
 
                         Status::InFlight if self.pacman != PackageManager::Off => {
                             if self.pacman.is_installed(&request.packageId) {
                                 let report = UpdateReport::single(id, UpdateResultCode::OK, "".to_string());
-                                ctx.send(Command::SendUpdateReport(report));
+                                send(Command::SendUpdateReport(report));
                             } else {
-                                ctx.send(Command::StartDownload(id));
+                                send(Command::StartDownload(id));
                             }
                         }
 
@@ -87,29 +107,54 @@ impl Interpreter<Event, Command> for EventInterpreter {
                 }
             }
 
+            Event::DownloadingUpdate(id) => self.metrics.download_started(&id),
+
             Event::DownloadComplete(dl) => {
+                let size = fs::metadata(&dl.update_image).map(|meta| meta.len()).ok();
+                self.metrics.download_finished(&dl.update_id, "complete", size);
                 if self.pacman != PackageManager::Off {
-                    ctx.send(Command::StartInstall(dl.update_id.clone()));
+                    send(Command::StartInstall(dl.update_id.clone()));
                 }
             }
 
             Event::DownloadFailed(id, reason) => {
+                self.metrics.download_finished(&id, "failed", None);
                 let report = UpdateReport::single(id, UpdateResultCode::GENERAL_ERROR, reason);
-                ctx.send(Command::SendUpdateReport(report));
+                send(Command::SendUpdateReport(report));
+            }
+
+            Event::DiskFull(id, reason) => {
+                self.metrics.download_finished(&id, "disk_full", None);
+                let report = UpdateReport::single(id, UpdateResultCode::DISK_FULL, reason);
+                send(Command::SendUpdateReport(report));
             }
 
             Event::InstallComplete(report) | Event::InstallFailed(report) => {
-                ctx.send(Command::SendUpdateReport(report));
+                self.metrics.install_finished(&report);
+                send(Command::SendUpdateReport(report));
+            }
+
+            Event::UpdateAborted(id) => {
+                let report = UpdateReport::single(id, UpdateResultCode::USER_DECLINED, "aborted".to_string());
+                send(Command::SendUpdateReport(report));
             }
 
             Event::UpdateReportSent => {
                 if self.pacman != PackageManager::Off {
                     self.pacman.installed_packages().map(|packages| {
-                        ctx.send(Command::SendInstalledPackages(packages));
+                        send(Command::SendInstalledPackages(packages));
                     }).unwrap_or_else(|err| error!("couldn't send a list of packages: {}", err));
                 }
             }
 
+            Event::ConfigReloaded(_) => {
+                if let Some(ref running) = self.running {
+                    let config   = running.read().unwrap();
+                    self.pacman  = config.device.package_manager.clone();
+                    self.sysinfo = config.device.system_info.clone();
+                }
+            }
+
             _ => ()
         }
     }
@@ -117,41 +162,72 @@ impl Interpreter<Event, Command> for EventInterpreter {
 
 
 /// The `CommandInterpreter` wraps each incoming `Command` inside an `Interpret`
-/// type with no response channel for sending to the `GlobalInterpreter`.
-pub struct CommandInterpreter;
+/// type with no response channel for sending to the `GlobalInterpreter`,
+/// reusing the correlation id of any follow-up command.
+pub struct CommandInterpreter {
+    pub correlations: Correlations,
+}
 
 impl Interpreter<Command, Interpret> for CommandInterpreter {
     fn interpret(&mut self, cmd: Command, itx: &Sender<Interpret>) {
         info!("CommandInterpreter received: {}", cmd);
-        itx.send(Interpret { command: cmd, response_tx: None });
+        match self.correlations.command_received(&cmd) {
+            Some(id) => itx.send(Interpret::correlated(cmd, "internal", id)),
+            None     => itx.send(Interpret::new(cmd, None, "internal"))
+        }
     }
 }
 
 
+/// Loads a new `Config` for `Command::ReloadConfig` then shares the resulting
+/// running config with the other threads. The `gateways` function starts each
+/// newly enabled gateway, returning the name and error of any that failed.
+pub struct Reloader {
+    pub load:     Box<Fn() -> Result<Config, Error> + Send>,
+    pub running:  Arc<RwLock<Config>>,
+    pub gateways: Box<FnMut(&Config) -> Vec<(&'static str, String)> + Send>,
+}
+
+
 /// The `GlobalInterpreter` interprets the `Command` inside incoming `Interpret`
 /// messages, broadcasting `Event`s globally and (optionally) sending the final
 /// outcome `Event` to the `Interpret` response channel.
 pub struct GlobalInterpreter<'t> {
-    pub config:      Config,
-    pub token:       Option<Cow<'t, AccessToken>>,
-    pub http_client: Box<Client>,
-    pub rvi:         Option<Services>
+    pub config:       Config,
+    pub token:        Option<Cow<'t, AccessToken>>,
+    pub http_client:  Box<Client>,
+    pub rvi:          Option<Services>,
+    pub metrics:      Metrics,
+    pub audit:        Option<AuditLog>,
+    pub reloader:     Option<Reloader>,
+    pub correlations: Correlations,
 }
 
 impl<'t> Interpreter<Interpret, Event> for GlobalInterpreter<'t> {
     fn interpret(&mut self, interpret: Interpret, etx: &Sender<Event>) {
+        let _scope = LogScope::interpret(&interpret);
         info!("GlobalInterpreter received: {}", interpret.command);
+        self.metrics.command(interpret.command.name());
+        self.audit.as_ref().map(|audit| audit.command(&interpret));
 
         let (multi_tx, multi_rx) = chan::async::<Event>();
-        let outcome = match (self.token.as_ref(), self.config.auth.is_none()) {
-            (Some(_), _) | (_, true) => self.authenticated(interpret.command, multi_tx),
-            _                        => self.unauthenticated(interpret.command, multi_tx)
+        let outcome = if !self.config.gateway.allows(interpret.gateway) {
+            Err(Error::AccessDenied(format!("the {} gateway is disabled", interpret.gateway)))
+        } else if interpret.command == Command::ReloadConfig {
+            self.reload_config(multi_tx)
+        } else {
+            match (self.token.as_ref(), self.config.auth.is_none()) {
+                (Some(_), _) | (_, true) => self.authenticated(interpret.command, multi_tx),
+                _                        => self.unauthenticated(interpret.command, multi_tx)
+            }
         };
 
         let mut response_ev: Option<Event> = None;
         match outcome {
             Ok(_) => {
                 for ev in multi_rx {
+                    debug!("GlobalInterpreter sending: {}", ev);
+                    self.correlations.event_sent(&ev, &interpret.correlation_id);
                     etx.send(ev.clone());
                     response_ev = Some(ev);
                 }
@@ -159,14 +235,17 @@ impl<'t> Interpreter<Interpret, Event> for GlobalInterpreter<'t> {
 
             Err(Error::HttpAuth(resp)) => {
                 error!("HTTP authorization failed: {}", resp);
+                self.metrics.auth_failed();
                 self.token = None;
                 let ev = Event::NotAuthenticated;
+                self.correlations.event_sent(&ev, &interpret.correlation_id);
                 etx.send(ev.clone());
                 response_ev = Some(ev);
             }
 
             Err(err) => {
                 let ev = Event::Error(format!("{}", err));
+                self.correlations.event_sent(&ev, &interpret.correlation_id);
                 etx.send(ev.clone());
                 response_ev = Some(ev);
             }
@@ -186,7 +265,10 @@ impl<'t> GlobalInterpreter<'t> {
             Command::Authenticate(_) => etx.send(Event::AlreadyAuthenticated),
 
             Command::GetUpdateRequests => {
-                let mut updates = try!(sota.get_update_requests());
+                let started = time::precise_time_ns();
+                let updates = sota.get_update_requests();
+                self.metrics.poll_finished(started, updates.is_ok());
+                let mut updates = try!(updates);
                 if updates.is_empty() {
                     etx.send(Event::NoUpdateRequests);
                 } else {
@@ -195,6 +277,8 @@ impl<'t> GlobalInterpreter<'t> {
                 }
             }
 
+            Command::GetHistory { since, limit, filter } => try!(self.get_history(since, limit, filter, &etx)),
+
             Command::ListInstalledPackages => {
                 let mut packages: Vec<Package> = Vec::new();
                 if self.config.device.package_manager != PackageManager::Off {
@@ -242,7 +326,10 @@ impl<'t> GlobalInterpreter<'t> {
                 } else {
                     let _ = sota.download_update(id.clone())
                         .map(|dl| etx.send(Event::DownloadComplete(dl)))
-                        .map_err(|err| etx.send(Event::DownloadFailed(id, format!("{}", err))));
+                        .map_err(|err| match err {
+                            Error::DiskFull(reason) => etx.send(Event::DiskFull(id, reason)),
+                            _                       => etx.send(Event::DownloadFailed(id, format!("{}", err)))
+                        });
                 }
             }
 
@@ -253,7 +340,21 @@ impl<'t> GlobalInterpreter<'t> {
                     .map_err(|report| etx.send(Event::InstallFailed(report)));
             }
 
+            // only RVI transfers can be aborted as Core downloads block the interpreter
+            Command::AbortUpdate(id) => {
+                let aborted = match self.rvi {
+                    Some(ref rvi) => rvi.transfers.lock().unwrap().remove(id.clone()),
+                    None          => false
+                };
+                if !aborted {
+                    return Err(Error::Command(format!("no transfer in progress for update {}", id)));
+                }
+                etx.send(Event::UpdateAborted(id));
+            }
+
             Command::Shutdown => process::exit(0),
+
+            Command::ReloadConfig => unreachable!("ReloadConfig is handled before authentication"),
         }
 
         Ok(())
@@ -268,12 +369,17 @@ impl<'t> GlobalInterpreter<'t> {
                     client_secret: config.client_secret,
                 }));
                 let server = config.server.join("/token").expect("couldn't build authentication url");
-                let token  = try!(authenticate(server, self.http_client.as_ref()));
+                let token  = try!(authenticate(server, self.http_client.as_ref()).map_err(|err| {
+                    self.metrics.auth_failed();
+                    err
+                }));
                 self.set_client(Auth::Token(token.clone()));
                 self.token = Some(token.into());
                 etx.send(Event::Authenticated);
             }
 
+            Command::GetHistory { since, limit, filter } => try!(self.get_history(since, limit, filter, &etx)),
+
             Command::Shutdown => process::exit(0),
 
             _ => etx.send(Event::NotAuthenticated)
@@ -282,6 +388,41 @@ impl<'t> GlobalInterpreter<'t> {
         Ok(())
     }
 
+    fn get_history(&self, since: Option<String>, limit: Option<u64>, filter: Option<String>,
+                   etx: &Sender<Event>) -> Result<(), Error> {
+        let audit   = try!(self.audit.as_ref().ok_or(Error::Config("the audit log is disabled".to_string())));
+        let entries = try!(audit.query(since.as_ref().map(|s| s.as_str()), limit, filter.as_ref().map(|f| f.as_str())));
+        Ok(etx.send(Event::History(entries)))
+    }
+
+    fn reload_config(&mut self, etx: Sender<Event>) -> Result<(), Error> {
+        let reloader = try!(self.reloader.as_mut().ok_or(Error::Config("reloading is not supported".to_string())));
+        let loaded   = try!((reloader.load)());
+        try!(loaded.validate());
+
+        let (mut config, mut changes) = self.config.reload(loaded);
+        for (name, err) in (reloader.gateways)(&config) {
+            let key = format!("gateway.{}", name);
+            changes.applied.retain(|applied| *applied != key);
+            changes.failed.push(format!("{}: {}", key, err));
+            config.gateway.disable(name);
+        }
+        if changes.applied.iter().any(|key| key == "auth") {
+            self.token = None;
+        }
+        *reloader.running.write().unwrap() = config.clone();
+        self.config = config;
+
+        info!("Reloaded config: {}", changes);
+        if !changes.restart_required.is_empty() {
+            warn!("Restart required to apply: {}", changes.restart_required.join(", "));
+        }
+        if !changes.failed.is_empty() {
+            error!("Couldn't apply: {}", changes.failed.join(", "));
+        }
+        Ok(etx.send(Event::ConfigReloaded(changes)))
+    }
+
     fn set_client(&mut self, auth: Auth) {
         if !self.http_client.is_testing() {
             self.http_client = Box::new(AuthClient::from(auth));
@@ -294,15 +435,20 @@ impl<'t> GlobalInterpreter<'t> {
 mod tests {
     use chan;
     use chan::{Sender, Receiver};
+    use std::sync::{Arc, Mutex, RwLock};
     use std::thread;
 
     use super::*;
-    use datatype::{AccessToken, Command, Config, DownloadComplete, Event,
-                   UpdateReport, UpdateResultCode};
+    use audit::AuditLog;
+    use datatype::{AccessToken, AuditConfig, AuthConfig, Command, Config, ConfigChanges,
+                   DownloadComplete, Event, Method, UpdateReport, UpdateResultCode};
     use gateway::Interpret;
-    use http::test_client::TestClient;
-    use package_manager::PackageManager;
+    use http::test_client::{TestClient, TestReply};
+    use metrics::Metrics;
+    use package_manager::{PackageManager, TestDir};
     use package_manager::tpm::assert_rx;
+    use rvi::{RemoteServices, Services};
+    use rvi::transfers::Transfers;
 
 
     fn new_interpreter(replies: Vec<String>, pkg_mgr: PackageManager) -> (Sender<Command>, Receiver<Event>) {
@@ -311,16 +457,20 @@ mod tests {
 
         thread::spawn(move || {
             let mut gi = GlobalInterpreter {
-                config:      Config::default(),
-                token:       Some(AccessToken::default().into()),
-                http_client: Box::new(TestClient::from(replies)),
-                rvi:         None
+                config:       Config::default(),
+                token:        Some(AccessToken::default().into()),
+                http_client:  Box::new(TestClient::from(replies)),
+                rvi:          None,
+                metrics:      Metrics::default(),
+                audit:        None,
+                reloader:     None,
+                correlations: Correlations::default(),
             };
             gi.config.device.package_manager = pkg_mgr;
 
             loop {
                 match crx.recv() {
-                    Some(cmd) => gi.interpret(Interpret { command: cmd, response_tx: None }, &etx),
+                    Some(cmd) => gi.interpret(Interpret::new(cmd, None, "test"), &etx),
                     None      => break
                 }
             }
@@ -350,7 +500,7 @@ mod tests {
             Event::DownloadingUpdate("1".to_string()),
             Event::DownloadComplete(DownloadComplete {
                 update_id:    "1".to_string(),
-                update_image: "/tmp/1".to_string(),
+                update_image: "/tmp/sota-packages/1".to_string(),
                 signature:    "".to_string()
             })
         ]);
@@ -385,4 +535,170 @@ mod tests {
             )
         ]);
     }
+
+    #[test]
+    fn abort_update() {
+        let dir       = TestDir::new("sota-test-abort");
+        let (stx, _)  = chan::async::<Event>();
+        let transfers = Arc::new(Mutex::new(Transfers::new(dir.0.clone())));
+        transfers.lock().unwrap().push("1".to_string(), "".to_string(), 3, None);
+        let mut gi = GlobalInterpreter {
+            config:       Config::default(),
+            token:        Some(AccessToken::default().into()),
+            http_client:  Box::new(TestClient::default()),
+            rvi:          Some(Services {
+                remote:    Arc::new(Mutex::new(RemoteServices::new("device".to_string(), "http://localhost".parse().unwrap()))),
+                sender:    Arc::new(Mutex::new(stx)),
+                transfers: transfers.clone(),
+            }),
+            metrics:      Metrics::default(),
+            audit:        None,
+            reloader:     None,
+            correlations: Correlations::default(),
+        };
+        let (etx, erx) = chan::async::<Event>();
+
+        gi.interpret(Interpret::new(Command::AbortUpdate("1".to_string()), None, "test"), &etx);
+        assert_eq!(erx.recv(), Some(Event::UpdateAborted("1".to_string())));
+        assert!(transfers.lock().unwrap().get("1".to_string()).is_none());
+
+        for _ in 0..2 {
+            gi.interpret(Interpret::new(Command::AbortUpdate("1".to_string()), None, "test"), &etx);
+            match erx.recv() {
+                Some(Event::Error(err)) => assert!(err.contains("no transfer in progress")),
+                other                   => panic!("expected error event, got {:?}", other)
+            }
+            gi.rvi = None;
+        }
+    }
+
+    #[test]
+    fn reauthenticate_after_unauthorized() {
+        let token  = r#"{"access_token": "new-token", "token_type": "bearer", "expires_in": 3600, "scope": ""}"#;
+        let client = TestClient::default();
+        client.script(Method::Get, "/updates", TestReply::Status(401, "".to_string()));
+        client.script(Method::Post, "/token", TestReply::Status(200, token.to_string()));
+        client.script(Method::Get, "/updates", TestReply::Status(200, "[]".to_string()));
+
+        let metrics = Metrics::default();
+        let mut gi  = GlobalInterpreter {
+            config:       Config::default(),
+            token:        Some(AccessToken::default().into()),
+            http_client:  Box::new(client.clone()),
+            rvi:          None,
+            metrics:      metrics.clone(),
+            audit:        None,
+            reloader:     None,
+            correlations: Correlations::default(),
+        };
+        gi.config.auth = Some(AuthConfig::default());
+        let (etx, erx) = chan::async::<Event>();
+
+        gi.interpret(Interpret::new(Command::GetUpdateRequests, None, "test"), &etx);
+        assert!(gi.token.is_none());
+        gi.interpret(Interpret::new(Command::GetUpdateRequests, None, "test"), &etx);
+        gi.interpret(Interpret::new(Command::Authenticate(None), None, "test"), &etx);
+        assert_eq!(gi.token.as_ref().map(|token| token.access_token.clone()), Some("new-token".to_string()));
+        gi.interpret(Interpret::new(Command::GetUpdateRequests, None, "test"), &etx);
+        assert_rx(erx, &[
+            Event::NotAuthenticated,
+            Event::NotAuthenticated,
+            Event::Authenticated,
+            Event::NoUpdateRequests,
+        ]);
+
+        let paths = client.requests().iter().map(|req| req.url.path().to_string()).collect::<Vec<_>>();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[1].ends_with("/token"));
+        assert!(metrics.render().contains("sota_auth_failures_total 1\n"));
+    }
+
+    #[test]
+    fn get_history() {
+        let dir    = TestDir::new("sota-test-audit");
+        let mut gi = GlobalInterpreter {
+            config:       Config::default(),
+            token:        Some(AccessToken::default().into()),
+            http_client:  Box::new(TestClient::default()),
+            rvi:          None,
+            metrics:      Metrics::default(),
+            audit:        Some(AuditLog::new(&AuditConfig {
+                enabled: true,
+                path:    format!("{}/audit.log", dir.0),
+                ..AuditConfig::default()
+            })),
+            reloader:     None,
+            correlations: Correlations::default(),
+        };
+        let (etx, erx) = chan::async::<Event>();
+
+        let query = Command::GetHistory { since: None, limit: None, filter: Some("1".to_string()) };
+        gi.interpret(Interpret::new(Command::AbortUpdate("1".to_string()), None, "test"), &etx);
+        gi.interpret(Interpret::new(query.clone(), None, "test"), &etx);
+        match erx.recv() {
+            Some(Event::Error(err)) => assert!(err.contains("no transfer in progress")),
+            other                   => panic!("expected error event, got {:?}", other)
+        }
+        match erx.recv() {
+            Some(Event::History(entries)) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].name, "AbortUpdate");
+                assert_eq!(entries[0].gateway, Some("test".to_string()));
+            }
+            other => panic!("expected history event, got {:?}", other)
+        }
+
+        gi.audit = None;
+        gi.interpret(Interpret::new(query, None, "test"), &etx);
+        match erx.recv() {
+            Some(Event::Error(err)) => assert!(err.contains("audit log is disabled")),
+            other                   => panic!("expected error event, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn reload_config() {
+        let running = Arc::new(RwLock::new(Config::default()));
+        let mut gi  = GlobalInterpreter {
+            config:       Config::default(),
+            token:        Some(AccessToken::default().into()),
+            http_client:  Box::new(TestClient::default()),
+            rvi:          None,
+            metrics:      Metrics::default(),
+            audit:        None,
+            reloader:     Some(Reloader {
+                load:     Box::new(|| Config::parse("[core]\npolling_sec = 60\n[gateway]\nhttp = true\nsocket = true\n[health]\nbusy_timeout_sec = 5")),
+                running:  running.clone(),
+                gateways: Box::new(|config: &Config| {
+                    if config.gateway.http { vec![("http", "address in use".to_string())] } else { Vec::new() }
+                }),
+            }),
+            correlations: Correlations::default(),
+        };
+        let (etx, erx) = chan::async::<Event>();
+
+        gi.interpret(Interpret::new(Command::ReloadConfig, None, "signal"), &etx);
+        assert_eq!(erx.recv(), Some(Event::ConfigReloaded(ConfigChanges {
+            applied:          vec!["core.polling_sec".to_string(), "gateway.socket".to_string()],
+            restart_required: vec!["health".to_string()],
+            failed:           vec!["gateway.http: address in use".to_string()],
+        })));
+        assert_eq!(running.read().unwrap().core.polling_sec, 60);
+        assert!(!running.read().unwrap().gateway.http);
+        assert_eq!(gi.config.health, Config::default().health);
+
+        gi.interpret(Interpret::new(Command::ListInstalledPackages, None, "console"), &etx);
+        match erx.recv() {
+            Some(Event::Error(err)) => assert!(err.contains("console gateway is disabled")),
+            other                   => panic!("expected error event, got {:?}", other)
+        }
+
+        gi.reloader.as_mut().map(|reloader| reloader.load = Box::new(|| Config::parse("[gateway]\ndbus = true")));
+        gi.interpret(Interpret::new(Command::ReloadConfig, None, "socket"), &etx);
+        match erx.recv() {
+            Some(Event::Error(err)) => assert!(err.contains("[dbus] config required")),
+            other                   => panic!("expected error event, got {:?}", other)
+        }
+        assert!(gi.config.gateway.socket);
+    }
 }
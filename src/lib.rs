@@ -7,6 +7,7 @@ extern crate dbus;
 extern crate hyper;
 extern crate openssl;
 #[macro_use] extern crate lazy_static;
+extern crate libc;
 #[macro_use] extern crate log;
 extern crate rand;
 extern crate rustc_serialize;
@@ -16,11 +17,15 @@ extern crate unix_socket;
 extern crate url;
 extern crate ws;
 
+pub mod audit;
 pub mod broadcast;
 pub mod datatype;
 pub mod gateway;
+pub mod health;
 pub mod http;
 pub mod interpreter;
+pub mod logging;
+pub mod metrics;
 pub mod oauth2;
 pub mod package_manager;
 pub mod rvi;
@@ -0,0 +1,428 @@
+use libc;
+use log::{LogLevel, LogRecord};
+use rand;
+use rustc_serialize::json;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::CStr;
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use time;
+use unix_socket::UnixDatagram;
+
+use datatype::{Command, Event, LogFormat, LogSink};
+use gateway::Interpret;
+
+
+/// The name the client logs under.
+pub const APP_NAME: &'static str = "sota_client";
+/// The default socket for sending native journald entries.
+pub const JOURNALD_SOCKET: &'static str = "/run/systemd/journal/socket";
+/// The default socket for sending syslog messages.
+pub const SYSLOG_SOCKET: &'static str = "/dev/log";
+
+
+/// Details of the running client that are added to each log record, and
+/// where each record is written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogContext {
+    pub format:  LogFormat,
+    pub sink:    LogSink,
+    pub socket:  Option<String>,
+    pub version: String,
+    pub device:  String,
+}
+
+impl Default for LogContext {
+    fn default() -> Self {
+        LogContext {
+            format:  LogFormat::Text,
+            sink:    LogSink::Stderr,
+            socket:  None,
+            version: "unknown".to_string(),
+            device:  "".to_string(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONTEXT: RwLock<LogContext> = RwLock::new(LogContext::default());
+    static ref SOCKET: Mutex<Option<UnixDatagram>> = Mutex::new(None);
+}
+
+thread_local! {
+    static FIELDS: RefCell<BTreeMap<&'static str, String>> = RefCell::new(BTreeMap::new());
+}
+
+
+/// Replace the details added to each log record.
+pub fn set_context(ctx: LogContext) {
+    *CONTEXT.write().unwrap() = ctx;
+}
+
+/// Generate a new random id for correlating log records.
+pub fn new_correlation_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Return the correlation id of the current thread, if any.
+pub fn correlation_id() -> Option<String> {
+    FIELDS.with(|fields| fields.borrow().get("CORRELATION_ID").cloned())
+}
+
+fn current_fields() -> BTreeMap<&'static str, String> {
+    FIELDS.with(|fields| fields.borrow().clone())
+}
+
+
+/// The maximum number of unclaimed messages remembered by `Correlations`.
+const MAX_CORRELATIONS: usize = 1024;
+
+/// Remembers the correlation id of each `Event` and `Command` passed between
+/// the interpreter threads so that any follow-up work is logged under the id
+/// of the command that caused it. Equal messages are claimed in sent order.
+#[derive(Clone, Default)]
+pub struct Correlations {
+    events:   Arc<Mutex<VecDeque<(Event, String)>>>,
+    commands: Arc<Mutex<VecDeque<(Command, String)>>>,
+}
+
+impl Correlations {
+    /// Remember the correlation id of an `Event` before it is broadcast.
+    pub fn event_sent(&self, event: &Event, id: &str) {
+        remember(&self.events, event, id)
+    }
+
+    /// Claim the correlation id of a broadcast `Event`, if any.
+    pub fn event_received(&self, event: &Event) -> Option<String> {
+        claim(&self.events, event)
+    }
+
+    /// Remember the correlation id of a follow-up `Command` before it is sent.
+    pub fn command_sent(&self, cmd: &Command, id: &str) {
+        remember(&self.commands, cmd, id)
+    }
+
+    /// Claim the correlation id of a received `Command`, if any.
+    pub fn command_received(&self, cmd: &Command) -> Option<String> {
+        claim(&self.commands, cmd)
+    }
+}
+
+fn remember<T: Clone>(sent: &Mutex<VecDeque<(T, String)>>, msg: &T, id: &str) {
+    let mut sent = sent.lock().unwrap();
+    if sent.len() >= MAX_CORRELATIONS {
+        sent.pop_front();
+    }
+    sent.push_back((msg.clone(), id.to_string()));
+}
+
+fn claim<T: PartialEq>(sent: &Mutex<VecDeque<(T, String)>>, msg: &T) -> Option<String> {
+    let mut sent = sent.lock().unwrap();
+    let index    = sent.iter().position(|&(ref sent, _)| sent == msg);
+    index.and_then(|index| sent.remove(index)).map(|(_, id)| id)
+}
+
+
+/// Tags all log records of the current thread with extra fields until
+/// dropped, when the previous values are restored.
+pub struct LogScope {
+    previous: Vec<(&'static str, Option<String>)>,
+}
+
+impl LogScope {
+    /// Start tagging log records of the current thread with these fields.
+    pub fn enter(fields: &[(&'static str, &str)]) -> LogScope {
+        let previous = FIELDS.with(|current| {
+            let mut current = current.borrow_mut();
+            fields.iter().map(|&(key, val)| (key, current.insert(key, val.to_string()))).collect()
+        });
+        LogScope { previous: previous }
+    }
+
+    /// Tag log records with the correlation id, command name, originating
+    /// gateway and any update id.
+    pub fn interpret(interpret: &Interpret) -> LogScope {
+        let mut fields = vec![("CORRELATION_ID", interpret.correlation_id.as_str()),
+                              ("COMMAND", interpret.command.name()),
+                              ("GATEWAY", interpret.gateway)];
+        interpret.command.update_id().map(|id| fields.push(("UPDATE_ID", id)));
+        LogScope::enter(&fields)
+    }
+
+    /// Tag log records with the event name, any update id and the
+    /// correlation id of the command that caused the event.
+    pub fn event(event: &Event, correlation_id: Option<&str>) -> LogScope {
+        let mut fields = vec![("EVENT", event.name())];
+        correlation_id.map(|id| fields.push(("CORRELATION_ID", id)));
+        event.update_id().map(|id| fields.push(("UPDATE_ID", id)));
+        LogScope::enter(&fields)
+    }
+}
+
+impl Drop for LogScope {
+    fn drop(&mut self) {
+        FIELDS.with(|current| {
+            let mut current = current.borrow_mut();
+            for (key, val) in self.previous.drain(..).rev() {
+                match val {
+                    Some(val) => current.insert(key, val),
+                    None      => current.remove(key)
+                };
+            }
+        });
+    }
+}
+
+
+/// Write a log record to the configured sink, falling back to stderr on failure.
+pub fn write_record(record: &LogRecord) {
+    let ctx       = CONTEXT.read().unwrap();
+    let timestamp = format!("{}", time::now_utc().rfc3339());
+    let message   = format!("{}", record.args());
+    let fields    = current_fields();
+    let module    = record.location().module_path();
+
+    write_line(&ctx, &fields, &timestamp, record.level(), module, &message).unwrap_or_else(|err| {
+        let line = format_line(&ctx, &timestamp, record.level(), module, fields.get("CORRELATION_ID"), &message);
+        let _ = writeln!(io::stderr(), "{}", line);
+        let _ = writeln!(io::stderr(), "couldn't write to {} log sink: {}", ctx.sink, err);
+    });
+}
+
+fn write_line(ctx: &LogContext, fields: &BTreeMap<&'static str, String>, timestamp: &str,
+              level: LogLevel, module: &str, message: &str) -> io::Result<()> {
+    let id = fields.get("CORRELATION_ID");
+    match ctx.sink {
+        LogSink::Stderr => writeln!(io::stderr(), "{}", format_line(ctx, timestamp, level, module, id, message)),
+
+        LogSink::Journald => {
+            let socket = ctx.socket.as_ref().map_or(JOURNALD_SOCKET, |socket| socket.as_str());
+            send_datagram(socket, &journald_entry(ctx, fields, level, module, message))
+        }
+
+        LogSink::Syslog => {
+            let socket = ctx.socket.as_ref().map_or(SYSLOG_SOCKET, |socket| socket.as_str());
+            let body   = match ctx.format {
+                LogFormat::Text => message.to_string(),
+                LogFormat::Json => format_line(ctx, timestamp, level, module, id, message)
+            };
+            send_datagram(socket, syslog_message(timestamp, level, &body).as_bytes())
+        }
+    }
+}
+
+fn send_datagram(path: &str, data: &[u8]) -> io::Result<()> {
+    let mut socket = SOCKET.lock().unwrap();
+    if socket.is_none() {
+        *socket = Some(try!(UnixDatagram::unbound()));
+    }
+    socket.as_ref().expect("no log socket").send_to(data, path).map(|_| ())
+}
+
+#[derive(RustcEncodable)]
+struct JsonRecord<'a> {
+    timestamp:      &'a str,
+    level:          String,
+    module:         &'a str,
+    version:        &'a str,
+    device:         &'a str,
+    correlation_id: Option<&'a String>,
+    message:        &'a str,
+}
+
+fn format_line(ctx: &LogContext, timestamp: &str, level: LogLevel, module: &str,
+               correlation_id: Option<&String>, message: &str) -> String {
+    match ctx.format {
+        LogFormat::Text => match correlation_id {
+            Some(id) => format!("{} ({}): {} [{}] - {}", timestamp, ctx.version, level, id, message),
+            None     => format!("{} ({}): {} - {}", timestamp, ctx.version, level, message)
+        },
+
+        LogFormat::Json => {
+            json::encode(&JsonRecord {
+                timestamp:      timestamp,
+                level:          format!("{}", level),
+                module:         module,
+                version:        &ctx.version,
+                device:         &ctx.device,
+                correlation_id: correlation_id,
+                message:        message,
+            }).unwrap_or_else(|err| format!("{} ({}): {} - couldn't encode log record: {}",
+                                            timestamp, ctx.version, level, err))
+        }
+    }
+}
+
+// Map the log level to a syslog severity.
+fn severity(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Error => 3,
+        LogLevel::Warn  => 4,
+        LogLevel::Info  => 6,
+        LogLevel::Debug => 7,
+        LogLevel::Trace => 7,
+    }
+}
+
+// Encode a journald entry using the native protocol, where values containing
+// newlines are sent with an explicit length.
+fn journald_entry(ctx: &LogContext, fields: &BTreeMap<&'static str, String>,
+                  level: LogLevel, module: &str, message: &str) -> Vec<u8> {
+    let mut entry = Vec::new();
+    append_field(&mut entry, "MESSAGE", message);
+    append_field(&mut entry, "PRIORITY", &format!("{}", severity(level)));
+    append_field(&mut entry, "SYSLOG_IDENTIFIER", APP_NAME);
+    append_field(&mut entry, "SOTA_MODULE", module);
+    append_field(&mut entry, "SOTA_VERSION", &ctx.version);
+    append_field(&mut entry, "SOTA_DEVICE", &ctx.device);
+    for (key, val) in fields {
+        append_field(&mut entry, key, val);
+    }
+    entry
+}
+
+fn append_field(entry: &mut Vec<u8>, key: &str, val: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if val.contains('\n') {
+        entry.push(b'\n');
+        let len = val.len() as u64;
+        for n in 0..8 {
+            entry.push((len >> (n * 8)) as u8);
+        }
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(val.as_bytes());
+    entry.push(b'\n');
+}
+
+// Format an RFC 5424 message from the daemon facility.
+fn syslog_message(timestamp: &str, level: LogLevel, body: &str) -> String {
+    let priority = 3 * 8 + severity(level);
+    let pid      = unsafe { libc::getpid() };
+    format!("<{}>1 {} {} {} {} - - {}", priority, timestamp, hostname(), APP_NAME, pid, body)
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    match unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } {
+        0 => unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned(),
+        _ => "-".to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use log::LogLevel;
+    use rustc_serialize::json::Json;
+    use std::collections::BTreeMap;
+    use unix_socket::UnixDatagram;
+
+    use super::*;
+    use super::{format_line, journald_entry, write_line};
+    use datatype::{Command, Event, LogFormat, LogSink};
+    use package_manager::TestDir;
+
+
+    fn test_fields() -> BTreeMap<&'static str, String> {
+        let mut fields = BTreeMap::new();
+        fields.insert("CORRELATION_ID", "abc".to_string());
+        fields.insert("UPDATE_ID", "update-1".to_string());
+        fields
+    }
+
+    #[test]
+    fn test_text_format() {
+        let ctx = LogContext::default();
+        assert_eq!(format_line(&ctx, "now", LogLevel::Info, "sota", None, "hello"),
+                   "now (unknown): INFO - hello");
+        assert_eq!(format_line(&ctx, "now", LogLevel::Warn, "sota", Some(&"abc".to_string()), "hello"),
+                   "now (unknown): WARN [abc] - hello");
+    }
+
+    #[test]
+    fn test_json_format() {
+        let ctx  = LogContext { format: LogFormat::Json, version: "1.0".to_string(), device: "uuid".to_string(), ..LogContext::default() };
+        let line = format_line(&ctx, "now", LogLevel::Error, "sota::interpreter", Some(&"abc".to_string()), "a \"quoted\" message");
+        let json = Json::from_str(&line).expect("couldn't parse json log record");
+        assert_eq!(json.find("level").and_then(Json::as_string), Some("ERROR"));
+        assert_eq!(json.find("module").and_then(Json::as_string), Some("sota::interpreter"));
+        assert_eq!(json.find("version").and_then(Json::as_string), Some("1.0"));
+        assert_eq!(json.find("device").and_then(Json::as_string), Some("uuid"));
+        assert_eq!(json.find("correlation_id").and_then(Json::as_string), Some("abc"));
+        assert_eq!(json.find("message").and_then(Json::as_string), Some("a \"quoted\" message"));
+    }
+
+    #[test]
+    fn test_log_scope() {
+        assert_eq!(correlation_id(), None);
+        {
+            let _outer = LogScope::enter(&[("CORRELATION_ID", "outer")]);
+            {
+                let _inner = LogScope::enter(&[("CORRELATION_ID", "inner"), ("EVENT", "Authenticated")]);
+                assert_eq!(correlation_id(), Some("inner".to_string()));
+            }
+            assert_eq!(correlation_id(), Some("outer".to_string()));
+            assert_eq!(super::current_fields().get("EVENT"), None);
+        }
+        assert_eq!(correlation_id(), None);
+    }
+
+    #[test]
+    fn test_correlations() {
+        let correlations = Correlations::default();
+        correlations.event_sent(&Event::NoUpdateRequests, "one");
+        correlations.event_sent(&Event::Authenticated, "two");
+        correlations.event_sent(&Event::NoUpdateRequests, "three");
+        assert_eq!(correlations.event_received(&Event::NoUpdateRequests), Some("one".to_string()));
+        assert_eq!(correlations.event_received(&Event::NoUpdateRequests), Some("three".to_string()));
+        assert_eq!(correlations.event_received(&Event::NoUpdateRequests), None);
+        assert_eq!(correlations.command_received(&Command::GetUpdateRequests), None);
+
+        for n in 0..super::MAX_CORRELATIONS {
+            correlations.command_sent(&Command::GetUpdateRequests, &n.to_string());
+        }
+        assert_eq!(correlations.command_received(&Command::GetUpdateRequests), Some("0".to_string()));
+        correlations.command_sent(&Command::GetUpdateRequests, "last");
+        correlations.command_sent(&Command::GetUpdateRequests, "overflow");
+        assert_eq!(correlations.command_received(&Command::GetUpdateRequests), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_journald_entry() {
+        let ctx   = LogContext::default();
+        let entry = journald_entry(&ctx, &test_fields(), LogLevel::Warn, "sota", "two\nlines");
+        let text  = String::from_utf8_lossy(&entry).into_owned();
+        assert!(entry.starts_with(b"MESSAGE\n\x09\x00\x00\x00\x00\x00\x00\x00two\nlines\n"));
+        assert!(text.contains("\nPRIORITY=4\n"));
+        assert!(text.contains("\nSYSLOG_IDENTIFIER=sota_client\n"));
+        assert!(text.contains("\nCORRELATION_ID=abc\n"));
+        assert!(text.contains("\nUPDATE_ID=update-1\n"));
+    }
+
+    #[test]
+    fn test_socket_sinks() {
+        let dir      = TestDir::new("sota-test-logging");
+        let path     = format!("{}/log.socket", dir.0);
+        let listener = UnixDatagram::bind(&path).expect("couldn't bind log socket");
+        let mut buf  = [0; 4096];
+
+        let journald = LogContext { sink: LogSink::Journald, socket: Some(path.clone()), ..LogContext::default() };
+        write_line(&journald, &test_fields(), "now", LogLevel::Info, "sota", "hello").expect("couldn't write to journald");
+        let (n, _) = listener.recv_from(&mut buf).expect("couldn't read journald entry");
+        let text   = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(text.starts_with("MESSAGE=hello\nPRIORITY=6\n"));
+        assert!(text.contains("\nUPDATE_ID=update-1\n"));
+
+        let syslog = LogContext { sink: LogSink::Syslog, socket: Some(path.clone()), ..LogContext::default() };
+        write_line(&syslog, &test_fields(), "now", LogLevel::Error, "sota", "hello").expect("couldn't write to syslog");
+        let (n, _) = listener.recv_from(&mut buf).expect("couldn't read syslog message");
+        let text   = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(text.starts_with("<27>1 now "));
+        assert!(text.contains(" sota_client "));
+        assert!(text.ends_with(" - - hello"));
+    }
+}
@@ -7,24 +7,27 @@ extern crate hyper;
 #[macro_use] extern crate log;
 extern crate rustc_serialize;
 #[macro_use] extern crate sota;
-extern crate time;
 
 use chan::{Sender, Receiver, WaitGroup};
 use chan_signal::Signal;
-use env_logger::LogBuilder;
+use env_logger::{LogBuilder, Logger};
 use getopts::Options;
-use log::{LogLevelFilter, LogRecord};
+use log::{Log, LogLevelFilter, LogMetadata, LogRecord, MaxLogLevelFilter};
 use std::{env, process, thread};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
-use sota::datatype::{Command, Config, Event};
-use sota::gateway::{Console, DBus, Gateway, Interpret, Http, Socket, Websocket};
+use sota::audit::AuditLog;
+use sota::datatype::{CONFIG_KEYS, Command, Config, ConfigOverride, DBusConfig, Error, Event};
+use sota::gateway::{ClientStatus, Console, DBus, Gateway, Interpret, Http, Socket, Websocket};
+use sota::health::{Health, Notifier};
 use sota::broadcast::Broadcast;
 use sota::http::{AuthClient, set_ca_certificates};
-use sota::interpreter::{EventInterpreter, CommandInterpreter, Interpreter, GlobalInterpreter};
+use sota::interpreter::{EventInterpreter, CommandInterpreter, Interpreter, GlobalInterpreter, Reloader};
+use sota::logging::{Correlations, LogContext, set_context, write_record};
+use sota::metrics::Metrics;
 use sota::rvi::{Edge, Services};
 
 
@@ -37,8 +40,18 @@ macro_rules! exit {
 
 
 fn main() {
-    let version = start_logging();
-    let config  = build_config(&version);
+    let (version, log_filter) = start_logging();
+    let (config, loader)      = build_config(&version);
+    set_context(LogContext {
+        format:  config.log.format,
+        sink:    config.log.sink,
+        socket:  config.log.socket.clone(),
+        version: version.clone(),
+        device:  config.device.uuid.clone(),
+    });
+    if env::var("RUST_LOG").is_err() {
+        log_filter.set(&config.log.level);
+    }
 
     set_ca_certificates(Path::new(&config.device.certificates_path));
 
@@ -47,52 +60,53 @@ fn main() {
     let (itx, irx) = chan::async::<Interpret>();
 
     let mut broadcast = Broadcast::new(erx);
-    let wg = WaitGroup::new();
+    let metrics       = Metrics::default();
+    let wg            = WaitGroup::new();
+
+    if config.gateway.metrics {
+        broadcast.record_metrics(metrics.clone());
+        metrics.start_server(&config.network.metrics_server).unwrap_or_else(|err| exit!(1, "{}", err));
+    }
+
+    let audit   = if config.audit.enabled { Some(AuditLog::new(&config.audit)) } else { None };
+    let health  = Health::new(Duration::from_secs(config.health.busy_timeout_sec));
+    let running = Arc::new(RwLock::new(config.clone()));
 
     ctx.send(Command::Authenticate(None));
 
     crossbeam::scope(|scope| {
         // subscribe to signals first
-        let signals = chan_signal::notify(&[Signal::INT, Signal::TERM]);
-        scope.spawn(move || start_signal_handler(signals));
-
-        if config.core.polling {
-            let poll_tick = config.core.polling_sec;
-            let poll_itx  = itx.clone();
-            let poll_wg   = wg.clone();
-            scope.spawn(move || start_update_poller(poll_tick, poll_itx, poll_wg));
-        }
+        let signals    = chan_signal::notify(&[Signal::INT, Signal::TERM, Signal::HUP]);
+        let signal_itx = itx.clone();
+        scope.spawn(move || start_signal_handler(signals, signal_itx));
+
+        let (poll_tx, poll_rx) = chan::async::<()>();
+        let poll_cfg = running.clone();
+        let poll_itx = itx.clone();
+        let poll_wg  = wg.clone();
+        scope.spawn(move || start_update_poller(poll_cfg, poll_itx, poll_wg, poll_rx));
 
         //
         // start gateways
         //
 
-        if config.gateway.console {
-            let cons_itx = itx.clone();
-            let cons_sub = broadcast.subscribe();
-            scope.spawn(move || Console.start(cons_itx, cons_sub));
-        }
-
-        if config.gateway.dbus {
-            let dbus_cfg = config.dbus.as_ref().unwrap_or_else(|| exit!(1, "{}", "dbus config required for dbus gateway"));
-            let dbus_itx = itx.clone();
-            let dbus_sub = broadcast.subscribe();
-            let mut dbus = DBus { dbus_cfg: dbus_cfg.clone(), itx: itx.clone() };
-            scope.spawn(move || dbus.start(dbus_itx, dbus_sub));
-        }
-
-        if config.gateway.http {
-            let http_itx = itx.clone();
-            let http_sub = broadcast.subscribe();
-            let mut http = Http { server: *config.network.http_server };
-            scope.spawn(move || http.start(http_itx, http_sub));
+        let mut gateways = Gateways {
+            itx:       itx.clone(),
+            etx:       etx.clone(),
+            broadcast: broadcast.clone(),
+            health:    health.clone(),
+            started:   HashSet::new(),
+        };
+        for (name, err) in gateways.start_enabled(&config) {
+            exit!(1, "couldn't start the {} gateway: {}", name, err);
         }
 
         let rvi_services = if config.gateway.rvi {
             let _        = config.dbus.as_ref().unwrap_or_else(|| exit!(1, "{}", "dbus config required for rvi gateway"));
             let rvi_cfg  = config.rvi.as_ref().unwrap_or_else(|| exit!(1, "{}", "rvi config required for rvi gateway"));
             let rvi_edge = config.network.rvi_edge_server.clone();
-            let services = Services::new(rvi_cfg.clone(), config.device.uuid.clone(), etx.clone());
+            let services = Services::new(rvi_cfg.clone(), config.device.uuid.clone(),
+                                         config.device.storage_limits(), etx.clone());
             let mut edge = Edge::new(services.clone(), rvi_edge, rvi_cfg.client.clone());
             scope.spawn(move || edge.start());
             Some(services)
@@ -100,141 +114,290 @@ fn main() {
             None
         };
 
-        if config.gateway.socket {
-            let socket_itx = itx.clone();
-            let socket_sub = broadcast.subscribe();
-            let mut socket = Socket {
-                commands_path: config.network.socket_commands_path.clone(),
-                events_path:   config.network.socket_events_path.clone()
-            };
-            scope.spawn(move || socket.start(socket_itx, socket_sub));
+        if let Some(ref audit) = audit {
+            let audit_log = audit.clone();
+            let audit_sub = broadcast.subscribe();
+            scope.spawn(move || audit_log.record_events(audit_sub));
         }
 
-        if config.gateway.websocket {
-            let ws_server = config.network.websocket_server.clone();
-            let ws_itx    = itx.clone();
-            let ws_sub    = broadcast.subscribe();
-            let mut ws    = Websocket { server: ws_server, clients: Arc::new(Mutex::new(HashMap::new())) };
-            scope.spawn(move || ws.start(ws_itx, ws_sub));
-        }
+        let reload_sub = broadcast.subscribe();
+        let reload_cfg = running.clone();
+        let reload_ver = version.clone();
+        scope.spawn(move || start_reload_handler(reload_sub, reload_cfg, reload_ver, log_filter, poll_tx));
 
         //
         // start interpreters
         //
 
+        let correlations = Correlations::default();
+
         let event_sub = broadcast.subscribe();
         let event_ctx = ctx.clone();
         let event_mgr = config.device.package_manager.clone();
         let event_sys = config.device.system_info.clone();
+        let event_cfg = running.clone();
         let event_wg  = wg.clone();
+        let event_met = metrics.clone();
+        let event_hb  = health.register("event_interpreter");
+        let event_cor = correlations.clone();
         scope.spawn(move || EventInterpreter {
-            pacman:  event_mgr,
-            sysinfo: event_sys,
-        }.run(event_sub, event_ctx, event_wg));
+            pacman:       event_mgr,
+            sysinfo:      event_sys,
+            metrics:      event_met,
+            running:      Some(event_cfg),
+            correlations: event_cor,
+        }.run(event_sub, event_ctx, event_wg, event_hb));
 
         let cmd_itx = itx.clone();
         let cmd_wg  = wg.clone();
-        scope.spawn(move || CommandInterpreter.run(crx, cmd_itx, cmd_wg));
-
+        let cmd_hb  = health.register("command_interpreter");
+        let cmd_cor = correlations.clone();
+        scope.spawn(move || CommandInterpreter { correlations: cmd_cor }.run(crx, cmd_itx, cmd_wg, cmd_hb));
+
+        let global_hb = health.register("global_interpreter");
+        let reloader  = Reloader {
+            load:     loader,
+            running:  running.clone(),
+            gateways: Box::new(move |config: &Config| gateways.start_enabled(config)),
+        };
         scope.spawn(move || GlobalInterpreter {
-            config:      config,
-            token:       None,
-            http_client: Box::new(AuthClient::default()),
-            rvi:         rvi_services,
-        }.run(irx, etx, wg));
+            config:       config,
+            token:        None,
+            http_client:  Box::new(AuthClient::default()),
+            rvi:          rvi_services,
+            metrics:      metrics,
+            audit:        audit,
+            reloader:     Some(reloader),
+            correlations: correlations,
+        }.run(irx, etx, wg, global_hb));
 
         scope.spawn(move || broadcast.start());
+
+        Notifier::from_env().map(|notifier| {
+            notifier.ready().unwrap_or_else(|err| error!("couldn't notify systemd: {}", err));
+            notifier.start_watchdog(health.clone());
+        });
     });
 }
 
-fn start_logging() -> String {
+fn start_logging() -> (String, LogFilter) {
     let version = option_env!("SOTA_VERSION").unwrap_or("unknown");
 
+    set_context(LogContext { version: version.to_string(), ..LogContext::default() });
+
+    let logger        = Arc::new(RwLock::new(build_logger(&env::var("RUST_LOG").unwrap_or("INFO".to_string()))));
+    let sink_logger   = logger.clone();
+    let mut max_level = None;
+    log::set_logger(|max_level_filter| {
+        max_level_filter.set(sink_logger.read().unwrap().filter());
+        max_level = Some(max_level_filter);
+        Box::new(SinkLogger(sink_logger))
+    }).expect("logger already initialized");
+
+    let filter = LogFilter { logger: logger, max_level: max_level.expect("max log level not set") };
+    (version.to_string(), filter)
+}
+
+fn build_logger(spec: &str) -> Logger {
     let mut builder = LogBuilder::new();
-    builder.format(move |record: &LogRecord| {
-        let timestamp = format!("{}", time::now_utc().rfc3339());
-        format!("{} ({}): {} - {}", timestamp, version, record.level(), record.args())
-    });
     builder.filter(Some("hyper"), LogLevelFilter::Info);
-    builder.parse(&env::var("RUST_LOG").unwrap_or("INFO".to_string()));
-    builder.init().expect("builder already initialized");
+    builder.parse(spec);
+    builder.build()
+}
+
+/// Filters log records by `RUST_LOG` then writes them to the configured sink.
+struct SinkLogger(Arc<RwLock<Logger>>);
+
+impl Log for SinkLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        Log::enabled(&*self.0.read().unwrap(), metadata)
+    }
 
-    version.to_string()
+    fn log(&self, record: &LogRecord) {
+        if self.enabled(record.metadata()) {
+            write_record(record);
+        }
+    }
+}
+
+/// Replaces the filter used by the `SinkLogger`.
+struct LogFilter {
+    logger:    Arc<RwLock<Logger>>,
+    max_level: MaxLogLevelFilter,
+}
+
+impl LogFilter {
+    /// Set a new filter in the `RUST_LOG` format, such as `debug` or `sota=trace`.
+    fn set(&self, spec: &str) {
+        let logger = build_logger(spec);
+        self.max_level.set(logger.filter());
+        *self.logger.write().unwrap() = logger;
+    }
 }
 
-fn start_signal_handler(signals: Receiver<Signal>) {
+fn start_signal_handler(signals: Receiver<Signal>, itx: Sender<Interpret>) {
     loop {
         match signals.recv() {
             Some(Signal::INT) | Some(Signal::TERM) => process::exit(0),
+            Some(Signal::HUP) => itx.send(Interpret::new(Command::ReloadConfig, None, "signal")),
             _ => ()
         }
     }
 }
 
-fn start_update_poller(interval: u64, itx: Sender<Interpret>, wg: WaitGroup) {
-    info!("Polling for new updates every {} seconds.", interval);
+/// Poll for new updates every `core.polling_sec` seconds. The thread is parked
+/// while polling is disabled, and any wait is restarted with the new settings
+/// when woken by a change to the polling config.
+fn start_update_poller(running: Arc<RwLock<Config>>, itx: Sender<Interpret>, wg: WaitGroup, wake: Receiver<()>) {
     let (etx, erx) = chan::async::<Event>();
-    let wait = Duration::from_secs(interval);
+    let mut interval = None;
     loop {
-        wg.wait();           // wait until not busy
-        thread::sleep(wait); // then wait `interval` seconds
-        itx.send(Interpret {
-            command:     Command::GetUpdateRequests,
-            response_tx: Some(Arc::new(Mutex::new(etx.clone())))
-        });                  // then request new updates
-        let _ = erx.recv();  // then wait for the response
+        let core = running.read().unwrap().core.clone();
+        if !core.polling {
+            if interval.is_some() {
+                info!("Polling for new updates is disabled.");
+                interval = None;
+            }
+            let _ = wake.recv();
+            continue
+        } else if interval != Some(core.polling_sec) {
+            info!("Polling for new updates every {} seconds.", core.polling_sec);
+            interval = Some(core.polling_sec);
+        }
+
+        wg.wait();                                                 // wait until not busy
+        let timeout = chan::after(Duration::from_secs(core.polling_sec));
+        chan_select! {                                             // then wait `polling_sec` seconds
+            wake.recv()    => continue,
+            timeout.recv() => (),
+        }
+        let resp_tx = Some(Arc::new(Mutex::new(etx.clone())));
+        itx.send(Interpret::new(Command::GetUpdateRequests, resp_tx, "poller")); // then request new updates
+        let _ = erx.recv();                                                     // then wait for the response
     }
 }
 
-fn build_config(version: &str) -> Config {
+fn start_reload_handler(erx: Receiver<Event>, running: Arc<RwLock<Config>>, version: String,
+                        log_filter: LogFilter, poll_tx: Sender<()>) {
+    for event in erx {
+        if let Event::ConfigReloaded(changes) = event {
+            let config = running.read().unwrap().clone();
+            set_context(LogContext {
+                format:  config.log.format,
+                sink:    config.log.sink,
+                socket:  config.log.socket.clone(),
+                version: version.clone(),
+                device:  config.device.uuid.clone(),
+            });
+            if changes.applied.iter().any(|key| key == "log.level") {
+                // as on startup, RUST_LOG takes precedence over the config
+                match env::var("RUST_LOG") {
+                    Ok(_)  => warn!("Ignoring the reloaded log.level as RUST_LOG is set."),
+                    Err(_) => log_filter.set(&config.log.level)
+                }
+            }
+            if changes.applied.iter().any(|key| key == "core.polling" || key == "core.polling_sec") {
+                poll_tx.send(());
+            }
+        }
+    }
+}
+
+
+/// The gateways that may be started or stopped by a config reload.
+const GATEWAYS: &'static [&'static str] = &["console", "dbus", "http", "socket", "websocket"];
+
+/// Starts each enabled gateway that is not already running. A gateway that is
+/// disabled by a reload keeps running until a restart, so the reload reports
+/// it as requiring a restart, while a gateway that fails to initialize is
+/// reported as failed and may be started by a later reload.
+struct Gateways {
+    itx:       Sender<Interpret>,
+    etx:       Sender<Event>,
+    broadcast: Broadcast<Event>,
+    health:    Health,
+    started:   HashSet<&'static str>,
+}
+
+impl Gateways {
+    fn start_enabled(&mut self, config: &Config) -> Vec<(&'static str, String)> {
+        let mut failed = Vec::new();
+        for &name in GATEWAYS {
+            if config.gateway.allows(name) && !self.started.contains(&name) {
+                if let Err(err) = self.start(name, config) {
+                    error!("couldn't start the {} gateway: {}", name, err);
+                    failed.push((name, err));
+                }
+            }
+        }
+        failed
+    }
+
+    fn start(&mut self, name: &'static str, config: &Config) -> Result<(), String> {
+        let mut gateway: Box<Gateway + Send> = match name {
+            "console" => Box::new(Console),
+
+            "dbus" => {
+                let dbus_cfg = try!(config.dbus.as_ref().ok_or("dbus config required for dbus gateway"));
+                Box::new(DBus {
+                    dbus_cfg: dbus_cfg.clone(),
+                    itx:      self.itx.clone(),
+                    etx:      self.etx.clone(),
+                    status:   Arc::new(Mutex::new(ClientStatus::default())),
+                    pulse_tx: None
+                })
+            }
+
+            "http" => Box::new(Http {
+                server: *config.network.http_server,
+                access: config.access.http.clone(),
+                health: self.health.clone()
+            }),
+
+            "socket" => Box::new(Socket {
+                commands_path:  config.network.socket_commands_path.clone(),
+                events_path:    config.network.socket_events_path.clone(),
+                events_version: config.network.socket_events_version.clone(),
+                persistent:     config.network.socket_persistent,
+                clients:        Arc::new(Mutex::new(HashMap::new())),
+                access:         config.access.socket.clone()
+            }),
+
+            "websocket" => Box::new(Websocket {
+                server:    config.network.websocket_server.clone(),
+                clients:   Arc::new(Mutex::new(HashMap::new())),
+                access:    config.access.websocket.clone(),
+                keepalive: config.network.websocket_keepalive_sec
+            }),
+
+            _ => return Err(format!("unknown gateway: {}", name))
+        };
+
+        info!("Starting the {} gateway.", name);
+        try!(gateway.initialize(self.itx.clone()));
+        let sub       = self.broadcast.subscribe();
+        let heartbeat = self.health.register(&format!("{}_gateway", name));
+        thread::spawn(move || gateway.run(sub, heartbeat));
+        self.started.insert(name);
+        Ok(())
+    }
+}
+
+fn build_config(version: &str) -> (Config, Box<Fn() -> Result<Config, Error> + Send>) {
     let args     = env::args().collect::<Vec<String>>();
     let program  = args[0].clone();
     let mut opts = Options::new();
 
     opts.optflag("h", "help", "print this help menu then quit");
-    opts.optflag("p", "print", "print the parsed config then quit");
+    opts.optflag("p", "print", "print the parsed config and where each value was set then quit");
     opts.optflag("v", "version", "print the version then quit");
-    opts.optopt("c", "config", "change config path", "PATH");
-
-    opts.optopt("", "auth-server", "change the auth server", "URL");
-    opts.optopt("", "auth-client-id", "change the auth client id", "ID");
-    opts.optopt("", "auth-client-secret", "change the auth client secret", "SECRET");
-    opts.optopt("", "auth-credentials-file", "change the auth credentials file", "PATH");
-
-    opts.optopt("", "core-server", "change the core server", "URL");
-    opts.optopt("", "core-polling", "toggle polling the core server for updates", "BOOL");
-    opts.optopt("", "core-polling-sec", "change the core polling interval", "SECONDS");
-
-    opts.optopt("", "dbus-name", "change the dbus registration name", "NAME");
-    opts.optopt("", "dbus-path", "change the dbus path", "PATH");
-    opts.optopt("", "dbus-interface", "change the dbus interface name", "INTERFACE");
-    opts.optopt("", "dbus-software-manager", "change the dbus software manager name", "NAME");
-    opts.optopt("", "dbus-software-manager-path", "change the dbus software manager path", "PATH");
-    opts.optopt("", "dbus-timeout", "change the dbus installation timeout", "TIMEOUT");
-
-    opts.optopt("", "device-uuid", "change the device uuid", "UUID");
-    opts.optopt("", "device-vin", "change the device vin", "VIN");
-    opts.optopt("", "device-packages-dir", "change downloaded directory for packages", "PATH");
-    opts.optopt("", "device-package-manager", "change the package manager", "MANAGER");
-    opts.optopt("", "device-certificates-path", "change the OpenSSL CA certificates file", "PATH");
-    opts.optopt("", "device-system-info", "change the system information command", "PATH");
-
-    opts.optopt("", "gateway-console", "toggle the console gateway", "BOOL");
-    opts.optopt("", "gateway-dbus", "toggle the dbus gateway", "BOOL");
-    opts.optopt("", "gateway-http", "toggle the http gateway", "BOOL");
-    opts.optopt("", "gateway-rvi", "toggle the rvi gateway", "BOOL");
-    opts.optopt("", "gateway-socket", "toggle the unix domain socket gateway", "BOOL");
-    opts.optopt("", "gateway-websocket", "toggle the websocket gateway", "BOOL");
-
-    opts.optopt("", "network-http-server", "change the http server gateway address", "ADDR");
-    opts.optopt("", "network-rvi-edge-server", "change the rvi edge server gateway address", "ADDR");
-    opts.optopt("", "network-socket-commands-path", "change the socket path for reading commands", "PATH");
-    opts.optopt("", "network-socket-events-path", "change the socket path for sending events", "PATH");
-    opts.optopt("", "network-websocket-server", "change the websocket gateway address", "ADDR");
-
-    opts.optopt("", "rvi-client", "change the rvi client URL", "URL");
-    opts.optopt("", "rvi-storage-dir", "change the rvi storage directory", "PATH");
-    opts.optopt("", "rvi-timeout", "change the rvi timeout", "TIMEOUT");
+    opts.optmulti("c", "config", "add a config path, merged in order with its PATH.d/*.toml drop-ins", "PATH");
+    opts.optopt("", "check-config", "check a config file and its PATH.d/*.toml drop-ins for problems then quit", "PATH");
+    opts.optopt("", "print-dbus-policy", "print a dbus policy file for USER then quit", "USER");
+
+    for key in CONFIG_KEYS {
+        opts.optopt("", &key.flag(), key.help, key.hint);
+    }
 
     let matches = opts.parse(&args[1..]).unwrap_or_else(|err| panic!(err.to_string()));
 
@@ -242,98 +405,53 @@ fn build_config(version: &str) -> Config {
         exit!(0, "{}", opts.usage(&format!("Usage: {} [options]", program)));
     } else if matches.opt_present("version") {
         exit!(0, "{}", version);
+    } else if let Some(path) = matches.opt_str("check-config") {
+        check_config(&path);
     }
 
-    let mut config = match matches.opt_str("config").or(env::var("SOTA_CONFIG").ok()) {
-        Some(file) => Config::load(&file).unwrap_or_else(|err| exit!(1, "{}", err)),
-        None => {
-            warn!("No config file given. Falling back to defaults.");
-            Config::default()
+    // environment variables take precedence over the file, flags over both
+    let mut overrides = ConfigOverride::from_env(env::vars()).unwrap_or_else(|err| exit!(1, "{}", err));
+    for key in CONFIG_KEYS {
+        let flag = key.flag();
+        if let Some(text) = matches.opt_str(&flag) {
+            overrides.push(key.parse(&format!("--{}", flag), &text).unwrap_or_else(|err| exit!(1, "{}", err)));
         }
-    };
-
-    config.auth.as_mut().map(|auth_cfg| {
-        matches.opt_str("auth-client-id").map(|id| auth_cfg.client_id = id);
-        matches.opt_str("auth-client-secret").map(|secret| auth_cfg.client_secret = secret);
-        matches.opt_str("auth-server").map(|text| {
-            auth_cfg.server = text.parse().unwrap_or_else(|err| exit!(1, "Invalid auth-server URL: {}", err));
-        });
-    });
-
-    matches.opt_str("core-server").map(|text| {
-        config.core.server = text.parse().unwrap_or_else(|err| exit!(1, "Invalid core-server URL: {}", err));
-    });
-    matches.opt_str("core-polling").map(|polling| {
-        config.core.polling = polling.parse().unwrap_or_else(|err| exit!(1, "Invalid core-polling boolean: {}", err));
-    });
-    matches.opt_str("core-polling-sec").map(|secs| {
-        config.core.polling_sec = secs.parse().unwrap_or_else(|err| exit!(1, "Invalid core-polling-sec: {}", err));
-    });
-
-    config.dbus.as_mut().map(|dbus_cfg| {
-        matches.opt_str("dbus-name").map(|name| dbus_cfg.name = name);
-        matches.opt_str("dbus-path").map(|path| dbus_cfg.path = path);
-        matches.opt_str("dbus-interface").map(|interface| dbus_cfg.interface = interface);
-        matches.opt_str("dbus-software-manager").map(|mgr| dbus_cfg.software_manager = mgr);
-        matches.opt_str("dbus-software-manager-path").map(|mgr_path| dbus_cfg.software_manager_path = mgr_path);
-        matches.opt_str("dbus-timeout").map(|timeout| {
-            dbus_cfg.timeout = timeout.parse().unwrap_or_else(|err| exit!(1, "Invalid dbus-timeout: {}", err));
-        });
-    });
+    }
 
-    matches.opt_str("device-uuid").map(|uuid| config.device.uuid = uuid);
-    matches.opt_str("device-vin").map(|vin| config.device.vin = vin);
-    matches.opt_str("device-packages-dir").map(|path| config.device.packages_dir = path);
-    matches.opt_str("device-package-manager").map(|text| {
-        config.device.package_manager = text.parse().unwrap_or_else(|err| exit!(1, "Invalid device-package-manager: {}", err));
-    });
-    matches.opt_str("device-certificates-path").map(|certs| config.device.certificates_path = certs);
-    matches.opt_str("device-system-info").map(|cmd| {
-        config.device.system_info = if cmd.len() > 0 { Some(cmd) } else { None }
-    });
+    let mut files = matches.opt_strs("config");
+    if files.is_empty() {
+        files.extend(env::var("SOTA_CONFIG").ok());
+    }
+    if files.is_empty() {
+        warn!("No config file given. Falling back to defaults.");
+    }
+    let (config, sources) = Config::load_files(&files, &overrides).unwrap_or_else(|err| exit!(1, "{}", err));
 
-    matches.opt_str("gateway-console").map(|console| {
-        config.gateway.console = console.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-console boolean: {}", err));
-    });
-    matches.opt_str("gateway-dbus").map(|dbus| {
-        config.gateway.dbus = dbus.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-dbus boolean: {}", err));
-    });
-    matches.opt_str("gateway-http").map(|http| {
-        config.gateway.http = http.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-http boolean: {}", err));
-    });
-    matches.opt_str("gateway-rvi").map(|rvi| {
-        config.gateway.rvi = rvi.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-rvi boolean: {}", err));
-    });
-    matches.opt_str("gateway-socket").map(|socket| {
-        config.gateway.socket = socket.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-socket boolean: {}", err));
-    });
-    matches.opt_str("gateway-websocket").map(|websocket| {
-        config.gateway.websocket = websocket.parse().unwrap_or_else(|err| exit!(1, "Invalid gateway-websocket boolean: {}", err));
-    });
+    if matches.opt_present("print") {
+        let values = sources.iter().map(|(key, source)| format!("{} = {}", key, source)).collect::<Vec<_>>();
+        exit!(0, "{:#?}\n\n# where each value was set (all others use defaults)\n{}", config, values.join("\n"));
+    } else if let Some(user) = matches.opt_str("print-dbus-policy") {
+        let dbus_cfg = config.dbus.clone().unwrap_or(DBusConfig::default());
+        exit!(0, "{}", dbus_cfg.policy(&user));
+    }
 
-    matches.opt_str("network-http-server").map(|addr| {
-        config.network.http_server = addr.parse().unwrap_or_else(|err| exit!(1, "Invalid network-http-server: {}", err));
-    });
-    matches.opt_str("network-rvi-edge-server").map(|addr| {
-        config.network.rvi_edge_server = addr.parse().unwrap_or_else(|err| exit!(1, "Invalid network-rvi-edge-server: {}", err));
-    });
-    matches.opt_str("network-socket-commands-path").map(|path| config.network.socket_commands_path = path);
-    matches.opt_str("network-socket-events-path").map(|path| config.network.socket_events_path = path);
-    matches.opt_str("network-websocket-server").map(|server| config.network.websocket_server = server);
+    // re-apply the environment and command line overrides on each reload
+    let loader: Box<Fn() -> Result<Config, Error> + Send> = if files.is_empty() {
+        Box::new(|| Err(Error::Config("no config file to reload".to_string())))
+    } else {
+        Box::new(move || Config::load_files(&files, &overrides).map(|(config, _)| config))
+    };
 
-    config.rvi.as_mut().map(|rvi_cfg| {
-        matches.opt_str("rvi-client").map(|url| {
-            rvi_cfg.client = url.parse().unwrap_or_else(|err| exit!(1, "Invalid rvi-client URL: {}", err));
-        });
-        matches.opt_str("rvi-storage-dir").map(|dir| rvi_cfg.storage_dir = dir);
-        matches.opt_str("rvi-timeout").map(|timeout| {
-            rvi_cfg.timeout = Some(timeout.parse().unwrap_or_else(|err| exit!(1, "Invalid rvi-timeout: {}", err)));
-        });
-    });
+    (config, loader)
+}
 
-    if matches.opt_present("print") {
-        exit!(0, "{:#?}", config);
+fn check_config(path: &str) -> ! {
+    let problems = Config::check_files(&[path.to_string()]).unwrap_or_else(|err| exit!(1, "{}: {}", path, err));
+    if problems.is_empty() {
+        exit!(0, "{}: no problems found", path);
     }
-
-    config
+    for &(ref file, ref problem) in &problems {
+        println!("{}: {}", file, problem);
+    }
+    exit!(1, "{}: {} problem(s) found", path, problems.len());
 }
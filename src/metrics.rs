@@ -0,0 +1,264 @@
+use hyper::StatusCode;
+use hyper::mime::{Attr, Mime, TopLevel, SubLevel, Value};
+use hyper::net::{HttpStream, Transport};
+use hyper::server::{Server as HyperServer, Request as HyperRequest};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use time;
+
+use datatype::{UpdateReport, UpdateRequestId};
+use http::{Server, ServerHandler};
+
+
+/// The type of each metric family as reported to Prometheus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Summary,
+}
+
+impl MetricKind {
+    fn name(&self) -> &'static str {
+        match *self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge   => "gauge",
+            MetricKind::Summary => "summary",
+        }
+    }
+}
+
+/// The metric families recorded by the client, as `(name, kind, labelled, help)`.
+const FAMILIES: &'static [(&'static str, MetricKind, bool, &'static str)] = &[
+    ("sota_auth_failures_total",       MetricKind::Counter, false, "Number of failed authentication attempts."),
+    ("sota_broadcast_in_flight",       MetricKind::Gauge,   false, "Number of events received but not yet delivered to every subscriber."),
+    ("sota_commands_total",            MetricKind::Counter, true,  "Number of commands interpreted by name."),
+    ("sota_download_bytes_total",      MetricKind::Counter, false, "Number of bytes of completed downloads."),
+    ("sota_download_duration_seconds", MetricKind::Summary, true,  "Duration of finished downloads by result."),
+    ("sota_downloads_total",           MetricKind::Counter, true,  "Number of finished downloads by result."),
+    ("sota_installs_total",            MetricKind::Counter, true,  "Number of installation results by result code."),
+    ("sota_poll_duration_seconds",     MetricKind::Summary, true,  "Duration of polling the Core server by result."),
+];
+
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    value: f64,
+    count: u64,
+}
+
+struct Family {
+    kind:    MetricKind,
+    help:    &'static str,
+    samples: BTreeMap<String, Sample>,
+}
+
+
+/// A registry of client metrics that may be rendered in the Prometheus text
+/// format. Cloned instances share the same registry.
+#[derive(Clone)]
+pub struct Metrics {
+    families:  Arc<Mutex<BTreeMap<&'static str, Family>>>,
+    downloads: Arc<Mutex<HashMap<UpdateRequestId, u64>>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut families = BTreeMap::new();
+        for &(name, kind, labelled, help) in FAMILIES {
+            let mut samples = BTreeMap::new();
+            if !labelled {
+                samples.insert(String::new(), Sample::default());
+            }
+            families.insert(name, Family { kind: kind, help: help, samples: samples });
+        }
+
+        Metrics {
+            families:  Arc::new(Mutex::new(families)),
+            downloads: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Metrics {
+    /// Add `value` to a counter or gauge.
+    pub fn add(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, labels, |sample| sample.value += value);
+    }
+
+    /// Set the current value of a gauge.
+    pub fn set(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, labels, |sample| sample.value = value);
+    }
+
+    /// Record a new observation for a summary.
+    pub fn observe(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+        self.update(name, labels, |sample| {
+            sample.value += value;
+            sample.count += 1;
+        });
+    }
+
+    fn update<F: FnOnce(&mut Sample)>(&self, name: &'static str, labels: &[(&str, &str)], update: F) {
+        let mut families = self.families.lock().unwrap();
+        match families.get_mut(name) {
+            Some(family) => update(family.samples.entry(format_labels(labels)).or_insert(Sample::default())),
+            None         => error!("unknown metric: {}", name)
+        }
+    }
+
+    /// Count a command received by the `GlobalInterpreter`.
+    pub fn command(&self, name: &str) {
+        self.add("sota_commands_total", &[("command", name)], 1.0);
+    }
+
+    /// Count a failed authentication attempt.
+    pub fn auth_failed(&self) {
+        self.add("sota_auth_failures_total", &[], 1.0);
+    }
+
+    /// Record the duration of a poll for updates started at `started` (in
+    /// nanoseconds from `time::precise_time_ns`).
+    pub fn poll_finished(&self, started: u64, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        self.observe("sota_poll_duration_seconds", &[("result", result)], seconds_since(started));
+    }
+
+    /// Mark the start of a download so its duration can be recorded.
+    pub fn download_started(&self, id: &str) {
+        self.downloads.lock().unwrap().insert(id.to_string(), time::precise_time_ns());
+    }
+
+    /// Record the outcome of a download along with the downloaded size.
+    pub fn download_finished(&self, id: &str, result: &str, bytes: Option<u64>) {
+        self.add("sota_downloads_total", &[("result", result)], 1.0);
+        bytes.map(|bytes| self.add("sota_download_bytes_total", &[], bytes as f64));
+        self.downloads.lock().unwrap().remove(id).map(|started| {
+            self.observe("sota_download_duration_seconds", &[("result", result)], seconds_since(started));
+        });
+    }
+
+    /// Count each operation result code of an installation report.
+    pub fn install_finished(&self, report: &UpdateReport) {
+        for result in &report.operation_results {
+            self.add("sota_installs_total", &[("result_code", &format!("{:?}", result.result_code))], 1.0);
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut text = String::new();
+        for (name, family) in families.iter() {
+            let _ = writeln!(text, "# HELP {} {}", name, family.help);
+            let _ = writeln!(text, "# TYPE {} {}", name, family.kind.name());
+            for (labels, sample) in &family.samples {
+                match family.kind {
+                    MetricKind::Summary => {
+                        let _ = writeln!(text, "{}_sum{} {}", name, labels, sample.value);
+                        let _ = writeln!(text, "{}_count{} {}", name, labels, sample.count);
+                    }
+                    _ => { let _ = writeln!(text, "{}{} {}", name, labels, sample.value); }
+                }
+            }
+        }
+        text
+    }
+
+    /// Serve the rendered metrics over HTTP at `addr` in the background.
+    pub fn start_server(&self, addr: &SocketAddr) -> Result<(), String> {
+        let metrics = self.clone();
+        let server  = try!(HyperServer::http(addr).map_err(|err| format!("couldn't start metrics server: {}", err)));
+        thread::spawn(move || {
+            let (_, server) = server.handle(move |_| MetricsHandler::new(metrics.clone())).unwrap();
+            server.run();
+        });
+        Ok(info!("Metrics server listening at http://{}/metrics", addr))
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new()
+    }
+    let pairs = labels.iter().map(|&(key, val)| {
+        let val = val.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n");
+        format!(r#"{}="{}""#, key, val)
+    }).collect::<Vec<_>>();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn seconds_since(started: u64) -> f64 {
+    time::precise_time_ns().saturating_sub(started) as f64 / 1e9
+}
+
+
+struct MetricsHandler {
+    metrics: Metrics,
+    path:    String,
+}
+
+impl MetricsHandler {
+    fn new(metrics: Metrics) -> ServerHandler<HttpStream> {
+        ServerHandler::new(Box::new(MetricsHandler { metrics: metrics, path: String::new() }))
+    }
+}
+
+impl<T: Transport> Server<T> for MetricsHandler {
+    fn headers(&mut self, req: HyperRequest<T>) {
+        self.path = format!("{}", req.uri());
+    }
+
+    fn request(&mut self, _: Vec<u8>) {}
+
+    fn response(&mut self) -> (StatusCode, Option<Vec<u8>>) {
+        match self.path.as_str() {
+            "/" | "/metrics" => (StatusCode::Ok, Some(self.metrics.render().into_bytes())),
+            _                => (StatusCode::NotFound, None)
+        }
+    }
+
+    fn content_type(&self) -> Mime {
+        Mime(TopLevel::Text, SubLevel::Plain, vec![(Attr::Ext("version".to_string()), Value::Ext("0.0.4".to_string()))])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datatype::{UpdateReport, UpdateResultCode};
+
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.command("GetUpdateRequests");
+        metrics.auth_failed();
+        metrics.download_started("1");
+        metrics.download_finished("1", "complete", Some(1024));
+        metrics.install_finished(&UpdateReport::single("1".to_string(), UpdateResultCode::OK, "".to_string()));
+        metrics.observe("sota_poll_duration_seconds", &[("result", "ok")], 0.5);
+        metrics.set("sota_broadcast_in_flight", &[], 3.0);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE sota_downloads_total counter\n"));
+        assert!(text.contains("sota_commands_total{command=\"GetUpdateRequests\"} 1\n"));
+        assert!(text.contains("sota_auth_failures_total 1\n"));
+        assert!(text.contains("sota_download_bytes_total 1024\n"));
+        assert!(text.contains("sota_downloads_total{result=\"complete\"} 1\n"));
+        assert!(text.contains("sota_download_duration_seconds_count{result=\"complete\"} 1\n"));
+        assert!(text.contains("sota_installs_total{result_code=\"OK\"} 1\n"));
+        assert!(text.contains("sota_poll_duration_seconds_sum{result=\"ok\"} 0.5\n"));
+        assert!(text.contains("sota_broadcast_in_flight 3\n"));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        assert_eq!(format_labels(&[]), "");
+        assert_eq!(format_labels(&[("a", "x"), ("b", "say \"hi\"\n")]), r#"{a="x",b="say \"hi\"\n"}"#);
+    }
+}
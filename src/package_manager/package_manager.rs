@@ -91,7 +91,8 @@ impl FromStr for PackageManager {
 
 impl Decodable for PackageManager {
     fn decode<D: Decoder>(d: &mut D) -> Result<PackageManager, D::Error> {
-        d.read_str().and_then(|s| Ok(s.parse::<PackageManager>().expect("couldn't parse PackageManager")))
+        let manager = try!(d.read_str());
+        manager.parse().map_err(|err| d.error(&format!("{}", err)))
     }
 }
 
@@ -3,47 +3,96 @@ use hyper::net::{HttpStream, Transport};
 use hyper::server::{Server as HyperServer, Request as HyperRequest};
 use rustc_serialize::json;
 use rustc_serialize::json::Json;
-use std::{mem, str};
+use std::{cmp, mem, str, thread};
+use std::time::Duration;
 
-use datatype::{RpcRequest, RpcOk, RpcErr, SocketAddr, Url};
+use datatype::{Event, RpcRequest, RpcOk, RpcErr, SocketAddr, Url};
 use http::{Server, ServerHandler};
 use super::services::Services;
 
 
+/// The initial delay before retrying a failed registration.
+const RETRY_MIN_SEC: u64 = 1;
+/// The maximum delay between registration attempts.
+const RETRY_MAX_SEC: u64 = 60;
+/// How often to re-register to detect a restarted RVI node.
+const REFRESH_SEC: u64 = 30;
+
+
 /// The HTTP server endpoint for `RVI` client communication.
 pub struct Edge {
-    rvi_edge: SocketAddr,
-    services: Services,
+    rvi_edge:   SocketAddr,
+    rvi_client: Url,
+    services:   Services,
 }
 
 impl Edge {
-    /// Create a new `Edge` by registering each `RVI` service.
-    pub fn new(mut services: Services, rvi_edge: SocketAddr, rvi_client: Url) -> Self {
-        services.register_services(|service| {
-            let req = RpcRequest::new("register_service", RegisterServiceRequest {
-                network_address: format!("http://{}", rvi_edge),
-                service:         service.to_string(),
-            });
-            let resp = req.send(rvi_client.clone())
-                .unwrap_or_else(|err| panic!("RegisterServiceRequest failed: {}", err));
-            let rpc_ok = json::decode::<RpcOk<RegisterServiceResponse>>(&resp)
-                .unwrap_or_else(|err| panic!("couldn't decode RegisterServiceResponse: {}", err));
-            rpc_ok.result.expect("expected rpc_ok result").service
-        });
-
-        Edge { rvi_edge: rvi_edge, services: services }
+    /// Create a new `Edge` for handling `RVI` service requests.
+    pub fn new(services: Services, rvi_edge: SocketAddr, rvi_client: Url) -> Self {
+        Edge { rvi_edge: rvi_edge, rvi_client: rvi_client, services: services }
     }
 
-    /// Start the HTTP server listening for incoming RVI client connections.
+    /// Start the HTTP server listening for incoming RVI client connections,
+    /// registering each `RVI` service in the background.
     pub fn start(&mut self) {
+        let services   = self.services.clone();
+        let rvi_client = self.rvi_client.clone();
+
         let server = HyperServer::http(&*self.rvi_edge)
             .unwrap_or_else(|err| panic!("couldn't start rvi edge server: {}", err));
         let (addr, server) = server.handle(move |_| EdgeHandler::new(self.services.clone())).unwrap();
         info!("RVI server edge listening at http://{}.", addr);
+
+        // register the bound address in case an ephemeral port was requested
+        let network_address = format!("http://{}", addr.addr());
+        thread::spawn(move || keep_registered(services, network_address, rvi_client));
         server.run();
     }
 }
 
+/// Register all services with the RVI node, retrying with an exponential
+/// backoff until it becomes available, then periodically re-register so that
+/// a restarted RVI node learns about the services again.
+fn keep_registered(services: Services, network_address: String, rvi_client: Url) {
+    let mut connected = false;
+    let mut delay     = RETRY_MIN_SEC;
+    loop {
+        match services.register_services(|service| register_service(service, &network_address, &rvi_client)) {
+            Ok(_) => {
+                if !connected {
+                    info!("Registered RVI services with {}.", rvi_client);
+                    connected = true;
+                    services.sender.lock().unwrap().send(Event::RviConnected);
+                    services.resume_transfers();
+                }
+                delay = RETRY_MIN_SEC;
+                thread::sleep(Duration::from_secs(REFRESH_SEC));
+            }
+
+            Err(err) => {
+                if connected {
+                    connected = false;
+                    services.sender.lock().unwrap().send(Event::RviDisconnected(err.clone()));
+                }
+                warn!("RVI registration failed, retrying in {}s: {}", delay, err);
+                thread::sleep(Duration::from_secs(delay));
+                delay = cmp::min(delay * 2, RETRY_MAX_SEC);
+            }
+        }
+    }
+}
+
+fn register_service(service: &str, network_address: &str, rvi_client: &Url) -> Result<String, String> {
+    let req = RpcRequest::new("register_service", RegisterServiceRequest {
+        network_address: network_address.to_string(),
+        service:         service.to_string(),
+    });
+    let resp   = try!(req.send(rvi_client.clone()).map_err(|err| format!("RegisterServiceRequest failed: {}", err)));
+    let rpc_ok = try!(json::decode::<RpcOk<RegisterServiceResponse>>(&resp)
+                      .map_err(|err| format!("couldn't decode RegisterServiceResponse: {}", err)));
+    rpc_ok.result.map(|resp| resp.service).ok_or("expected rpc_ok result".to_string())
+}
+
 
 #[derive(RustcEncodable)]
 struct RegisterServiceRequest {
@@ -1,9 +1,11 @@
 pub mod edge;
 pub mod parameters;
 pub mod services;
+pub mod simulator;
 pub mod transfers;
 
 pub use self::edge::Edge;
 pub use self::parameters::Parameter;
 pub use self::services::{RemoteServices, Services};
+pub use self::simulator::{Faults, RviSimulator, SimMessage};
 pub use self::transfers::Transfer;
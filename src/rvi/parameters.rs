@@ -1,8 +1,8 @@
 use std::sync::Mutex;
 
-use datatype::{ChunkReceived, Event, DownloadComplete, UpdateRequestId, UpdateAvailable};
+use datatype::{ChunkReceived, Error, Event, DownloadComplete, UpdateRequestId, UpdateAvailable};
 use super::services::{BackendServices, RemoteServices};
-use super::transfers::Transfers;
+use super::transfers::{checksum_hasher, Transfers};
 
 
 /// Each `Parameter` implementation handles a specific kind of RVI client request,
@@ -10,6 +10,11 @@ use super::transfers::Transfers;
 pub trait Parameter {
     fn handle(&self, remote: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>)
               -> Result<Option<Event>, String>;
+
+    /// Reject malformed requests before they are handled.
+    fn validate(&self, _: &Mutex<Transfers>) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 
@@ -20,9 +25,14 @@ pub struct Notify {
 }
 
 impl Parameter for Notify {
-    fn handle(&self, remote: &Mutex<RemoteServices>, _: &Mutex<Transfers>) -> Result<Option<Event>, String> {
+    fn handle(&self, remote: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>) -> Result<Option<Event>, String> {
         remote.lock().unwrap().backend = Some(self.services.clone());
-        Ok(Some(Event::UpdateAvailable(self.update_available.clone())))
+        let update = &self.update_available;
+        match transfers.lock().unwrap().announce(update.update_id.clone(), update.size) {
+            Ok(_)                   => Ok(Some(Event::UpdateAvailable(update.clone()))),
+            Err(Error::DiskFull(e)) => Ok(Some(Event::DiskFull(update.update_id.clone(), e))),
+            Err(err)                => Err(format!("couldn't check disk space: {}", err))
+        }
     }
 }
 
@@ -37,19 +47,30 @@ pub struct Start {
 impl Parameter for Start {
     fn handle(&self, remote: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>) -> Result<Option<Event>, String> {
         info!("Starting transfer for update_id {}", self.update_id);
+        let remote        = remote.lock().unwrap();
         let mut transfers = transfers.lock().unwrap();
-        transfers.push(self.update_id.clone(), self.checksum.clone());
-
-        let remote = remote.lock().unwrap();
-        let chunk  = ChunkReceived {
+        match transfers.check_space(&self.update_id) {
+            Ok(_)                   => (),
+            Err(Error::DiskFull(e)) => return Ok(Some(Event::DiskFull(self.update_id.clone(), e))),
+            Err(err)                => return Err(format!("couldn't check disk space: {}", err))
+        }
+        transfers.push(self.update_id.clone(), self.checksum.clone(), self.chunkscount, remote.backend.clone());
+        let transfer = try!(transfers.get(self.update_id.clone()).ok_or(format!("couldn't start transfer {}", self.update_id)));
+
+        let chunk = ChunkReceived {
             device:    remote.device_id.clone(),
             update_id: self.update_id.clone(),
-            chunks:    Vec::new()
+            chunks:    transfer.transferred_chunks.clone(),
+            missing:   transfer.missing_chunks(),
         };
         remote.send_chunk_received(chunk)
             .map(|_| None)
             .map_err(|err| format!("error sending start ack: {}", err))
     }
+
+    fn validate(&self, _: &Mutex<Transfers>) -> Result<(), String> {
+        checksum_hasher(&self.checksum).map(|_| ())
+    }
 }
 
 
@@ -75,12 +96,21 @@ impl Parameter for Chunk {
                     device:    remote.device_id.clone(),
                     update_id: self.update_id.clone(),
                     chunks:    transfer.transferred_chunks.clone(),
+                    missing:   transfer.missing_chunks(),
                 };
+                let received = transfer.transferred_chunks.len() as u64;
                 remote.send_chunk_received(chunk)
-                    .map(|_| None)
+                    .map(|_| Some(Event::DownloadProgress(self.update_id.clone(), received)))
                     .map_err(|err| format!("error sending ChunkReceived: {}", err))
             })
     }
+
+    fn validate(&self, transfers: &Mutex<Transfers>) -> Result<(), String> {
+        let transfers = transfers.lock().unwrap();
+        let transfer  = try!(transfers.get(self.update_id.clone())
+                             .ok_or(format!("couldn't find transfer for update_id {}", self.update_id)));
+        transfer.check_index(self.index)
+    }
 }
 
 
@@ -91,11 +121,23 @@ pub struct Finish {
 }
 
 impl Parameter for Finish {
-    fn handle(&self, _: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>) -> Result<Option<Event>, String> {
+    fn handle(&self, remote: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>) -> Result<Option<Event>, String> {
+        let remote        = remote.lock().unwrap();
         let mut transfers = transfers.lock().unwrap();
         let image = {
             let transfer = try!(transfers.get(self.update_id.clone())
                                 .ok_or(format!("unknown package: {}", self.update_id)));
+            let missing  = transfer.missing_chunks();
+            if !missing.is_empty() {
+                let chunk = ChunkReceived {
+                    device:    remote.device_id.clone(),
+                    update_id: self.update_id.clone(),
+                    chunks:    transfer.transferred_chunks.clone(),
+                    missing:   missing,
+                };
+                try!(remote.send_chunk_received(chunk)
+                     .map_err(|err| format!("error sending ChunkReceived: {}", err)));
+            }
             let package  = try!(transfer.assemble_package()
                                 .map_err(|err| format!("couldn't assemble package: {}", err)));
             try!(package.into_os_string().into_string()
@@ -125,11 +167,17 @@ impl Parameter for Report {
 
 
 #[derive(RustcDecodable, RustcEncodable)]
-pub struct Abort;
+pub struct Abort {
+    update_id: UpdateRequestId
+}
 
 impl Parameter for Abort {
     fn handle(&self, _: &Mutex<RemoteServices>, transfers: &Mutex<Transfers>) -> Result<Option<Event>, String> {
-        transfers.lock().unwrap().clear();
-        Ok(None)
+        let mut transfers = transfers.lock().unwrap();
+        try!(transfers.get(self.update_id.clone())
+             .ok_or(format!("couldn't find transfer for update_id {}", self.update_id)));
+        transfers.remove(self.update_id.clone());
+        info!("Aborted transfer of {}", self.update_id);
+        Ok(Some(Event::DownloadFailed(self.update_id.clone(), "transfer aborted".to_string())))
     }
 }
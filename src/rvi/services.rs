@@ -1,18 +1,25 @@
 use chan;
 use chan::Sender;
 use rustc_serialize::{json, Decodable, Encodable};
+use std::collections::HashMap;
 use std::thread;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use time;
 
 use datatype::{ChunkReceived, DownloadStarted, Event, InstalledSoftware,
-               RpcRequest, RpcOk, RpcErr, RviConfig, UpdateReport, UpdateRequestId,
-               Url};
+               RpcRequest, RpcOk, RpcErr, RviConfig, StorageLimits, UpdateReport,
+               UpdateRequestId, Url};
 use super::parameters::{Abort, Chunk, Finish, Notify, Parameter, Report, Start};
 use super::transfers::Transfers;
 
 
+/// The name of each service registered with the RVI node.
+pub const SERVICE_NAMES: &'static [&'static str] = &[
+    "/sota/notify", "/sota/start", "/sota/chunk", "/sota/abort", "/sota/finish", "/sota/getpackages"
+];
+
+
 /// Hold references to RVI service endpoints, currently active `Transfers`, and
 /// where to broadcast outcome `Event`s to.
 #[derive(Clone)]
@@ -23,42 +30,86 @@ pub struct Services {
 }
 
 impl Services {
-    /// Set up a new RVI service handler, pruning any inactive `Transfer`s each second.
-    pub fn new(rvi_cfg: RviConfig, device_id: String, sender: Sender<Event>) -> Self {
-        let transfers = Arc::new(Mutex::new(Transfers::new(rvi_cfg.storage_dir)));
+    /// Set up a new RVI service handler, reloading any `Transfer`s persisted by
+    /// a previous run and pruning any inactive ones each second.
+    pub fn new(rvi_cfg: RviConfig, device_id: String, limits: StorageLimits, sender: Sender<Event>) -> Self {
+        let mut transfers = Transfers::load(rvi_cfg.storage_dir);
+        transfers.limits  = limits;
+        let transfers     = Arc::new(Mutex::new(transfers));
+        let sender    = Arc::new(Mutex::new(sender));
         rvi_cfg.timeout.map_or_else(|| info!("Transfers will never time out."), |timeout| {
             info!("Transfers timeout after {} seconds.", timeout);
             let transfers = transfers.clone();
+            let sender    = sender.clone();
             thread::spawn(move || {
                 let tick = chan::tick(Duration::from_secs(1));
                 loop {
                     let _ = tick.recv();
-                    let mut transfers = transfers.lock().unwrap();
-                    transfers.prune(time::get_time().sec, timeout);
+                    let timeouts = transfers.lock().unwrap().prune(time::get_time().sec, timeout);
+                    for id in timeouts {
+                        sender.lock().unwrap().send(Event::DownloadFailed(id, "transfer timed out".to_string()));
+                    }
                 }
             });
         });
 
         Services {
             remote:    Arc::new(Mutex::new(RemoteServices::new(device_id, rvi_cfg.client))),
-            sender:    Arc::new(Mutex::new(sender)),
+            sender:    sender,
             transfers: transfers,
         }
     }
 
-    /// Register each RVI endpoint with the provided registration function which
-    /// should return a `String` representation of the URL used to contact that
-    /// service.
-    pub fn register_services<F: Fn(&str) -> String>(&mut self, register: F) {
-        let _ = register("/sota/notify");
+    /// Register each RVI endpoint in `SERVICE_NAMES` with the provided
+    /// registration function which should return a `String` representation of
+    /// the URL used to contact that service.
+    pub fn register_services<F>(&self, register: F) -> Result<(), String>
+        where F: Fn(&str) -> Result<String, String>
+    {
+        let mut urls = HashMap::new();
+        for name in SERVICE_NAMES {
+            urls.insert(*name, try!(register(name)));
+        }
+        let url   = |name: &str| urls.get(name).cloned().ok_or(format!("{} is missing from SERVICE_NAMES", name));
+        let local = LocalServices {
+            start:       try!(url("/sota/start")),
+            chunk:       try!(url("/sota/chunk")),
+            abort:       try!(url("/sota/abort")),
+            finish:      try!(url("/sota/finish")),
+            getpackages: try!(url("/sota/getpackages"))
+        };
+        self.remote.lock().unwrap().local = Some(local);
+        Ok(())
+    }
+
+    /// Notify the backend of the chunks already received for each resumed
+    /// `Transfer` so that it only sends the chunks still missing.
+    ///
+    /// Like the `Parameter` handlers, the `remote` lock is taken before the
+    /// `transfers` lock, which is released before any acknowledgement is sent.
+    pub fn resume_transfers(&self) {
         let mut remote = self.remote.lock().unwrap();
-        remote.local   = Some(LocalServices {
-            start:    register("/sota/start"),
-            chunk:    register("/sota/chunk"),
-            abort:    register("/sota/abort"),
-            finish:   register("/sota/finish"),
-            getpackages: register("/sota/getpackages")
-        });
+        let chunks     = {
+            let transfers = self.transfers.lock().unwrap();
+            transfers.iter().map(|transfer| {
+                if remote.backend.is_none() {
+                    remote.backend = transfer.backend.clone();
+                }
+                ChunkReceived {
+                    device:    remote.device_id.clone(),
+                    update_id: transfer.update_id.clone(),
+                    chunks:    transfer.transferred_chunks.clone(),
+                    missing:   transfer.missing_chunks(),
+                }
+            }).collect::<Vec<_>>()
+        };
+
+        for chunk in chunks {
+            let update_id = chunk.update_id.clone();
+            let _ = remote.send_chunk_received(chunk)
+                .map(|_| info!("Requested remaining chunks for update_id {}", update_id))
+                .map_err(|err| error!("couldn't resume transfer {}: {}", update_id, err));
+        }
     }
 
     /// Handle an incoming message for a specific service endpoint.
@@ -76,7 +127,8 @@ impl Services {
 
     /// Parse the message as an `RpcRequest<RviMessage<Parameter>>` then delegate
     /// to the specific `Parameter.handle()` function, forwarding any returned
-    /// `Event` to the `Services` sender.
+    /// `Event` to the `Services` sender. A `DiskFull` event is also returned to
+    /// the caller as an RPC error.
     fn handle_message<P>(&self, id: u64, msg: &str) -> Result<RpcOk<i32>, RpcErr>
         where P: Parameter + Encodable + Decodable
     {
@@ -84,12 +136,26 @@ impl Services {
             error!("couldn't decode message: {}", err);
             RpcErr::invalid_params(id, format!("couldn't decode message: {}", err))
         }));
-        let event = try!(request.params.parameters[0].handle(&self.remote, &self.transfers).map_err(|err| {
+        let param = try!(request.params.parameters.first().ok_or_else(|| {
+            error!("no parameters in message");
+            RpcErr::invalid_params(request.id, "expected one parameter".to_string())
+        }));
+        try!(param.validate(&self.transfers).map_err(|err| {
+            error!("invalid parameters: {}", err);
+            RpcErr::invalid_params(request.id, format!("invalid parameters: {}", err))
+        }));
+        let event = try!(param.handle(&self.remote, &self.transfers).map_err(|err| {
             error!("couldn't handle parameters: {}", err);
             RpcErr::unspecified(request.id, format!("couldn't handle parameters: {}", err))
         }));
-        event.map(|ev| self.sender.lock().unwrap().send(ev));
-        Ok(RpcOk::new(request.id, None))
+        match event {
+            Some(Event::DiskFull(id, reason)) => {
+                self.sender.lock().unwrap().send(Event::DiskFull(id, reason.clone()));
+                Err(RpcErr::unspecified(request.id, format!("disk full: {}", reason)))
+            }
+            Some(ev) => { self.sender.lock().unwrap().send(ev); Ok(RpcOk::new(request.id, None)) }
+            None     => Ok(RpcOk::new(request.id, None))
+        }
     }
 }
 
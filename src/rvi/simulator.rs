@@ -0,0 +1,334 @@
+use chan;
+use chan::{Receiver, Sender};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use hyper::StatusCode;
+use hyper::net::{HttpStream, Transport};
+use hyper::server::{Server as HyperServer, Request as HyperRequest};
+use rustc_serialize::Encodable;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json;
+use rustc_serialize::json::Json;
+use std::collections::HashMap;
+use std::{mem, str, thread};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use datatype::{RpcRequest, RpcOk, RpcErr, UpdateAvailable, UpdateRequestId, Url};
+use http::{Server, ServerHandler};
+use super::services::{BackendServices, RviMessage, SERVICE_NAMES};
+
+
+/// The prefix added to each service name registered with the simulator.
+pub const SERVICE_PREFIX: &'static str = "genivi.org/simulator";
+
+
+/// A message sent by the client to one of the backend services.
+#[derive(Clone, Debug)]
+pub struct SimMessage {
+    pub service:    String,
+    pub parameters: Vec<Json>,
+}
+
+/// Faults to inject into a simulated package transfer.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    /// Chunk indices that are never sent to the client.
+    pub lost_chunks:  Vec<u64>,
+    /// Send a checksum that won't match the package.
+    pub bad_checksum: bool,
+    /// Abort the transfer after this many chunks have been sent.
+    pub abort_after:  Option<u64>,
+}
+
+
+/// A stand-in for an RVI node that accepts service registrations from the
+/// client, records the messages it sends to the backend, and can drive the
+/// client through a complete package transfer.
+pub struct RviSimulator {
+    pub addr:   SocketAddr,
+    registered: Arc<Mutex<HashMap<String, String>>>,
+    messages:   Receiver<SimMessage>,
+}
+
+impl RviSimulator {
+    /// Start a new simulator listening on the given address in the background.
+    pub fn start(addr: &str) -> RviSimulator {
+        let addr: SocketAddr = addr.parse().expect("couldn't parse simulator address");
+        let registered       = Arc::new(Mutex::new(HashMap::new()));
+        let (msg_tx, msg_rx) = chan::async::<SimMessage>();
+        let (addr_tx, addr_rx) = chan::sync::<SocketAddr>(0);
+
+        let shared = registered.clone();
+        thread::spawn(move || {
+            let server = HyperServer::http(&addr)
+                .unwrap_or_else(|err| panic!("couldn't start rvi simulator: {}", err));
+            let (listening, server) = server.handle(move |_| {
+                SimHandler::new(shared.clone(), msg_tx.clone())
+            }).unwrap();
+            addr_tx.send(*listening.addr());
+            server.run();
+        });
+
+        let addr = addr_rx.recv().expect("couldn't get simulator address");
+        info!("RVI simulator listening at http://{}.", addr);
+        RviSimulator { addr: addr, registered: registered, messages: msg_rx }
+    }
+
+    /// The URL that the client should use to contact this RVI node.
+    pub fn url(&self) -> Url {
+        format!("http://{}", self.addr).parse().expect("couldn't parse simulator url")
+    }
+
+    /// The backend services announced to the client on notification.
+    pub fn backend() -> BackendServices {
+        BackendServices {
+            start:    "backend/start".to_string(),
+            ack:      "backend/ack".to_string(),
+            report:   "backend/report".to_string(),
+            packages: "backend/packages".to_string(),
+        }
+    }
+
+    /// Wait until the client has registered each of its services.
+    pub fn wait_registered(&self, timeout: Duration) -> bool {
+        let deadline = chan::after(timeout);
+        let tick     = chan::tick(Duration::from_millis(50));
+        loop {
+            let all_registered = {
+                let registered = self.registered.lock().unwrap();
+                SERVICE_NAMES.iter().all(|name| registered.contains_key(&format!("{}{}", SERVICE_PREFIX, name)))
+            };
+            if all_registered {
+                return true
+            }
+            chan_select! {
+                deadline.recv() => return false,
+                tick.recv()     => (),
+            }
+        }
+    }
+
+    /// Wait for the next message sent by the client to a backend service.
+    pub fn next_message(&self, timeout: Duration) -> Option<SimMessage> {
+        let deadline = chan::after(timeout);
+        let messages = self.messages.clone();
+        chan_select! {
+            deadline.recv()      => None,
+            messages.recv() -> msg => msg,
+        }
+    }
+
+    /// Wait for the next message sent to a specific backend service, skipping
+    /// any others.
+    pub fn expect_message(&self, service: &str, timeout: Duration) -> Option<SimMessage> {
+        while let Some(msg) = self.next_message(timeout) {
+            if msg.service == service {
+                return Some(msg)
+            }
+        }
+        None
+    }
+
+    /// Send a message to one of the services registered by the client.
+    pub fn send<E: Encodable>(&self, service: &str, parameters: E) -> Result<String, String> {
+        let address = try!(self.registered.lock().unwrap()
+                           .get(&format!("{}{}", SERVICE_PREFIX, service)).cloned()
+                           .ok_or(format!("service not registered: {}", service)));
+        let url     = try!(address.parse::<Url>().map_err(|err| format!("bad service address: {}", err)));
+        RpcRequest::new("message", RviMessage::new(service, vec![parameters], 60)).send(url)
+    }
+
+    /// Notify the client that a new update is available.
+    pub fn notify(&self, update_id: &str, size: u64) -> Result<String, String> {
+        self.send("/sota/notify", SimNotify {
+            update_available: UpdateAvailable {
+                update_id:            update_id.to_string(),
+                signature:            "".to_string(),
+                description:          "simulated update".to_string(),
+                request_confirmation: false,
+                size:                 size,
+            },
+            services: RviSimulator::backend(),
+        })
+    }
+
+    /// Start the transfer of an update.
+    pub fn start_transfer(&self, update_id: &str, chunkscount: u64, checksum: &str) -> Result<String, String> {
+        self.send("/sota/start", SimStart {
+            update_id:   update_id.to_string(),
+            chunkscount: chunkscount,
+            checksum:    checksum.to_string(),
+        })
+    }
+
+    /// Send a single chunk of an update, where the first index is 1.
+    pub fn send_chunk(&self, update_id: &str, index: u64, data: &[u8]) -> Result<String, String> {
+        self.send("/sota/chunk", SimChunk {
+            update_id: update_id.to_string(),
+            bytes:     data.to_base64(STANDARD),
+            index:     index,
+        })
+    }
+
+    /// Finish the transfer of an update.
+    pub fn finish(&self, update_id: &str) -> Result<String, String> {
+        self.send("/sota/finish", SimFinish { update_id: update_id.to_string(), signature: "".to_string() })
+    }
+
+    /// Abort the transfer of an update.
+    pub fn abort(&self, update_id: &str) -> Result<String, String> {
+        self.send("/sota/abort", SimAbort { update_id: update_id.to_string() })
+    }
+
+    /// Ask the client for a list of its installed software.
+    pub fn get_packages(&self) -> Result<String, String> {
+        self.send("/sota/getpackages", SimGetPackages)
+    }
+
+    /// Drive the client through notify, start, chunks and finish for a package
+    /// split into `chunk_size` byte chunks, injecting any `Faults`. Returns the
+    /// outcome of the final step.
+    pub fn transfer(&self, update_id: &str, package: &[u8], chunk_size: usize, faults: &Faults) -> Result<String, String> {
+        let chunks   = package.chunks(chunk_size).collect::<Vec<_>>();
+        let checksum = if faults.bad_checksum {
+            format!("sha256:{}", String::from_utf8(vec![b'0'; 64]).unwrap())
+        } else {
+            let mut hasher = Sha256::new();
+            hasher.input(package);
+            format!("sha256:{}", hasher.result_str())
+        };
+
+        try!(self.notify(update_id, package.len() as u64));
+        try!(self.start_transfer(update_id, chunks.len() as u64, &checksum));
+        for (n, chunk) in chunks.iter().enumerate() {
+            let index = n as u64 + 1;
+            if faults.abort_after == Some(n as u64) {
+                return self.abort(update_id)
+            } else if faults.lost_chunks.contains(&index) {
+                continue
+            }
+            try!(self.send_chunk(update_id, index, chunk));
+        }
+        self.finish(update_id)
+    }
+}
+
+
+#[derive(RustcEncodable)]
+struct SimNotify {
+    update_available: UpdateAvailable,
+    services:         BackendServices,
+}
+
+#[derive(RustcEncodable)]
+struct SimStart {
+    update_id:   UpdateRequestId,
+    chunkscount: u64,
+    checksum:    String,
+}
+
+#[derive(RustcEncodable)]
+struct SimChunk {
+    update_id: UpdateRequestId,
+    bytes:     String,
+    index:     u64,
+}
+
+#[derive(RustcEncodable)]
+struct SimFinish {
+    update_id: UpdateRequestId,
+    signature: String,
+}
+
+#[derive(RustcEncodable)]
+struct SimAbort {
+    update_id: UpdateRequestId,
+}
+
+#[derive(RustcEncodable)]
+struct SimGetPackages;
+
+#[derive(RustcDecodable, RustcEncodable)]
+struct Registered {
+    service: String,
+    status:  i32,
+}
+
+
+struct SimHandler {
+    registered: Arc<Mutex<HashMap<String, String>>>,
+    messages:   Sender<SimMessage>,
+    resp_code:  StatusCode,
+    resp_body:  Option<Vec<u8>>
+}
+
+impl SimHandler {
+    fn new(registered: Arc<Mutex<HashMap<String, String>>>, messages: Sender<SimMessage>) -> ServerHandler<HttpStream> {
+        ServerHandler::new(Box::new(SimHandler {
+            registered: registered,
+            messages:   messages,
+            resp_code:  StatusCode::InternalServerError,
+            resp_body:  None,
+        }))
+    }
+
+    fn handle(&self, body: &[u8]) -> Result<String, RpcErr> {
+        let text   = try!(str::from_utf8(body).map_err(|err| RpcErr::parse_error(err.to_string())));
+        let data   = try!(Json::from_str(text).map_err(|err| RpcErr::parse_error(err.to_string())));
+        let object = try!(data.as_object().ok_or(RpcErr::parse_error("not an object".to_string())));
+        let id     = try!(object.get("id").and_then(|x| x.as_u64())
+                          .ok_or(RpcErr::parse_error("expected id".to_string())));
+        let method = try!(object.get("method").and_then(|x| x.as_string())
+                          .ok_or(RpcErr::invalid_request(id, "expected method".to_string())));
+        let params = try!(object.get("params").and_then(|p| p.as_object())
+                          .ok_or(RpcErr::invalid_request(id, "expected params".to_string())));
+
+        match method {
+            "register_service" => {
+                let service = try!(params.get("service").and_then(|s| s.as_string())
+                                   .ok_or(RpcErr::invalid_params(id, "expected params.service".to_string())));
+                let address = try!(params.get("network_address").and_then(|s| s.as_string())
+                                   .ok_or(RpcErr::invalid_params(id, "expected params.network_address".to_string())));
+                let name    = format!("{}{}", SERVICE_PREFIX, service);
+                self.registered.lock().unwrap().insert(name.clone(), address.to_string());
+                let resp = RpcOk::new(id, Some(Registered { service: name, status: 0 }));
+                Ok(json::encode(&resp).expect("couldn't encode register_service response"))
+            }
+
+            "message" => {
+                let service = try!(params.get("service_name").and_then(|s| s.as_string())
+                                   .ok_or(RpcErr::invalid_params(id, "expected params.service_name".to_string())));
+                let parameters = params.get("parameters").and_then(|p| p.as_array()).cloned().unwrap_or(Vec::new());
+                self.messages.send(SimMessage { service: service.to_string(), parameters: parameters });
+                Ok(json::encode(&RpcOk::<i32>::new(id, None)).expect("couldn't encode message response"))
+            }
+
+            _ => Err(RpcErr::method_not_found(id, format!("unknown method: {}", method)))
+        }
+    }
+}
+
+impl<T: Transport> Server<T> for SimHandler {
+    fn headers(&mut self, _: HyperRequest<T>) {}
+
+    fn request(&mut self, body: Vec<u8>) {
+        match self.handle(&body) {
+            Ok(body) => {
+                self.resp_code = StatusCode::Ok;
+                self.resp_body = Some(body.into_bytes());
+            }
+
+            Err(err) => {
+                let body = json::encode::<RpcErr>(&err).expect("couldn't encode RpcErr response");
+                self.resp_code = StatusCode::BadRequest;
+                self.resp_body = Some(body.into_bytes());
+            }
+        }
+    }
+
+    fn response(&mut self) -> (StatusCode, Option<Vec<u8>>) {
+        (self.resp_code, mem::replace(&mut self.resp_body, None))
+    }
+}
@@ -1,27 +1,89 @@
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
+use crypto::sha2::{Sha256, Sha512};
 use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json;
 use std::fs;
 use std::collections::HashMap;
-use std::fs::File;
+use std::collections::hash_map::Values;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::vec::Vec;
 use time;
 
-use datatype::UpdateRequestId;
+use datatype::{ChunkRange, Error, StorageLimits, UpdateRequestId};
+use super::services::BackendServices;
 
 
 /// Holds all currently active transfers where each is referenced by `UpdateRequestId`.
 pub struct Transfers {
     items:       HashMap<UpdateRequestId, Transfer>,
-    storage_dir: String
+    announced:   HashMap<UpdateRequestId, u64>,
+    storage_dir: String,
+    pub limits:  StorageLimits,
 }
 
 impl Transfers {
     pub fn new(storage_dir: String) -> Transfers {
-        Transfers { items: HashMap::new(), storage_dir: storage_dir }
+        Transfers {
+            items:       HashMap::new(),
+            announced:   HashMap::new(),
+            storage_dir: storage_dir,
+            limits:      StorageLimits::default(),
+        }
+    }
+
+    /// Record the size of an update announced by the backend, returning an
+    /// error if there is not enough disk space to store it.
+    pub fn announce(&mut self, update_id: UpdateRequestId, size: u64) -> Result<(), Error> {
+        try!(self.limits.check(&self.storage_dir, size));
+        self.announced.insert(update_id, size);
+        Ok(())
+    }
+
+    /// Check there is enough disk space to start the transfer of an update,
+    /// using the size previously announced for it.
+    pub fn check_space(&mut self, update_id: &UpdateRequestId) -> Result<(), Error> {
+        let size = self.announced.remove(update_id).unwrap_or(0);
+        self.limits.check(&self.storage_dir, size)
+    }
+
+    /// Reload any transfers that were persisted in the storage directory by a
+    /// previous run of the client.
+    pub fn load(storage_dir: String) -> Transfers {
+        let mut transfers = Transfers::new(storage_dir.clone());
+        let mut path      = PathBuf::from(&storage_dir);
+        path.push("downloads");
+
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(_)      => return transfers
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err)  => { error!("found a malformed entry: {}", err); continue }
+            };
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue
+            }
+
+            match Transfer::load(storage_dir.clone(), &path) {
+                Ok(transfer) => {
+                    info!("Resuming transfer for update_id {} with {} chunks received.",
+                          transfer.update_id, transfer.transferred_chunks.len());
+                    transfers.items.insert(transfer.update_id.clone(), transfer);
+                }
+                Err(err) => error!("couldn't load transfer from {:?}: {}", path, err)
+            }
+        }
+        transfers
+    }
+
+    pub fn iter(&self) -> Values<UpdateRequestId, Transfer> {
+        self.items.values()
     }
 
     pub fn get(&self, update_id: UpdateRequestId) -> Option<&Transfer> {
@@ -32,20 +94,33 @@ impl Transfers {
         self.items.get_mut(&update_id)
     }
 
-    pub fn push(&mut self, update_id: UpdateRequestId, checksum: String) {
-        let transfer = Transfer::new(self.storage_dir.to_string(), update_id.clone(), checksum);
-        self.items.insert(update_id, transfer);
-    }
+    /// Start a new transfer, or resume an existing one with the same checksum
+    /// and chunk count.
+    pub fn push(&mut self, update_id: UpdateRequestId, checksum: String, chunkscount: u64,
+                backend: Option<BackendServices>) {
+        if let Some(transfer) = self.items.get_mut(&update_id) {
+            if transfer.checksum == checksum && transfer.chunkscount == chunkscount {
+                transfer.backend = backend;
+                transfer.last_chunk_received = time::get_time().sec;
+                return;
+            }
+        }
+        self.items.remove(&update_id); // drop any stale chunks before saving the new state
 
-    pub fn remove(&mut self, update_id: UpdateRequestId) {
-        self.items.remove(&update_id);
+        let mut transfer = Transfer::new(self.storage_dir.to_string(), update_id.clone(), checksum, chunkscount);
+        transfer.backend = backend;
+        let _ = transfer.save().map_err(|err| error!("couldn't save transfer {}: {}", update_id, err));
+        self.items.insert(update_id, transfer);
     }
 
-    pub fn clear(&mut self) {
-        self.items.clear();
+    /// Remove a transfer, returning whether it existed.
+    pub fn remove(&mut self, update_id: UpdateRequestId) -> bool {
+        self.items.remove(&update_id).is_some()
     }
 
-    pub fn prune(&mut self, now: i64, timeout: i64) {
+    /// Remove any transfers inactive for longer than `timeout` seconds,
+    /// returning the `UpdateRequestId` of each.
+    pub fn prune(&mut self, now: i64, timeout: i64) -> Vec<UpdateRequestId> {
         let mut timeouts = Vec::new();
         for (id, transfer) in &mut self.items {
             if now - transfer.last_chunk_received > timeout {
@@ -53,10 +128,27 @@ impl Transfers {
             }
         }
 
-        for id in timeouts {
-            self.items.remove(&id);
+        for id in &timeouts {
+            self.items.remove(id);
             info!("Transfer for update_id {} timed out.", id)
         }
+        timeouts
+    }
+}
+
+
+/// Select the hash algorithm from the checksum prefix (either `sha256:` or
+/// `sha512:`), falling back to SHA1 when no prefix is given. Returns the hasher
+/// and the expected hex digest.
+pub fn checksum_hasher(checksum: &str) -> Result<(Box<Digest>, String), String> {
+    let mut parts = checksum.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(hex), None)           => Ok((Box::new(Sha1::new()), hex.to_lowercase())),
+        (Some("sha1"), Some(hex))   => Ok((Box::new(Sha1::new()), hex.to_lowercase())),
+        (Some("sha256"), Some(hex)) => Ok((Box::new(Sha256::new()), hex.to_lowercase())),
+        (Some("sha512"), Some(hex)) => Ok((Box::new(Sha512::new()), hex.to_lowercase())),
+        (Some(algo), Some(_))       => Err(format!("unsupported checksum algorithm: {}", algo)),
+        (None, _)                   => Err("empty checksum".to_string())
     }
 }
 
@@ -65,21 +157,142 @@ impl Transfers {
 pub struct Transfer {
     pub update_id:           UpdateRequestId,
     pub checksum:            String,
+    pub chunkscount:         u64,
     pub transferred_chunks:  Vec<u64>,
     pub storage_dir:         String,
-    pub last_chunk_received: i64
+    pub last_chunk_received: i64,
+    pub backend:             Option<BackendServices>,
+}
+
+/// The number of chunks received between each rewrite of the transfer
+/// metadata. Each chunk in between is appended to a separate journal file.
+const CHECKPOINT_CHUNKS: usize = 256;
+
+/// The metadata of a `Transfer` that is persisted alongside its chunks.
+#[derive(RustcDecodable, RustcEncodable)]
+struct TransferState {
+    update_id:          UpdateRequestId,
+    checksum:           String,
+    chunkscount:        u64,
+    transferred_chunks: Vec<u64>,
+    backend:            Option<BackendServices>,
 }
 
 impl Transfer {
     /// Prepare for the transfer of a new package.
-    pub fn new(storage_dir: String, update_id: UpdateRequestId, checksum: String) -> Transfer {
+    pub fn new(storage_dir: String, update_id: UpdateRequestId, checksum: String, chunkscount: u64) -> Transfer {
         Transfer {
             update_id:           update_id,
             checksum:            checksum,
+            chunkscount:         chunkscount,
             transferred_chunks:  Vec::new(),
             storage_dir:         storage_dir,
-            last_chunk_received: time::get_time().sec
+            last_chunk_received: time::get_time().sec,
+            backend:             None,
+        }
+    }
+
+    /// Read a persisted `Transfer` from the metadata file at `path`.
+    pub fn load(storage_dir: String, path: &Path) -> Result<Transfer, String> {
+        let mut file = try!(File::open(path).map_err(|err| format!("couldn't open transfer file: {}", err)));
+        let mut text = String::new();
+        try!(file.read_to_string(&mut text).map_err(|err| format!("couldn't read transfer file: {}", err)));
+        let state: TransferState = try!(json::decode(&text).map_err(|err| format!("couldn't decode transfer: {}", err)));
+
+        let mut transfer = Transfer {
+            update_id:           state.update_id,
+            checksum:            state.checksum,
+            chunkscount:         state.chunkscount,
+            transferred_chunks:  state.transferred_chunks,
+            storage_dir:         storage_dir,
+            last_chunk_received: time::get_time().sec,
+            backend:             state.backend,
+        };
+        try!(transfer.replay_journal());
+        Ok(transfer)
+    }
+
+    // Add the chunks recorded in the journal since the last checkpoint,
+    // ignoring any partially written final line.
+    fn replay_journal(&mut self) -> Result<(), String> {
+        let mut text = String::new();
+        match File::open(try!(self.get_journal_path())) {
+            Ok(mut file) => try!(file.read_to_string(&mut text).map_err(|err| format!("couldn't read journal: {}", err))),
+            Err(_)       => return Ok(())
+        };
+        for index in text.lines().filter_map(|line| line.trim().parse::<u64>().ok()) {
+            if index >= 1 && index <= self.chunkscount {
+                self.transferred_chunks.push(index);
+            }
+        }
+        self.transferred_chunks.sort();
+        self.transferred_chunks.dedup();
+        Ok(())
+    }
+
+    /// Persist the transfer metadata so that the transfer may be resumed.
+    pub fn save(&self) -> Result<(), String> {
+        let state = TransferState {
+            update_id:          self.update_id.clone(),
+            checksum:           self.checksum.clone(),
+            chunkscount:        self.chunkscount,
+            transferred_chunks: self.transferred_chunks.clone(),
+            backend:            self.backend.clone(),
+        };
+        let text = try!(json::encode(&state).map_err(|err| format!("couldn't encode transfer: {}", err)));
+
+        let path     = try!(self.get_state_path());
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = try!(File::create(&tmp_path).map_err(|err| format!("couldn't create transfer file: {}", err)));
+        try!(file.write_all(text.as_bytes()).map_err(|err| format!("couldn't write transfer file: {}", err)));
+        try!(file.sync_all().map_err(|err| format!("couldn't sync transfer file: {}", err)));
+        try!(fs::rename(&tmp_path, &path).map_err(|err| format!("couldn't rename transfer file: {}", err)));
+
+        // the journal entries are now part of the metadata file
+        let journal = try!(self.get_journal_path());
+        match fs::remove_file(&journal) {
+            Err(ref err) if journal.exists() => Err(format!("couldn't remove journal: {}", err)),
+            _                                => Ok(())
+        }
+    }
+
+    // Append a received chunk to the journal rather than rewriting all of the
+    // metadata, which is only saved at each checkpoint.
+    fn record_chunk(&self, index: u64) -> Result<(), String> {
+        if self.transferred_chunks.len() % CHECKPOINT_CHUNKS == 0 {
+            return self.save()
+        }
+        let mut file = try!(OpenOptions::new().create(true).append(true).open(try!(self.get_journal_path()))
+                            .map_err(|err| format!("couldn't open journal: {}", err)));
+        file.write_all(format!("{}\n", index).as_bytes()).map_err(|err| format!("couldn't write journal: {}", err))
+    }
+
+    /// Check that a chunk index is within the expected range and has not
+    /// already been received. Chunk indices start at 1.
+    pub fn check_index(&self, index: u64) -> Result<(), String> {
+        if index < 1 || index > self.chunkscount {
+            Err(format!("chunk index {} out of range 1-{}", index, self.chunkscount))
+        } else if self.transferred_chunks.binary_search(&index).is_ok() {
+            Err(format!("chunk {} already received", index))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Return the ranges of chunk indices that are yet to be received.
+    pub fn missing_chunks(&self) -> Vec<ChunkRange> {
+        let mut missing = Vec::new();
+        let mut next    = 1;
+        for &index in &self.transferred_chunks {
+            if index > next {
+                missing.push(ChunkRange { start: next, end: index - 1 });
+            }
+            next = index + 1;
         }
+        if next <= self.chunkscount {
+            missing.push(ChunkRange { start: next, end: self.chunkscount });
+        }
+        missing
     }
 
     /// Write the received chunk to disk and store metadata inside `Transfer`.
@@ -97,19 +310,33 @@ impl Transfer {
         self.transferred_chunks.push(index);
         self.transferred_chunks.sort();
         self.transferred_chunks.dedup();
-        Ok(())
+        self.record_chunk(index)
     }
 
     /// Assemble all received chunks into a complete package.
     pub fn assemble_package(&self) -> Result<PathBuf, String> {
         debug!("finalizing package {}", self.update_id);
-        try!(self.assemble_chunks());
-        self.verify()
+        let missing = self.missing_chunks();
+        if !missing.is_empty() {
+            let ranges = missing.iter()
+                .map(|range| if range.start == range.end {
+                    format!("{}", range.start)
+                } else {
+                    format!("{}-{}", range.start, range.end)
+                })
+                .collect::<Vec<_>>();
+            return Err(format!("update_id {} is missing chunks: {}", self.update_id, ranges.join(", ")));
+        }
+        let digest = try!(self.assemble_chunks());
+        self.verify(&digest)
             .and_then(|_| self.get_package_path())
             .map_err(|err| format!("couldn't assemble_package for update_id {}: {}", self.update_id, err))
     }
 
-    fn assemble_chunks(&self) -> Result<(), String> {
+    /// Concatenate the chunks into the package file, returning the hex digest
+    /// of the package as it is written.
+    fn assemble_chunks(&self) -> Result<String, String> {
+        let (mut hasher, _) = try!(checksum_hasher(&self.checksum));
         let pkg_path = try!(self.get_package_path());
         debug!("saving update_id {} to {}", self.update_id, pkg_path.display());
         let mut file = try!(File::create(pkg_path).map_err(|err| format!("couldn't open package file: {}", err)));
@@ -121,37 +348,36 @@ impl Transfer {
             let entry = try!(entry.map_err(|err| format!("bad entry: {}", err)));
             let name  = try!(entry.file_name().into_string().map_err(|err| format!("bad entry name: {:?}", err)));
             let index = try!(u64::from_str(&name).map_err(|err| format!("couldn't parse chunk index: {}", err)));
+            if index < 1 || index > self.chunkscount {
+                return Err(format!("unexpected chunk index {} for update_id {}", index, self.update_id));
+            }
             indices.push(index);
         }
         indices.sort();
 
         for index in indices {
-            try!(self.append_chunk(&mut file, chunk_dir.clone(), index));
+            try!(self.append_chunk(&mut file, &mut *hasher, chunk_dir.clone(), index));
         }
-        Ok(debug!("assembled chunks for update_id {}", self.update_id))
+        debug!("assembled chunks for update_id {}", self.update_id);
+        Ok(hasher.result_str())
     }
 
-    fn append_chunk(&self, file: &mut File, mut chunk_dir: PathBuf, index: u64) -> Result<(), String> {
+    fn append_chunk(&self, file: &mut File, hasher: &mut Digest, mut chunk_dir: PathBuf, index: u64) -> Result<(), String> {
         chunk_dir.push(&index.to_string());
         let mut chunk = try!(File::open(chunk_dir).map_err(|err| format!("couldn't open chunk: {}", err)));
         let mut buf   = Vec::new();
         try!(chunk.read_to_end(&mut buf).map_err(|err| format!("couldn't read file {}: {}", index, err)));
-        try!(file.write(&buf).map_err(|err| format!("couldn't write chunk {}: {}", index, err)));
+        try!(file.write_all(&buf).map_err(|err| format!("couldn't write chunk {}: {}", index, err)));
+        hasher.input(&buf);
         Ok(trace!("wrote chunk {} for update_id {}", index, self.update_id))
     }
 
-    fn verify(&self) -> Result<(), String> {
-        let path     = try!(self.get_package_path());
-        let mut file = try!(File::open(path).map_err(|err| format!("couldn't open package path: {}", err)));
-        let mut data = Vec::new();
-        try!(file.read_to_end(&mut data).map_err(|err| format!("couldn't read file: {}", err)));
-
-        let mut hash = Sha1::new();
-        hash.input(&data);
-        if hash.result_str() == self.checksum {
+    fn verify(&self, digest: &str) -> Result<(), String> {
+        let (_, expected) = try!(checksum_hasher(&self.checksum));
+        if digest == expected {
             Ok(())
         } else {
-            Err(format!("update_id {} checksum failed: expected {}, got {}", self.update_id, self.checksum, hash.result_str()))
+            Err(format!("update_id {} checksum failed: expected {}, got {}", self.update_id, expected, digest))
         }
     }
 
@@ -164,6 +390,19 @@ impl Transfer {
            .map_err(|err| format!("couldn't create chunk dir: {}", err))
     }
 
+    fn get_state_path(&self) -> Result<PathBuf, String> {
+        let mut path = PathBuf::from(&self.storage_dir);
+        path.push("downloads");
+        try!(fs::create_dir_all(&path)
+                .map_err(|err| format!("couldn't create downloads dir {:?}: {}", path, err)));
+        path.push(format!("{}.json", self.update_id));
+        Ok(path)
+    }
+
+    fn get_journal_path(&self) -> Result<PathBuf, String> {
+        self.get_state_path().map(|path| path.with_extension("chunks"))
+    }
+
     fn get_package_path(&self) -> Result<PathBuf, String> {
         let mut path = PathBuf::from(&self.storage_dir);
         path.push("packages");
@@ -187,6 +426,10 @@ impl Drop for Transfer {
                     Ok(fs::remove_dir(dir).map_err(|err| error!("couldn't remove dir: {}", err)))
                 })
         });
+        let _ = self.get_state_path().map(|path| {
+            fs::remove_file(path).map_err(|err| error!("couldn't remove transfer file: {}", err))
+        });
+        let _ = self.get_journal_path().map(|path| fs::remove_file(path));
     }
 }
 
@@ -197,12 +440,14 @@ mod test {
     use rand::Rng;
     use rustc_serialize::base64;
     use rustc_serialize::base64::ToBase64;
+    use std::mem;
     use std::path::PathBuf;
     use std::fs::File;
     use std::io::prelude::*;
     use time;
 
     use super::*;
+    use datatype::ChunkRange;
     use package_manager::TestDir;
 
 
@@ -211,9 +456,11 @@ mod test {
             Transfer {
                 update_id:           rand::thread_rng().gen_ascii_chars().take(10).collect::<String>(),
                 checksum:            "".to_string(),
+                chunkscount:         20,
                 transferred_chunks:  Vec::new(),
                 storage_dir:         test_dir.0.clone(),
-                last_chunk_received: time::get_time().sec
+                last_chunk_received: time::get_time().sec,
+                backend:             None,
             }
         }
 
@@ -248,14 +495,27 @@ mod test {
     fn test_checksum() {
         let test_dir     = TestDir::new("sota-test-transfers");
         let mut transfer = Transfer::new_test(&test_dir);
-        transfer.assert_chunk_written(&test_dir, 0, "test\n".to_string().as_bytes());
-        transfer.assemble_chunks().expect("couldn't assemble chunks");
+        transfer.chunkscount = 1;
+        transfer.assert_chunk_written(&test_dir, 1, "test\n".to_string().as_bytes());
 
         transfer.checksum = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83".to_string();
-        assert!(transfer.verify().is_ok());
+        let digest = transfer.assemble_chunks().expect("couldn't assemble chunks");
+        assert!(transfer.verify(&digest).is_ok());
+
+        transfer.checksum = "sha256:f2ca1bb6c7e907d06dafe4687e579fce76b37e4e93b7605022da52e6ccc26fd2".to_string();
+        let digest = transfer.assemble_chunks().expect("couldn't assemble chunks");
+        assert!(transfer.verify(&digest).is_ok());
+
+        transfer.checksum = "sha512:0e3e75234abc68f4378a86b3f4b32a198ba301845b0cd6e50106e874345700cc\
+                             6663a86c1ea125dc5e92be17c98f9a0f85ca9d5f595db2012f7cc3571945c123".to_string();
+        let digest = transfer.assemble_chunks().expect("couldn't assemble chunks");
+        assert!(transfer.verify(&digest).is_ok());
 
         transfer.checksum = "invalid".to_string();
-        assert!(!transfer.verify().is_ok());
+        assert!(!transfer.verify(&digest).is_ok());
+
+        transfer.checksum = "md5:d8e8fca2dc0f896fd7cb4cb0031ba249".to_string();
+        assert!(transfer.assemble_chunks().is_err());
     }
 
     #[test]
@@ -263,7 +523,7 @@ mod test {
         let test_dir     = TestDir::new("sota-test-transfers");
         let mut transfer = Transfer::new_test(&test_dir);
         let mut assembly = String::new();
-        for index in 1..20 {
+        for index in 1..21 {
             let data = rand::thread_rng().gen_ascii_chars().take(index).collect::<String>();
             assembly.push_str(&data);
             transfer.assert_chunk_written(&test_dir, index as u64, data.as_bytes());
@@ -275,4 +535,96 @@ mod test {
         let _       = File::open(PathBuf::from(path)).unwrap().read_to_end(&mut buf).unwrap();
         assert_eq!(assembly.into_bytes(), buf);
     }
+
+    #[test]
+    fn test_resume_transfer() {
+        let test_dir      = TestDir::new("sota-test-transfers");
+        let mut transfers = Transfers::new(test_dir.0.clone());
+        transfers.push("1".to_string(), "abc".to_string(), 3, None);
+        {
+            let transfer = transfers.get_mut("1".to_string()).unwrap();
+            transfer.assert_chunk_written(&test_dir, 1, b"one");
+            transfer.assert_chunk_written(&test_dir, 3, b"three");
+            let journal = transfer.get_journal_path().unwrap();
+            assert!(journal.exists());
+            transfer.save().unwrap();
+            assert!(!journal.exists());
+            transfer.assert_chunk_written(&test_dir, 2, b"two");
+            assert!(journal.exists());
+        }
+        mem::forget(transfers); // simulate a client restart
+
+        let transfers = Transfers::load(test_dir.0.clone());
+        let transfer  = transfers.get("1".to_string()).expect("transfer wasn't reloaded");
+        assert_eq!(transfer.checksum, "abc".to_string());
+        assert_eq!(transfer.chunkscount, 3);
+        assert_eq!(transfer.transferred_chunks, vec![1, 2, 3]);
+        assert_eq!(transfers.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_push_resumes_transfer() {
+        let test_dir      = TestDir::new("sota-test-transfers");
+        let mut transfers = Transfers::new(test_dir.0.clone());
+        transfers.push("1".to_string(), "abc".to_string(), 3, None);
+        transfers.get_mut("1".to_string()).unwrap().assert_chunk_written(&test_dir, 2, b"two");
+
+        transfers.push("1".to_string(), "abc".to_string(), 3, None);
+        let transfer = transfers.get("1".to_string()).unwrap();
+        assert_eq!(transfer.transferred_chunks, vec![2]);
+        assert_eq!(transfer.missing_chunks(), vec![ChunkRange { start: 1, end: 1 }, ChunkRange { start: 3, end: 3 }]);
+
+        transfers.push("1".to_string(), "def".to_string(), 3, None);
+        let transfer = transfers.get("1".to_string()).unwrap();
+        assert!(transfer.transferred_chunks.is_empty());
+        assert!(transfer.get_state_path().unwrap().exists());
+
+        transfers.push("2".to_string(), "abc".to_string(), 0, None);
+        assert!(transfers.get("2".to_string()).unwrap().missing_chunks().is_empty());
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let test_dir     = TestDir::new("sota-test-transfers");
+        let mut transfer = Transfer::new_test(&test_dir);
+        transfer.chunkscount = 10;
+        assert_eq!(transfer.missing_chunks(), vec![ChunkRange { start: 1, end: 10 }]);
+
+        for index in &[1, 2, 5, 9] {
+            transfer.assert_chunk_written(&test_dir, *index, b"data");
+        }
+        assert_eq!(transfer.missing_chunks(), vec![
+            ChunkRange { start: 3, end: 4 },
+            ChunkRange { start: 6, end: 8 },
+            ChunkRange { start: 10, end: 10 },
+        ]);
+        let err = transfer.assemble_package().unwrap_err();
+        assert!(err.contains("missing chunks: 3-4, 6-8, 10"));
+    }
+
+    #[test]
+    fn test_check_index() {
+        let test_dir     = TestDir::new("sota-test-transfers");
+        let mut transfer = Transfer::new_test(&test_dir);
+        transfer.chunkscount = 2;
+        assert!(transfer.check_index(0).is_err());
+        assert!(transfer.check_index(3).is_err());
+        assert!(transfer.check_index(1).is_ok());
+        transfer.assert_chunk_written(&test_dir, 1, b"data");
+        assert!(transfer.check_index(1).is_err());
+    }
+
+    #[test]
+    fn test_prune() {
+        let test_dir      = TestDir::new("sota-test-transfers");
+        let mut transfers = Transfers::new(test_dir.0.clone());
+        transfers.push("1".to_string(), "".to_string(), 1, None);
+        transfers.push("2".to_string(), "".to_string(), 1, None);
+        transfers.get_mut("1".to_string()).unwrap().last_chunk_received = 0;
+
+        let now = time::get_time().sec;
+        assert_eq!(transfers.prune(now, 60), vec!["1".to_string()]);
+        assert!(transfers.get("1".to_string()).is_none());
+        assert!(transfers.get("2".to_string()).is_some());
+    }
 }
@@ -1,13 +1,23 @@
+use chan;
+use chan::Receiver;
 use rustc_serialize::json;
 use std::{fs, io};
 use std::fs::File;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 use datatype::{Config, DownloadComplete, Error, Package,
                UpdateReport, UpdateRequest, UpdateRequestId, Url};
+use health;
 use http::{Client, Response};
 
 
+/// How often to check whether a download is still receiving data.
+const PROGRESS_CHECK_SEC: u64 = 5;
+
+
 /// Encapsulate the client configuration and HTTP client used for
 /// software-over-the-air updates.
 pub struct Sota<'c, 'h> {
@@ -52,9 +62,13 @@ impl<'c, 'h> Sota<'c, 'h> {
 
     /// Download a specific update from the Core server.
     pub fn download_update(&mut self, id: UpdateRequestId) -> Result<DownloadComplete, Error> {
-        let resp_rx = self.client.get(self.endpoint(&format!("/updates/{}/download", id)), None);
-        let resp    = try!(resp_rx.recv().ok_or(Error::Client("couldn't download update".to_string())));
-        let data    = match resp {
+        let dir       = &self.config.device.packages_dir;
+        let available = try!(self.config.device.storage_limits().available(dir));
+        let received  = Arc::new(AtomicUsize::new(0));
+        let url       = self.endpoint(&format!("/updates/{}/download", id));
+        let resp_rx   = self.client.download(url, available, received.clone());
+        let resp      = try!(wait_for_download(resp_rx, &received).ok_or(Error::Client("couldn't download update".to_string())));
+        let data      = match resp {
             Response::Success(data) => data,
             Response::Failed(data)  => return Err(Error::from(data)),
             Response::Error(err)    => return Err(err)
@@ -123,13 +137,37 @@ impl<'c, 'h> Sota<'c, 'h> {
 }
 
 
+/// Wait for the response to a download, marking the current thread as still
+/// making progress each time more of the body has been received.
+fn wait_for_download(resp_rx: Receiver<Response>, received: &AtomicUsize) -> Option<Response> {
+    let tick     = chan::tick(Duration::from_secs(PROGRESS_CHECK_SEC));
+    let mut last = 0;
+    loop {
+        chan_select! {
+            resp_rx.recv() -> resp => return resp,
+            tick.recv() => {
+                let bytes = received.load(Ordering::SeqCst);
+                if bytes > last {
+                    trace!("{} bytes of the download received", bytes);
+                    last = bytes;
+                    health::progress();
+                }
+            },
+        }
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use rustc_serialize::json;
+    use std::fs::File;
+    use std::io::Write;
 
     use super::*;
-    use datatype::{Config, Package, UpdateRequest, UpdateRequestStatus};
-    use http::TestClient;
+    use datatype::{Config, Error, Method, Package, UpdateRequest, UpdateRequestStatus};
+    use http::{TestClient, TestReply};
+    use package_manager::TestDir;
 
 
     #[test]
@@ -155,4 +193,55 @@ mod tests {
         let ids: Vec<String> = updates.iter().map(|p| p.requestId.clone()).collect();
         assert_eq!(ids, vec!["someid".to_string()])
     }
+
+    #[test]
+    fn test_error_mapping() {
+        let mut config     = Config::default();
+        config.device.uuid = "mydevice".to_string();
+        let client = TestClient::default();
+        client.script(Method::Get, "/updates", TestReply::Status(500, "oops".to_string()));
+        client.script(Method::Get, "/updates", TestReply::Status(401, "".to_string()));
+        client.script(Method::Get, "/updates", TestReply::Error("refused".to_string()));
+        let mut sota = Sota::new(&config, &client);
+
+        match sota.get_update_requests() {
+            Err(Error::Http(data)) => assert_eq!(data.body, b"oops".to_vec()),
+            other                  => panic!("expected Error::Http, got {:?}", other)
+        }
+        match sota.get_update_requests() {
+            Err(Error::HttpAuth(_)) => (),
+            other                   => panic!("expected Error::HttpAuth, got {:?}", other)
+        }
+        match sota.get_update_requests() {
+            Err(Error::Client(err)) => assert_eq!(err, "refused"),
+            other                   => panic!("expected Error::Client, got {:?}", other)
+        }
+
+        let requests = client.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, Method::Get);
+        assert_eq!(requests[0].url.path(), "/api/v1/mydevice/mydevice/updates");
+    }
+
+    #[test]
+    fn test_download_disk_quota() {
+        let dir    = TestDir::new("sota-test-download");
+        let client = TestClient::default();
+        client.script(Method::Get, "/download", TestReply::Status(200, "0123456789".to_string()));
+        client.script(Method::Get, "/download", TestReply::Status(200, "01234".to_string()));
+        let mut config = Config::default();
+        config.device.packages_dir  = dir.0.clone();
+        config.device.disk_quota_mb = Some(1);
+        // leave 5 bytes of the quota free
+        File::create(format!("{}/other", dir.0)).unwrap().write_all(&vec![0; 1024 * 1024 - 5]).unwrap();
+        let mut sota = Sota::new(&config, &client);
+
+        match sota.download_update("1".to_string()) {
+            Err(Error::DiskFull(_)) => (),
+            other                   => panic!("expected Error::DiskFull, got {:?}", other)
+        }
+        let dl = sota.download_update("2".to_string()).expect("couldn't download update");
+        assert_eq!(dl.update_image, format!("{}/2", dir.0));
+        assert_eq!(client.requests()[0].max_body, Some(5));
+    }
 }
@@ -0,0 +1,219 @@
+#[macro_use] extern crate chan;
+extern crate sota;
+
+use chan::{Receiver, Sender, WaitGroup};
+use std::thread;
+use std::time::Duration;
+
+use sota::audit::AuditLog;
+use sota::datatype::{AuditConfig, AuthConfig, Command, Config, Event, Package, UpdateRequest,
+                     UpdateRequestStatus, UpdateResultCode};
+use sota::gateway::Interpret;
+use sota::health::Heartbeat;
+use sota::http::{AuthClient, MockCore};
+use sota::interpreter::{CommandInterpreter, EventInterpreter, GlobalInterpreter, Interpreter};
+use sota::logging::Correlations;
+use sota::metrics::Metrics;
+use sota::package_manager::{PackageManager, TestDir};
+
+
+const DEVICE: &'static str = "mock-device";
+
+
+/// The audit log of all commands received by the client.
+fn audit_log(dir: &TestDir) -> AuditLog {
+    AuditLog::new(&AuditConfig {
+        enabled:     true,
+        path:        format!("{}/audit.log", dir.0),
+        max_size_kb: 1024,
+        max_files:   1,
+    })
+}
+
+
+/// Run the command, event and global interpreters against the mock server,
+/// returning a sender for commands and a receiver of all broadcast events.
+fn start_client(core: &MockCore, dir: &TestDir) -> (Sender<Command>, Receiver<Event>) {
+    let mut config = Config::default();
+    config.auth = Some(AuthConfig {
+        server:           core.url(),
+        client_id:        "client-id".to_string(),
+        client_secret:    "client-secret".to_string(),
+        credentials_file: format!("{}/credentials.toml", dir.0),
+    });
+    config.core.server            = core.url();
+    config.device.uuid            = DEVICE.to_string();
+    config.device.packages_dir    = dir.0.clone();
+    config.device.package_manager = PackageManager::new_tpm(true);
+    config.device.system_info     = None;
+
+    let (ctx, crx)         = chan::async::<Command>();
+    let (itx, irx)         = chan::async::<Interpret>();
+    let (etx, erx)         = chan::async::<Event>();
+    let (ei_tx, ei_rx)     = chan::async::<Event>();
+    let (test_tx, test_rx) = chan::async::<Event>();
+
+    let correlations = Correlations::default();
+    let event_mgr    = config.device.package_manager.clone();
+    let event_cor    = correlations.clone();
+    let cmd_cor      = correlations.clone();
+    let ei_ctx       = ctx.clone();
+    let audit        = audit_log(dir);
+    thread::spawn(move || {
+        EventInterpreter {
+            pacman:       event_mgr,
+            sysinfo:      None,
+            metrics:      Metrics::default(),
+            running:      None,
+            correlations: event_cor,
+        }.run(ei_rx, ei_ctx, WaitGroup::new(), Heartbeat::default())
+    });
+    thread::spawn(move || {
+        CommandInterpreter { correlations: cmd_cor }.run(crx, itx, WaitGroup::new(), Heartbeat::default())
+    });
+    thread::spawn(move || {
+        GlobalInterpreter {
+            config:       config,
+            token:        None,
+            http_client:  Box::new(AuthClient::default()),
+            rvi:          None,
+            metrics:      Metrics::default(),
+            audit:        Some(audit),
+            reloader:     None,
+            correlations: correlations,
+        }.run(irx, etx, WaitGroup::new(), Heartbeat::default())
+    });
+    thread::spawn(move || {
+        for event in erx {
+            ei_tx.send(event.clone());
+            test_tx.send(event);
+        }
+    });
+
+    (ctx, test_rx)
+}
+
+/// Wait for the next event matching the predicate, skipping any others.
+fn expect_event<F: Fn(&Event) -> bool>(events: &Receiver<Event>, matches: F) -> Event {
+    let deadline = chan::after(Duration::from_secs(10));
+    loop {
+        chan_select! {
+            deadline.recv() => panic!("timed out waiting for event"),
+            events.recv() -> event => {
+                let event = event.expect("events channel closed");
+                if matches(&event) {
+                    return event
+                }
+            },
+        }
+    }
+}
+
+fn update(id: &str) -> UpdateRequest {
+    UpdateRequest {
+        requestId:  id.to_string(),
+        status:     UpdateRequestStatus::Pending,
+        packageId:  Package { name: "pkg".to_string(), version: "1.0".to_string() },
+        installPos: 0,
+        createdAt:  "2016-01-01".to_string(),
+    }
+}
+
+
+#[test]
+fn core_auth_poll_download_install_report() {
+    let dir  = TestDir::new("sota-test-core");
+    let core = MockCore::start(DEVICE);
+    core.add_update(update("update-1"), b"package data".to_vec());
+    let (ctx, events) = start_client(&core, &dir);
+
+    ctx.send(Command::Authenticate(None));
+    expect_event(&events, |event| *event == Event::Authenticated);
+    ctx.send(Command::GetUpdateRequests);
+    expect_event(&events, |event| *event == Event::UpdatesReceived(vec![update("update-1")]));
+    expect_event(&events, |event| event.name() == "DownloadComplete");
+    match expect_event(&events, |event| event.name() == "InstallComplete") {
+        Event::InstallComplete(report) => {
+            assert_eq!(report.update_id, "update-1");
+            assert_eq!(report.operation_results[0].result_code, UpdateResultCode::OK);
+        }
+        _ => unreachable!()
+    }
+    expect_event(&events, |event| *event == Event::UpdateReportSent);
+
+    let token = core.requests_to("POST", "/token");
+    assert_eq!(token.len(), 1);
+    assert!(token[0].auth.as_ref().map_or(false, |auth| auth.starts_with("Basic ")));
+
+    let base = format!("/api/v1/mydevice/{}", DEVICE);
+    let poll = core.requests_to("GET", &format!("{}/updates", base));
+    assert_eq!(poll.len(), 1);
+    assert_eq!(poll[0].auth, Some("Bearer mock-token".to_string()));
+    assert_eq!(core.requests_to("GET", &format!("{}/updates/update-1/download", base)).len(), 1);
+
+    let reports = core.requests_to("POST", &format!("{}/updates/update-1", base));
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].text().contains("update-1"));
+
+    ctx.send(Command::GetUpdateRequests);
+    expect_event(&events, |event| *event == Event::NoUpdateRequests);
+}
+
+#[test]
+fn core_failed_download_is_reported() {
+    let dir  = TestDir::new("sota-test-core");
+    let core = MockCore::start(DEVICE);
+    core.add_update(update("update-2"), b"package data".to_vec());
+    core.fail("GET", "/updates/update-2/download", 500, 1);
+    let (ctx, events) = start_client(&core, &dir);
+
+    ctx.send(Command::Authenticate(None));
+    expect_event(&events, |event| *event == Event::Authenticated);
+    ctx.send(Command::GetUpdateRequests);
+    expect_event(&events, |event| event.name() == "DownloadFailed");
+    expect_event(&events, |event| *event == Event::UpdateReportSent);
+
+    let base    = format!("/api/v1/mydevice/{}", DEVICE);
+    let reports = core.requests_to("POST", &format!("{}/updates/update-2", base));
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].text().contains(r#""result_code":19"#));
+}
+
+#[test]
+fn core_unauthorized_poll_triggers_reauthentication() {
+    let dir  = TestDir::new("sota-test-core");
+    let core = MockCore::start(DEVICE);
+    core.fail("GET", "/updates", 401, 1);
+    let (ctx, events) = start_client(&core, &dir);
+
+    ctx.send(Command::Authenticate(None));
+    expect_event(&events, |event| *event == Event::Authenticated);
+    ctx.send(Command::GetUpdateRequests);
+    expect_event(&events, |event| *event == Event::NotAuthenticated);
+    expect_event(&events, |event| *event == Event::Authenticated);
+    assert_eq!(core.requests_to("POST", "/token").len(), 2);
+}
+
+#[test]
+fn core_follow_up_commands_keep_the_correlation_id() {
+    let dir  = TestDir::new("sota-test-core");
+    let core = MockCore::start(DEVICE);
+    core.add_update(update("update-3"), b"package data".to_vec());
+    let (ctx, events) = start_client(&core, &dir);
+
+    ctx.send(Command::Authenticate(None));
+    expect_event(&events, |event| *event == Event::Authenticated);
+    ctx.send(Command::GetUpdateRequests);
+    expect_event(&events, |event| *event == Event::UpdateReportSent);
+
+    let commands = audit_log(&dir).query(None, None, None).expect("couldn't query audit log")
+        .into_iter().filter(|entry| entry.kind == "command").collect::<Vec<_>>();
+    let id_of    = |name: &str| {
+        commands.iter().find(|entry| entry.name == name).and_then(|entry| entry.correlation_id.clone())
+    };
+    let poll_id  = id_of("GetUpdateRequests").expect("no GetUpdateRequests command");
+    assert_eq!(id_of("StartDownload"), Some(poll_id.clone()));
+    assert_eq!(id_of("StartInstall"), Some(poll_id.clone()));
+    assert_eq!(id_of("SendUpdateReport"), Some(poll_id.clone()));
+    assert!(id_of("Authenticate") != Some(poll_id));
+}
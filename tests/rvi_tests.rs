@@ -0,0 +1,168 @@
+#[macro_use] extern crate chan;
+extern crate rustc_serialize;
+extern crate sota;
+
+use chan::Receiver;
+use rustc_serialize::json::Json;
+use std::fs::File;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+use sota::datatype::{Event, RviConfig, StorageLimits, UpdateReport, UpdateResultCode};
+use sota::package_manager::TestDir;
+use sota::rvi::{Edge, Faults, RviSimulator, Services};
+
+
+struct RviClient {
+    services: Services,
+    events:   Receiver<Event>,
+    _dir:     TestDir,
+}
+
+/// Start the RVI services of the client and connect them to the simulator.
+fn start_client(sim: &RviSimulator) -> RviClient {
+    start_limited_client(sim, StorageLimits::default())
+}
+
+/// Start the RVI services of the client with limits on its disk usage.
+fn start_limited_client(sim: &RviSimulator, limits: StorageLimits) -> RviClient {
+    let dir     = TestDir::new("sota-test-rvi");
+    let rvi_cfg = RviConfig { client: sim.url(), storage_dir: dir.0.clone(), timeout: None };
+    let (etx, erx) = chan::async::<Event>();
+    let services   = Services::new(rvi_cfg, "device".to_string(), limits, etx);
+
+    let edge_addr = "127.0.0.1:0".parse().unwrap();
+    let mut edge  = Edge::new(services.clone(), edge_addr, sim.url());
+    thread::spawn(move || edge.start());
+
+    assert!(sim.wait_registered(Duration::from_secs(10)), "services not registered");
+    expect_event(&erx, |event| *event == Event::RviConnected);
+    RviClient { services: services, events: erx, _dir: dir }
+}
+
+/// Wait for the next event matching the predicate, skipping any others.
+fn expect_event<F: Fn(&Event) -> bool>(events: &Receiver<Event>, matches: F) -> Event {
+    let deadline = chan::after(Duration::from_secs(10));
+    loop {
+        chan_select! {
+            deadline.recv() => panic!("timed out waiting for event"),
+            events.recv() -> event => {
+                let event = event.expect("events channel closed");
+                if matches(&event) {
+                    return event
+                }
+            },
+        }
+    }
+}
+
+fn package() -> Vec<u8> {
+    (0..1000).map(|n| (n % 251) as u8).collect()
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    File::open(path).expect("couldn't open package").read_to_end(&mut buf).expect("couldn't read package");
+    buf
+}
+
+
+#[test]
+fn rvi_full_transfer() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let client = start_client(&sim);
+
+    sim.transfer("update-1", &package(), 64, &Faults::default()).expect("transfer failed");
+    match expect_event(&client.events, |event| event.name() == "DownloadComplete") {
+        Event::DownloadComplete(dl) => {
+            assert_eq!(dl.update_id, "update-1");
+            assert_eq!(read_file(&dl.update_image), package());
+        }
+        _ => unreachable!()
+    }
+
+    let report = UpdateReport::single("update-1".to_string(), UpdateResultCode::OK, "".to_string());
+    client.services.remote.lock().unwrap().send_update_report(report).expect("couldn't send report");
+    let msg = sim.expect_message("backend/report", Duration::from_secs(10)).expect("no report received");
+    let id  = msg.parameters[0].find_path(&["update_report", "update_id"]).and_then(Json::as_string);
+    assert_eq!(id, Some("update-1"));
+}
+
+#[test]
+fn rvi_lost_chunks_are_resent() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let client = start_client(&sim);
+    let faults = Faults { lost_chunks: vec![3, 4], ..Faults::default() };
+
+    assert!(sim.transfer("update-2", &package(), 64, &faults).is_err());
+    let missing = Json::from_str(r#"[{ "start": 3, "end": 4 }]"#).unwrap();
+    loop {
+        let msg = sim.expect_message("backend/ack", Duration::from_secs(10)).expect("no ack received");
+        if msg.parameters[0].find("missing") == Some(&missing) {
+            break
+        }
+    }
+
+    let package = package();
+    sim.send_chunk("update-2", 3, &package[128..192]).expect("couldn't resend chunk 3");
+    sim.send_chunk("update-2", 4, &package[192..256]).expect("couldn't resend chunk 4");
+    sim.finish("update-2").expect("couldn't finish transfer");
+    expect_event(&client.events, |event| event.name() == "DownloadComplete");
+}
+
+#[test]
+fn rvi_bad_checksum_is_rejected() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let _      = start_client(&sim);
+    let faults = Faults { bad_checksum: true, ..Faults::default() };
+    assert!(sim.transfer("update-3", &package(), 64, &faults).is_err());
+}
+
+#[test]
+fn rvi_abort_fails_download() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let client = start_client(&sim);
+    let faults = Faults { abort_after: Some(2), ..Faults::default() };
+
+    sim.transfer("update-4", &package(), 64, &faults).expect("abort failed");
+    let event = expect_event(&client.events, |event| event.name() == "DownloadFailed");
+    assert_eq!(event, Event::DownloadFailed("update-4".to_string(), "transfer aborted".to_string()));
+    assert!(sim.send_chunk("update-4", 3, b"data").is_err());
+}
+
+#[test]
+fn rvi_duplicate_chunk_is_rejected() {
+    let sim = RviSimulator::start("127.0.0.1:0");
+    let _   = start_client(&sim);
+
+    sim.notify("update-5", 8).expect("couldn't notify");
+    sim.start_transfer("update-5", 2, "sha256:00").expect("couldn't start transfer");
+    assert!(sim.send_chunk("update-5", 1, b"data").is_ok());
+    assert!(sim.send_chunk("update-5", 1, b"data").is_err());
+    assert!(sim.send_chunk("update-5", 3, b"data").is_err());
+}
+
+#[test]
+fn rvi_disk_full_start_fails() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let limits = StorageLimits { reserve: u64::max_value() / 2, quota: None };
+    let client = start_limited_client(&sim, limits);
+
+    assert!(sim.start_transfer("update-6", 2, "sha256:00").is_err());
+    expect_event(&client.events, |event| event.name() == "DiskFull");
+    assert!(sim.send_chunk("update-6", 1, b"data").is_err());
+}
+
+#[test]
+fn rvi_empty_parameters_are_rejected() {
+    let sim    = RviSimulator::start("127.0.0.1:0");
+    let client = start_client(&sim);
+    let msg    = r#"{ "jsonrpc": "2.0", "id": 7, "method": "message",
+                      "params": { "service_name": "/sota/finish", "parameters": [], "timeout": null } }"#;
+
+    match client.services.handle_service("/sota/finish", 7, msg) {
+        Err(err) => assert_eq!(err.error.data, "expected one parameter"),
+        Ok(_)    => panic!("expected an rpc error")
+    }
+}